@@ -65,7 +65,12 @@ fn write_repr_enum<'a, T: std::io::Write, I, P>(
     writeln!(writer, "}}").unwrap();
 }
 
-fn write_logictypes() {
+type LogicTypes = (
+    Vec<(String, EnumVariant<u16>)>,
+    Vec<(String, EnumVariant<u8>)>,
+);
+
+fn write_logictypes() -> LogicTypes {
     let out_dir = env::var_os("OUT_DIR").unwrap();
 
     let dest_path = Path::new(&out_dir).join("logictypes.rs");
@@ -165,6 +170,8 @@ fn write_logictypes() {
     write_repr_enum(&mut writer, "SlotLogicType", &slotlogictypes, true);
 
     println!("cargo:rerun-if-changed=data/slotlogictypes.txt");
+
+    (logictypes, slotlogictypes)
 }
 
 fn write_enums() {
@@ -331,6 +338,7 @@ fn write_instructions_enum() {
     let mut writer = BufWriter::new(&output_file);
 
     let mut instructions = BTreeSet::new();
+    let mut register_writers = BTreeSet::new();
     let infile = Path::new("data/instructions.txt");
     let contents = fs::read_to_string(infile).unwrap();
 
@@ -338,11 +346,14 @@ fn write_instructions_enum() {
         let mut it = line.split(' ');
         let instruction = it.next().unwrap();
         instructions.insert(instruction.to_string());
+        if it.next() == Some("REGISTER") {
+            register_writers.insert(instruction.to_string());
+        }
     }
 
     write!(
         &mut writer,
-        "#[derive(Debug, Display, PartialEq, Eq, Clone, Copy, Serialize, Deserialize)]\n\
+        "#[derive(Debug, Display, PartialEq, Eq, Clone, Copy, Serialize, Deserialize, EnumIter)]\n\
          pub enum InstructionOp {{\n\
         "
     )
@@ -378,15 +389,228 @@ fn write_instructions_enum() {
     )
     .unwrap();
 
+    let register_writers = register_writers
+        .iter()
+        .map(|typ| format!("Self::{}", typ.to_case(Case::Pascal)))
+        .collect::<Vec<String>>()
+        .join(" | ");
+    write!(
+        &mut writer,
+        "\nimpl InstructionOp {{\n    \
+            /// whether the first operand is a register the instruction writes its result to\n    \
+            pub fn writes_register(&self) -> bool {{\n        \
+                matches!(self, {register_writers})\n    \
+            }}\n\
+         }}\n"
+    )
+    .unwrap();
+
     println!("cargo:rerun-if-changed=data/instructions.txt");
 }
 
+/// the enum variant a logic type name (or one of its aliases) was generated as
+fn variant_for<P: Display + FromStr>(
+    variants: &[(String, EnumVariant<P>)],
+    name: &str,
+) -> Option<String> {
+    variants
+        .iter()
+        .find(|(variant, info)| variant == name || info.aliases.iter().any(|alias| alias == name))
+        .map(|(variant, _)| variant.replace('.', "").to_case(Case::Pascal))
+}
+
+fn field_type(access: &serde_json::Value) -> &'static str {
+    match access.as_str() {
+        Some("Read") => "FieldType::Read",
+        Some("Write") => "FieldType::Write",
+        _ => "FieldType::ReadWrite",
+    }
+}
+
+fn slot_type(name: &str) -> String {
+    match name {
+        "Motherboard" => "MotherBoard".to_owned(),
+        _ => name.to_owned(),
+    }
+}
+
+fn write_prefabs((logictypes, slotlogictypes): &LogicTypes) {
+    let out_dir = env::var_os("OUT_DIR").unwrap();
+
+    let dest_path = Path::new(&out_dir).join("prefabs.rs");
+    let output_file = File::create(dest_path).unwrap();
+    let mut writer = BufWriter::new(&output_file);
+
+    let infile = Path::new("data/database.json");
+    let contents = fs::read_to_string(infile).unwrap();
+    let database: serde_json::Value = serde_json::from_str(&contents).unwrap();
+
+    let mut prefabs_map_builder = ::phf_codegen::Map::new();
+    let mut names_map_builder = ::phf_codegen::Map::new();
+    for (name, prefab) in database["db"].as_object().unwrap() {
+        let hash = prefab["hash"].as_i64().unwrap() as i32;
+
+        // logic types the emulator doesn't know yet are left out
+        let logic = prefab["logic"]
+            .as_object()
+            .into_iter()
+            .flatten()
+            .filter_map(|(typ, access)| {
+                let variant = variant_for(logictypes, typ)?;
+                Some(format!("(LogicType::{variant}, {})", field_type(access)))
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+        let slot_logic = prefab["slotlogic"]
+            .as_object()
+            .into_iter()
+            .flatten()
+            .filter_map(|(index, fields)| Some((index.parse::<usize>().ok()?, fields)))
+            .flat_map(|(index, fields)| {
+                fields
+                    .as_object()
+                    .into_iter()
+                    .flatten()
+                    .filter_map(move |(typ, access)| {
+                        let variant = variant_for(slotlogictypes, typ)?;
+                        Some(format!(
+                            "({index}, SlotLogicType::{variant}, {})",
+                            field_type(access)
+                        ))
+                    })
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+        let slots = prefab["slots"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .map(|slot| {
+                format!(
+                    "PrefabSlot {{ name: {:?}, typ: SlotType::{} }}",
+                    slot["name"].as_str().unwrap_or_default(),
+                    slot_type(slot["typ"].as_str().unwrap_or("None"))
+                )
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+        let mut connections = prefab["conn"]
+            .as_object()
+            .into_iter()
+            .flatten()
+            .filter_map(|(index, conn)| Some((index.parse::<usize>().ok()?, conn)))
+            .collect::<Vec<_>>();
+        connections.sort_by_key(|(index, _)| *index);
+        let connections = connections
+            .into_iter()
+            .map(|(_, conn)| {
+                format!(
+                    "PrefabConnection {{ name: {:?}, typ: ConnectionType::{}, role: ConnectionRole::{} }}",
+                    conn["name"].as_str().unwrap_or_default(),
+                    conn["typ"].as_str().unwrap_or("None"),
+                    conn["role"].as_str().unwrap_or("None")
+                )
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+        let mut modes = prefab["modes"]
+            .as_object()
+            .into_iter()
+            .flatten()
+            .filter_map(|(value, mode)| Some((value.parse::<u32>().ok()?, mode.as_str()?)))
+            .collect::<Vec<_>>();
+        modes.sort_by_key(|(value, _)| *value);
+        let modes = modes
+            .into_iter()
+            .map(|(value, mode)| format!("({value}, {mode:?})"))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let pins = match prefab["device"]["pins"].as_u64() {
+            Some(pins) => format!("Some({pins})"),
+            None => "None".to_owned(),
+        };
+        let item = match prefab["item"].as_object() {
+            Some(item) => format!(
+                "Some(PrefabItem {{ slot_class: SlotType::{}, sorting: SortingClass::{}, max_quantity: {} }})",
+                slot_type(item["slotclass"].as_str().unwrap_or("None")),
+                item["sorting"].as_str().unwrap_or("Default"),
+                match item.get("maxquantity").and_then(|max| max.as_f64()) {
+                    Some(max) => format!("Some({})", max as u32),
+                    None => "None".to_owned(),
+                }
+            ),
+            None => "None".to_owned(),
+        };
+
+        prefabs_map_builder.entry(
+            name.clone(),
+            &format!(
+                "PrefabInfo {{ name: {name:?}, hash: {hash}, title: {:?}, logic: &[{logic}], \
+                 slot_logic: &[{slot_logic}], slots: &[{slots}], connections: &[{connections}], \
+                 modes: &[{modes}], pins: {pins}, item: {item}, transmitter: {}, receiver: {} }}",
+                prefab["title"].as_str().unwrap_or_default(),
+                prefab["transmitter"].as_bool().unwrap_or_default(),
+                prefab["receiver"].as_bool().unwrap_or_default(),
+            ),
+        );
+        names_map_builder.entry(hash, &format!("{name:?}"));
+    }
+
+    let mut reagents_map_builder = ::phf_codegen::Map::new();
+    let mut reagent_names_map_builder = ::phf_codegen::Map::new();
+    for (name, reagent) in database["reagents"].as_object().unwrap() {
+        let hash = reagent["Hash"].as_i64().unwrap() as i32;
+        let sources = reagent["Sources"]
+            .as_object()
+            .into_iter()
+            .flatten()
+            .map(|(item, amount)| format!("({item:?}, {:?})", amount.as_f64().unwrap_or_default()))
+            .collect::<Vec<_>>()
+            .join(", ");
+        reagents_map_builder.entry(
+            name.clone(),
+            &format!(
+                "ReagentInfo {{ name: {name:?}, hash: {hash}, unit: {:?}, sources: &[{sources}] }}",
+                reagent["Unit"].as_str().unwrap_or_default(),
+            ),
+        );
+        reagent_names_map_builder.entry(hash, &format!("{name:?}"));
+    }
+
+    writeln!(
+        &mut writer,
+        "pub(crate) static PREFABS: phf::Map<&'static str, PrefabInfo> = {};",
+        prefabs_map_builder.build()
+    )
+    .unwrap();
+    writeln!(
+        &mut writer,
+        "pub(crate) static PREFAB_NAMES: phf::Map<i32, &'static str> = {};",
+        names_map_builder.build()
+    )
+    .unwrap();
+    writeln!(
+        &mut writer,
+        "pub(crate) static REAGENTS: phf::Map<&'static str, ReagentInfo> = {};",
+        reagents_map_builder.build()
+    )
+    .unwrap();
+    writeln!(
+        &mut writer,
+        "pub(crate) static REAGENT_NAMES: phf::Map<i32, &'static str> = {};",
+        reagent_names_map_builder.build()
+    )
+    .unwrap();
+    println!("cargo:rerun-if-changed=data/database.json");
+}
+
 fn main() {
     // write_instructions();
-    write_logictypes();
+    let logictypes = write_logictypes();
     write_modes();
     write_constants();
     write_enums();
 
     write_instructions_enum();
+    write_prefabs(&logictypes);
 }
@@ -0,0 +1,1083 @@
+use std::{
+    cell::RefCell,
+    f64::consts::{PI, TAU},
+    fmt::Debug,
+    rc::Rc,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    device::{Device, FieldType},
+    grammar::{BatchMode, LogicType},
+    vm::VM,
+};
+
+/// simulates what a device does on its own each tick, like a heater warming its room
+pub trait Behavior: Debug {
+    /// the device this behavior drives
+    fn device(&self) -> u32;
+
+    /// move the behavior to a renumbered device
+    fn set_device(&mut self, device: u32);
+
+    /// add the fields the behavior reads and writes to its device
+    fn attach(&mut self, _vm: &VM) {}
+
+    /// run one game tick, before the ics run
+    fn tick(&mut self, vm: &VM);
+
+    /// other devices the behavior reads from or acts on
+    fn links(&self) -> Vec<u32> {
+        Vec::new()
+    }
+
+    fn set_links(&mut self, _links: &[u32]) {}
+
+    /// point links to `old` at `new`, or drop them when `old` is removed
+    fn replace_link(&mut self, old: u32, new: Option<u32>) {
+        let links = self
+            .links()
+            .into_iter()
+            .filter_map(|link| if link == old { new } else { Some(link) })
+            .collect::<Vec<_>>();
+        self.set_links(&links);
+    }
+
+    /// the built-in this behavior can be saved as, if it is one
+    fn freeze(&self) -> Option<BuiltinBehavior> {
+        None
+    }
+}
+
+/// behaviors shipped with the emulator, attached by prefab name when a device is added
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum BuiltinBehavior {
+    DaylightSensor(DaylightSensor),
+    WallHeater(WallHeater),
+    LogicMemory(LogicMemory),
+    LogicMath(LogicMath),
+    LogicCompare(LogicCompare),
+    LogicSelect(LogicSelect),
+    LogicMinMax(LogicMinMax),
+    LogicReader(LogicReader),
+    LogicWriter(LogicWriter),
+    LogicBatchReader(LogicBatchReader),
+    LogicBatchWriter(LogicBatchWriter),
+}
+
+impl BuiltinBehavior {
+    /// the default behavior for a prefab, if there is one
+    pub fn for_prefab(prefab: &str, device: u32) -> Option<Self> {
+        Some(match prefab {
+            "StructureDaylightSensor" => {
+                BuiltinBehavior::DaylightSensor(DaylightSensor::new(device))
+            }
+            "StructureWallHeater" => BuiltinBehavior::WallHeater(WallHeater::new(device)),
+            "StructureLogicMemory" => BuiltinBehavior::LogicMemory(LogicMemory { device }),
+            "StructureLogicMath" => BuiltinBehavior::LogicMath(LogicMath::new(device)),
+            "StructureLogicCompare" => BuiltinBehavior::LogicCompare(LogicCompare::new(device)),
+            "StructureLogicSelect" => BuiltinBehavior::LogicSelect(LogicSelect::new(device)),
+            "StructureLogicMinMax" => BuiltinBehavior::LogicMinMax(LogicMinMax::new(device)),
+            "StructureLogicReader" => BuiltinBehavior::LogicReader(LogicReader::new(device)),
+            "StructureLogicWriter" => BuiltinBehavior::LogicWriter(LogicWriter::new(device)),
+            "StructureLogicBatchReader" => {
+                BuiltinBehavior::LogicBatchReader(LogicBatchReader::new(device))
+            }
+            "StructureLogicBatchWriter" => {
+                BuiltinBehavior::LogicBatchWriter(LogicBatchWriter::new(device))
+            }
+            _ => return None,
+        })
+    }
+
+    pub fn device(&self) -> u32 {
+        self.as_behavior().device()
+    }
+
+    pub fn into_behavior(self) -> Box<dyn Behavior> {
+        match self {
+            BuiltinBehavior::DaylightSensor(behavior) => Box::new(behavior),
+            BuiltinBehavior::WallHeater(behavior) => Box::new(behavior),
+            BuiltinBehavior::LogicMemory(behavior) => Box::new(behavior),
+            BuiltinBehavior::LogicMath(behavior) => Box::new(behavior),
+            BuiltinBehavior::LogicCompare(behavior) => Box::new(behavior),
+            BuiltinBehavior::LogicSelect(behavior) => Box::new(behavior),
+            BuiltinBehavior::LogicMinMax(behavior) => Box::new(behavior),
+            BuiltinBehavior::LogicReader(behavior) => Box::new(behavior),
+            BuiltinBehavior::LogicWriter(behavior) => Box::new(behavior),
+            BuiltinBehavior::LogicBatchReader(behavior) => Box::new(behavior),
+            BuiltinBehavior::LogicBatchWriter(behavior) => Box::new(behavior),
+        }
+    }
+
+    fn as_behavior(&self) -> &dyn Behavior {
+        match self {
+            BuiltinBehavior::DaylightSensor(behavior) => behavior,
+            BuiltinBehavior::WallHeater(behavior) => behavior,
+            BuiltinBehavior::LogicMemory(behavior) => behavior,
+            BuiltinBehavior::LogicMath(behavior) => behavior,
+            BuiltinBehavior::LogicCompare(behavior) => behavior,
+            BuiltinBehavior::LogicSelect(behavior) => behavior,
+            BuiltinBehavior::LogicMinMax(behavior) => behavior,
+            BuiltinBehavior::LogicReader(behavior) => behavior,
+            BuiltinBehavior::LogicWriter(behavior) => behavior,
+            BuiltinBehavior::LogicBatchReader(behavior) => behavior,
+            BuiltinBehavior::LogicBatchWriter(behavior) => behavior,
+        }
+    }
+}
+
+fn read(vm: &VM, device: u32, typ: LogicType) -> Option<f64> {
+    let device = vm.devices.get(&device)?.borrow();
+    device.get_fields(vm).get(&typ).map(|field| field.value)
+}
+
+/// behaviors write past the field's access, like the game does
+fn write(vm: &VM, device: u32, typ: LogicType, val: f64) {
+    let Some(dev) = vm.devices.get(&device) else {
+        return;
+    };
+    if dev.borrow_mut().set_field(typ, val, vm, true).is_ok() {
+        vm.set_modified(device);
+    }
+}
+
+fn ensure_fields(vm: &VM, device: u32, fields: &[(LogicType, FieldType, f64)]) {
+    if let Some(device) = vm.devices.get(&device) {
+        let mut device = device.borrow_mut();
+        for (typ, field_type, value) in fields {
+            device.ensure_field(*typ, *field_type, *value);
+        }
+    }
+}
+
+/// switched on, and powered if it has a power connection
+fn is_running(vm: &VM, device: u32) -> bool {
+    read(vm, device, LogicType::On) != Some(0.0) && read(vm, device, LogicType::Power) != Some(0.0)
+}
+
+/// reports the sun's position over a day of `day_length` ticks, rising at tick 0
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DaylightSensor {
+    pub device: u32,
+    #[serde(default = "default_day_length")]
+    pub day_length: u64,
+    /// `SolarIrradiance` with the sun overhead
+    #[serde(default = "default_peak_irradiance")]
+    pub peak_irradiance: f64,
+}
+
+fn default_day_length() -> u64 {
+    2400
+}
+
+fn default_peak_irradiance() -> f64 {
+    1000.0
+}
+
+impl DaylightSensor {
+    pub fn new(device: u32) -> Self {
+        DaylightSensor {
+            device,
+            day_length: default_day_length(),
+            peak_irradiance: default_peak_irradiance(),
+        }
+    }
+}
+
+impl Behavior for DaylightSensor {
+    fn device(&self) -> u32 {
+        self.device
+    }
+
+    fn set_device(&mut self, device: u32) {
+        self.device = device;
+    }
+
+    fn attach(&mut self, vm: &VM) {
+        ensure_fields(
+            vm,
+            self.device,
+            &[
+                (LogicType::On, FieldType::ReadWrite, 1.0),
+                (LogicType::Mode, FieldType::ReadWrite, 0.0),
+                (LogicType::Activate, FieldType::ReadWrite, 0.0),
+                (LogicType::Horizontal, FieldType::Read, 0.0),
+                (LogicType::Vertical, FieldType::Read, 0.0),
+                (LogicType::SolarAngle, FieldType::Read, 0.0),
+                (LogicType::SolarIrradiance, FieldType::Read, 0.0),
+            ],
+        );
+    }
+
+    fn tick(&mut self, vm: &VM) {
+        if !is_running(vm, self.device) {
+            return;
+        }
+        let day = (vm.clock() % self.day_length.max(1)) as f64 / self.day_length.max(1) as f64;
+        let height = (TAU * day).sin();
+        let horizontal = 360.0 * day;
+        // degrees from straight up, past 90 the sun is below the horizon
+        let vertical = 90.0 - height.asin() * 180.0 / PI;
+        let solar_angle = match read(vm, self.device, LogicType::Mode).map(|mode| mode as u32) {
+            Some(1) => horizontal,
+            Some(2) => vertical,
+            _ => vertical.min(180.0 - vertical),
+        };
+        write(vm, self.device, LogicType::Horizontal, horizontal);
+        write(vm, self.device, LogicType::Vertical, vertical);
+        write(vm, self.device, LogicType::SolarAngle, solar_angle);
+        write(
+            vm,
+            self.device,
+            LogicType::SolarIrradiance,
+            self.peak_irradiance * height.max(0.0),
+        );
+    }
+
+    fn freeze(&self) -> Option<BuiltinBehavior> {
+        Some(BuiltinBehavior::DaylightSensor(self.clone()))
+    }
+}
+
+/// while on and powered, raises the `Temperature` of each linked room device every tick
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WallHeater {
+    pub device: u32,
+    /// devices whose `Temperature` stands in for the heated room, like gas sensors
+    #[serde(default)]
+    pub rooms: Vec<u32>,
+    /// kelvin added per tick
+    #[serde(default = "default_heat_rate")]
+    pub heat_rate: f64,
+}
+
+fn default_heat_rate() -> f64 {
+    1.0
+}
+
+impl WallHeater {
+    /// watts drawn while on
+    pub const POWER_USE: f64 = 1010.0;
+
+    pub fn new(device: u32) -> Self {
+        WallHeater {
+            device,
+            rooms: Vec::new(),
+            heat_rate: default_heat_rate(),
+        }
+    }
+}
+
+impl Behavior for WallHeater {
+    fn device(&self) -> u32 {
+        self.device
+    }
+
+    fn set_device(&mut self, device: u32) {
+        self.device = device;
+    }
+
+    fn attach(&mut self, vm: &VM) {
+        ensure_fields(
+            vm,
+            self.device,
+            &[
+                (LogicType::On, FieldType::ReadWrite, 0.0),
+                (LogicType::Lock, FieldType::ReadWrite, 0.0),
+                (LogicType::Error, FieldType::Read, 0.0),
+                (LogicType::RequiredPower, FieldType::Read, 0.0),
+            ],
+        );
+    }
+
+    fn tick(&mut self, vm: &VM) {
+        let on = read(vm, self.device, LogicType::On).is_some_and(|on| on != 0.0);
+        write(
+            vm,
+            self.device,
+            LogicType::RequiredPower,
+            if on { Self::POWER_USE } else { 0.0 },
+        );
+        if !on || !is_running(vm, self.device) {
+            return;
+        }
+        for room in &self.rooms {
+            if let Some(temperature) = read(vm, *room, LogicType::Temperature) {
+                write(
+                    vm,
+                    *room,
+                    LogicType::Temperature,
+                    temperature + self.heat_rate,
+                );
+            }
+        }
+    }
+
+    fn links(&self) -> Vec<u32> {
+        self.rooms.clone()
+    }
+
+    fn set_links(&mut self, links: &[u32]) {
+        self.rooms = links.to_vec();
+    }
+
+    fn freeze(&self) -> Option<BuiltinBehavior> {
+        Some(BuiltinBehavior::WallHeater(self.clone()))
+    }
+}
+
+/// holds whatever is written to its `Setting`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LogicMemory {
+    pub device: u32,
+}
+
+impl Behavior for LogicMemory {
+    fn device(&self) -> u32 {
+        self.device
+    }
+
+    fn set_device(&mut self, device: u32) {
+        self.device = device;
+    }
+
+    fn attach(&mut self, vm: &VM) {
+        ensure_fields(
+            vm,
+            self.device,
+            &[(LogicType::Setting, FieldType::ReadWrite, 0.0)],
+        );
+    }
+
+    fn tick(&mut self, _vm: &VM) {}
+
+    fn freeze(&self) -> Option<BuiltinBehavior> {
+        Some(BuiltinBehavior::LogicMemory(self.clone()))
+    }
+}
+
+fn logic_unit_fields(vm: &VM, device: u32) {
+    ensure_fields(
+        vm,
+        device,
+        &[
+            (LogicType::On, FieldType::ReadWrite, 1.0),
+            (LogicType::Mode, FieldType::ReadWrite, 0.0),
+            (LogicType::Setting, FieldType::Read, 0.0),
+            (LogicType::Error, FieldType::Read, 0.0),
+            (LogicType::RequiredPower, FieldType::Read, 0.0),
+        ],
+    );
+}
+
+/// the `Setting` of both inputs and the unit's mode, or `None` with `Error` set
+fn logic_unit_inputs(vm: &VM, device: u32, inputs: &[Option<u32>; 2]) -> Option<(f64, f64, f64)> {
+    if !is_running(vm, device) {
+        return None;
+    }
+    let values = inputs
+        .iter()
+        .map(|input| input.and_then(|input| read(vm, input, LogicType::Setting)))
+        .collect::<Option<Vec<_>>>();
+    write(
+        vm,
+        device,
+        LogicType::Error,
+        if values.is_some() { 0.0 } else { 1.0 },
+    );
+    let values = values?;
+    let mode = read(vm, device, LogicType::Mode).unwrap_or(0.0);
+    Some((values[0], values[1], mode))
+}
+
+fn compare(mode: f64, a: f64, b: f64) -> bool {
+    match mode as u32 {
+        1 => a > b,
+        2 => a < b,
+        3 => a != b,
+        _ => a == b,
+    }
+}
+
+fn inputs_from_links(links: &[u32]) -> [Option<u32>; 2] {
+    [links.first().copied(), links.get(1).copied()]
+}
+
+/// keeps the other inputs where they are when one is unlinked
+fn replace_inputs<'a>(
+    inputs: impl IntoIterator<Item = &'a mut Option<u32>>,
+    old: u32,
+    new: Option<u32>,
+) {
+    for input in inputs {
+        if *input == Some(old) {
+            *input = new;
+        }
+    }
+}
+
+/// applies its mode (Add, Subtract, Multiply, Divide, Mod, Atan2, Pow, Log) to the `Setting`
+/// of its two inputs
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LogicMath {
+    pub device: u32,
+    #[serde(default)]
+    pub inputs: [Option<u32>; 2],
+}
+
+impl LogicMath {
+    pub fn new(device: u32) -> Self {
+        LogicMath {
+            device,
+            inputs: [None, None],
+        }
+    }
+}
+
+impl Behavior for LogicMath {
+    fn device(&self) -> u32 {
+        self.device
+    }
+
+    fn set_device(&mut self, device: u32) {
+        self.device = device;
+    }
+
+    fn attach(&mut self, vm: &VM) {
+        logic_unit_fields(vm, self.device);
+    }
+
+    fn tick(&mut self, vm: &VM) {
+        let Some((a, b, mode)) = logic_unit_inputs(vm, self.device, &self.inputs) else {
+            return;
+        };
+        let val = match mode as u32 {
+            1 => a - b,
+            2 => a * b,
+            3 => a / b,
+            4 => ((a % b) + b) % b,
+            5 => a.atan2(b),
+            6 => a.powf(b),
+            7 => a.log(b),
+            _ => a + b,
+        };
+        write(vm, self.device, LogicType::Setting, val);
+    }
+
+    fn links(&self) -> Vec<u32> {
+        self.inputs.iter().flatten().copied().collect()
+    }
+
+    fn set_links(&mut self, links: &[u32]) {
+        self.inputs = inputs_from_links(links);
+    }
+
+    fn replace_link(&mut self, old: u32, new: Option<u32>) {
+        replace_inputs(&mut self.inputs, old, new);
+    }
+
+    fn freeze(&self) -> Option<BuiltinBehavior> {
+        Some(BuiltinBehavior::LogicMath(self.clone()))
+    }
+}
+
+/// `Setting` is 1 if its inputs pass its mode's comparison (Equals, Greater, Less, NotEquals)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LogicCompare {
+    pub device: u32,
+    #[serde(default)]
+    pub inputs: [Option<u32>; 2],
+}
+
+impl LogicCompare {
+    pub fn new(device: u32) -> Self {
+        LogicCompare {
+            device,
+            inputs: [None, None],
+        }
+    }
+}
+
+impl Behavior for LogicCompare {
+    fn device(&self) -> u32 {
+        self.device
+    }
+
+    fn set_device(&mut self, device: u32) {
+        self.device = device;
+    }
+
+    fn attach(&mut self, vm: &VM) {
+        logic_unit_fields(vm, self.device);
+    }
+
+    fn tick(&mut self, vm: &VM) {
+        let Some((a, b, mode)) = logic_unit_inputs(vm, self.device, &self.inputs) else {
+            return;
+        };
+        let val = if compare(mode, a, b) { 1.0 } else { 0.0 };
+        write(vm, self.device, LogicType::Setting, val);
+    }
+
+    fn links(&self) -> Vec<u32> {
+        self.inputs.iter().flatten().copied().collect()
+    }
+
+    fn set_links(&mut self, links: &[u32]) {
+        self.inputs = inputs_from_links(links);
+    }
+
+    fn replace_link(&mut self, old: u32, new: Option<u32>) {
+        replace_inputs(&mut self.inputs, old, new);
+    }
+
+    fn freeze(&self) -> Option<BuiltinBehavior> {
+        Some(BuiltinBehavior::LogicCompare(self.clone()))
+    }
+}
+
+/// `Setting` is the first input if the inputs pass its mode's comparison, else the second
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LogicSelect {
+    pub device: u32,
+    #[serde(default)]
+    pub inputs: [Option<u32>; 2],
+}
+
+impl LogicSelect {
+    pub fn new(device: u32) -> Self {
+        LogicSelect {
+            device,
+            inputs: [None, None],
+        }
+    }
+}
+
+impl Behavior for LogicSelect {
+    fn device(&self) -> u32 {
+        self.device
+    }
+
+    fn set_device(&mut self, device: u32) {
+        self.device = device;
+    }
+
+    fn attach(&mut self, vm: &VM) {
+        logic_unit_fields(vm, self.device);
+    }
+
+    fn tick(&mut self, vm: &VM) {
+        let Some((a, b, mode)) = logic_unit_inputs(vm, self.device, &self.inputs) else {
+            return;
+        };
+        let val = if compare(mode, a, b) { a } else { b };
+        write(vm, self.device, LogicType::Setting, val);
+    }
+
+    fn links(&self) -> Vec<u32> {
+        self.inputs.iter().flatten().copied().collect()
+    }
+
+    fn set_links(&mut self, links: &[u32]) {
+        self.inputs = inputs_from_links(links);
+    }
+
+    fn replace_link(&mut self, old: u32, new: Option<u32>) {
+        replace_inputs(&mut self.inputs, old, new);
+    }
+
+    fn freeze(&self) -> Option<BuiltinBehavior> {
+        Some(BuiltinBehavior::LogicSelect(self.clone()))
+    }
+}
+
+/// `Setting` is the greater of its inputs in mode 0 (Greater), the lesser in mode 1 (Less)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LogicMinMax {
+    pub device: u32,
+    #[serde(default)]
+    pub inputs: [Option<u32>; 2],
+}
+
+impl LogicMinMax {
+    pub fn new(device: u32) -> Self {
+        LogicMinMax {
+            device,
+            inputs: [None, None],
+        }
+    }
+}
+
+impl Behavior for LogicMinMax {
+    fn device(&self) -> u32 {
+        self.device
+    }
+
+    fn set_device(&mut self, device: u32) {
+        self.device = device;
+    }
+
+    fn attach(&mut self, vm: &VM) {
+        logic_unit_fields(vm, self.device);
+    }
+
+    fn tick(&mut self, vm: &VM) {
+        let Some((a, b, mode)) = logic_unit_inputs(vm, self.device, &self.inputs) else {
+            return;
+        };
+        let val = if mode as u32 == 1 { a.min(b) } else { a.max(b) };
+        write(vm, self.device, LogicType::Setting, val);
+    }
+
+    fn links(&self) -> Vec<u32> {
+        self.inputs.iter().flatten().copied().collect()
+    }
+
+    fn set_links(&mut self, links: &[u32]) {
+        self.inputs = inputs_from_links(links);
+    }
+
+    fn replace_link(&mut self, old: u32, new: Option<u32>) {
+        replace_inputs(&mut self.inputs, old, new);
+    }
+
+    fn freeze(&self) -> Option<BuiltinBehavior> {
+        Some(BuiltinBehavior::LogicMinMax(self.clone()))
+    }
+}
+
+fn logic_io_fields(vm: &VM, device: u32, output: LogicType, output_type: FieldType) {
+    ensure_fields(
+        vm,
+        device,
+        &[
+            (LogicType::On, FieldType::ReadWrite, 1.0),
+            (output, output_type, 0.0),
+            (LogicType::Error, FieldType::Read, 0.0),
+            (LogicType::RequiredPower, FieldType::Read, 0.0),
+        ],
+    );
+}
+
+/// `Error` is 1 when `val` is `None`
+fn report(vm: &VM, device: u32, val: Option<f64>) -> Option<f64> {
+    write(
+        vm,
+        device,
+        LogicType::Error,
+        if val.is_some() { 0.0 } else { 1.0 },
+    );
+    val
+}
+
+/// a field read the way a script would, respecting its access
+fn read_logic(vm: &VM, device: Option<u32>, typ: Option<LogicType>) -> Option<f64> {
+    let device = vm.devices.get(&device?)?.borrow();
+    device.get_field(typ?, vm).ok()
+}
+
+/// `ForceWrite` set, writers send values the field already has
+fn force_write(vm: &VM, device: u32) -> bool {
+    read(vm, device, LogicType::ForceWrite).is_some_and(|force| force != 0.0)
+}
+
+/// a field written the way a script would, respecting its access, a value the field already has
+/// is only sent again if `force` is set
+fn write_logic(
+    vm: &VM,
+    device: &Rc<RefCell<Device>>,
+    typ: LogicType,
+    val: f64,
+    force: bool,
+) -> Option<()> {
+    let mut device = device.borrow_mut();
+    if !force && device.get_field(typ, vm).is_ok_and(|old| old == val) {
+        return Some(());
+    }
+    device.set_field(typ, val, vm, false).ok()?;
+    vm.set_modified(device.id);
+    Some(())
+}
+
+/// `Setting` mirrors `field` of its input device
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LogicReader {
+    pub device: u32,
+    #[serde(default)]
+    pub input: Option<u32>,
+    #[serde(default)]
+    pub field: Option<LogicType>,
+}
+
+impl LogicReader {
+    pub fn new(device: u32) -> Self {
+        LogicReader {
+            device,
+            input: None,
+            field: None,
+        }
+    }
+}
+
+impl Behavior for LogicReader {
+    fn device(&self) -> u32 {
+        self.device
+    }
+
+    fn set_device(&mut self, device: u32) {
+        self.device = device;
+    }
+
+    fn attach(&mut self, vm: &VM) {
+        logic_io_fields(vm, self.device, LogicType::Setting, FieldType::Read);
+    }
+
+    fn tick(&mut self, vm: &VM) {
+        if !is_running(vm, self.device) {
+            return;
+        }
+        if let Some(val) = report(vm, self.device, read_logic(vm, self.input, self.field)) {
+            write(vm, self.device, LogicType::Setting, val);
+        }
+    }
+
+    fn links(&self) -> Vec<u32> {
+        self.input.into_iter().collect()
+    }
+
+    fn set_links(&mut self, links: &[u32]) {
+        self.input = links.first().copied();
+    }
+
+    fn freeze(&self) -> Option<BuiltinBehavior> {
+        Some(BuiltinBehavior::LogicReader(self.clone()))
+    }
+}
+
+/// writes the `Setting` of its input device to `field` of its output device
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LogicWriter {
+    pub device: u32,
+    #[serde(default)]
+    pub input: Option<u32>,
+    #[serde(default)]
+    pub output: Option<u32>,
+    #[serde(default)]
+    pub field: Option<LogicType>,
+}
+
+impl LogicWriter {
+    pub fn new(device: u32) -> Self {
+        LogicWriter {
+            device,
+            input: None,
+            output: None,
+            field: None,
+        }
+    }
+}
+
+impl Behavior for LogicWriter {
+    fn device(&self) -> u32 {
+        self.device
+    }
+
+    fn set_device(&mut self, device: u32) {
+        self.device = device;
+    }
+
+    fn attach(&mut self, vm: &VM) {
+        logic_io_fields(vm, self.device, LogicType::ForceWrite, FieldType::Write);
+    }
+
+    fn tick(&mut self, vm: &VM) {
+        if !is_running(vm, self.device) {
+            return;
+        }
+        let force = force_write(vm, self.device);
+        let written = read_logic(vm, self.input, Some(LogicType::Setting)).and_then(|val| {
+            let output = vm.devices.get(&self.output?)?;
+            write_logic(vm, output, self.field?, val, force)?;
+            Some(val)
+        });
+        report(vm, self.device, written);
+    }
+
+    fn links(&self) -> Vec<u32> {
+        self.input.into_iter().chain(self.output).collect()
+    }
+
+    fn set_links(&mut self, links: &[u32]) {
+        self.input = links.first().copied();
+        self.output = links.get(1).copied();
+    }
+
+    fn replace_link(&mut self, old: u32, new: Option<u32>) {
+        replace_inputs([&mut self.input, &mut self.output], old, new);
+    }
+
+    fn freeze(&self) -> Option<BuiltinBehavior> {
+        Some(BuiltinBehavior::LogicWriter(self.clone()))
+    }
+}
+
+/// `Setting` is `field` of every device of `prefab_hash` on its networks, combined by `mode`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LogicBatchReader {
+    pub device: u32,
+    #[serde(default)]
+    pub prefab_hash: i32,
+    #[serde(default)]
+    pub field: Option<LogicType>,
+    #[serde(default = "default_batch_mode")]
+    pub mode: BatchMode,
+}
+
+fn default_batch_mode() -> BatchMode {
+    BatchMode::Average
+}
+
+impl LogicBatchReader {
+    pub fn new(device: u32) -> Self {
+        LogicBatchReader {
+            device,
+            prefab_hash: 0,
+            field: None,
+            mode: default_batch_mode(),
+        }
+    }
+}
+
+impl Behavior for LogicBatchReader {
+    fn device(&self) -> u32 {
+        self.device
+    }
+
+    fn set_device(&mut self, device: u32) {
+        self.device = device;
+    }
+
+    fn attach(&mut self, vm: &VM) {
+        logic_io_fields(vm, self.device, LogicType::Setting, FieldType::Read);
+    }
+
+    fn tick(&mut self, vm: &VM) {
+        if !is_running(vm, self.device) {
+            return;
+        }
+        let val = self.field.and_then(|field| {
+            vm.get_batch_device_field(self.device, self.prefab_hash as f64, field, self.mode)
+                .ok()
+        });
+        if let Some(val) = report(vm, self.device, val) {
+            write(vm, self.device, LogicType::Setting, val);
+        }
+    }
+
+    fn freeze(&self) -> Option<BuiltinBehavior> {
+        Some(BuiltinBehavior::LogicBatchReader(self.clone()))
+    }
+}
+
+/// writes the `Setting` of its input device to `field` of every device of `prefab_hash` on its
+/// networks
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LogicBatchWriter {
+    pub device: u32,
+    #[serde(default)]
+    pub input: Option<u32>,
+    #[serde(default)]
+    pub prefab_hash: i32,
+    #[serde(default)]
+    pub field: Option<LogicType>,
+}
+
+impl LogicBatchWriter {
+    pub fn new(device: u32) -> Self {
+        LogicBatchWriter {
+            device,
+            input: None,
+            prefab_hash: 0,
+            field: None,
+        }
+    }
+}
+
+impl Behavior for LogicBatchWriter {
+    fn device(&self) -> u32 {
+        self.device
+    }
+
+    fn set_device(&mut self, device: u32) {
+        self.device = device;
+    }
+
+    fn attach(&mut self, vm: &VM) {
+        logic_io_fields(vm, self.device, LogicType::ForceWrite, FieldType::Write);
+    }
+
+    fn tick(&mut self, vm: &VM) {
+        if !is_running(vm, self.device) {
+            return;
+        }
+        let force = force_write(vm, self.device);
+        let written = read_logic(vm, self.input, Some(LogicType::Setting)).and_then(|val| {
+            let field = self.field?;
+            vm.batch_device(self.device, self.prefab_hash as f64, None)
+                .map(|device| write_logic(vm, device, field, val, force))
+                .collect::<Option<Vec<_>>>()?;
+            Some(val)
+        });
+        report(vm, self.device, written);
+    }
+
+    fn links(&self) -> Vec<u32> {
+        self.input.into_iter().collect()
+    }
+
+    fn set_links(&mut self, links: &[u32]) {
+        self.input = links.first().copied();
+    }
+
+    fn freeze(&self) -> Option<BuiltinBehavior> {
+        Some(BuiltinBehavior::LogicBatchWriter(self.clone()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{network::Connection, prefabs, vm::VMError};
+    use itertools::Itertools;
+
+    /// a prefab device with all its cable connections on the default network
+    fn add(vm: &mut VM, prefab: &str) -> Result<u32, VMError> {
+        let id = vm.add_device_by_prefab(prefab, None)?;
+        let cables = vm.devices[&id]
+            .borrow()
+            .connections
+            .iter()
+            .positions(|conn| matches!(conn, Connection::CableNetwork { .. }))
+            .collect_vec();
+        for index in cables {
+            vm.set_device_connection(id, index, Some(vm.default_network))?;
+        }
+        Ok(id)
+    }
+
+    fn set(vm: &VM, device: u32, typ: LogicType, val: f64) -> Result<(), VMError> {
+        vm.devices[&device]
+            .borrow_mut()
+            .set_field(typ, val, vm, true)?;
+        Ok(())
+    }
+
+    fn field(vm: &VM, device: u32, typ: LogicType) -> f64 {
+        vm.devices[&device].borrow().get_field(typ, vm).unwrap()
+    }
+
+    #[test]
+    fn closed_loop() -> Result<(), VMError> {
+        let mut vm = VM::new();
+        let room = vm.add_device(None)?;
+        set(&vm, room, LogicType::Temperature, 280.0)?;
+        let heater = add(&mut vm, "StructureWallHeater")?;
+        vm.link_behavior(heater, &[room])?;
+        let low = add(&mut vm, "StructureLogicMemory")?;
+        set(&vm, low, LogicType::Setting, 3.0)?;
+        let high = add(&mut vm, "StructureLogicMemory")?;
+        set(&vm, high, LogicType::Setting, 4.0)?;
+        let sum = add(&mut vm, "StructureLogicMath")?;
+        vm.link_behavior(sum, &[low, high])?;
+        let pick = add(&mut vm, "StructureLogicSelect")?;
+        set(&vm, pick, LogicType::Mode, 1.0)?;
+        vm.link_behavior(pick, &[low, high])?;
+        let sun = add(&mut vm, "StructureDaylightSensor")?;
+        let thermostat = vm.add_ic(None)?;
+        vm.set_pin(thermostat, 0, Some(room))?;
+        vm.set_pin(thermostat, 1, Some(heater))?;
+        vm.set_code(
+            thermostat,
+            "start:\nl r0 d0 Temperature\nslt r1 r0 285\ns d1 On r1\nyield\nj start",
+        )?;
+
+        vm.run_ticks(20)?;
+        let temperature = field(&vm, room, LogicType::Temperature);
+        assert!((285.0..=286.0).contains(&temperature));
+        assert_eq!(field(&vm, heater, LogicType::RequiredPower), 0.0);
+        assert_eq!(field(&vm, sum, LogicType::Setting), 7.0);
+        assert_eq!(field(&vm, pick, LogicType::Setting), 4.0);
+        assert!(field(&vm, sun, LogicType::SolarIrradiance) > 0.0);
+
+        let state = vm.save_vm_state();
+        assert_eq!(state.behaviors.len(), 6);
+        let mut restored = VM::new();
+        restored.restore_vm_state(state)?;
+        assert_eq!(restored.get_behaviors(), vm.get_behaviors());
+        Ok(())
+    }
+
+    #[test]
+    fn logic_chips() -> Result<(), VMError> {
+        let sensor_hash = prefabs::get("StructureGasSensor").unwrap().hash;
+        let pump_hash = prefabs::get("StructureVolumePump").unwrap().hash;
+        let mut vm = VM::new();
+        let north = add(&mut vm, "StructureGasSensor")?;
+        set(&vm, north, LogicType::Temperature, 290.0)?;
+        let south = add(&mut vm, "StructureGasSensor")?;
+        set(&vm, south, LogicType::Temperature, 300.0)?;
+        let pump = add(&mut vm, "StructureVolumePump")?;
+        set(&vm, pump, LogicType::Setting, 0.0)?;
+        let average = add(&mut vm, "StructureLogicBatchReader")?;
+        vm.set_builtin_behavior(BuiltinBehavior::LogicBatchReader(LogicBatchReader {
+            device: average,
+            prefab_hash: sensor_hash,
+            field: Some(LogicType::Temperature),
+            mode: BatchMode::Average,
+        }))?;
+        let read_north = add(&mut vm, "StructureLogicReader")?;
+        vm.set_builtin_behavior(BuiltinBehavior::LogicReader(LogicReader {
+            device: read_north,
+            input: Some(north),
+            field: Some(LogicType::Temperature),
+        }))?;
+        let lowest = add(&mut vm, "StructureLogicMinMax")?;
+        set(&vm, lowest, LogicType::Mode, 1.0)?;
+        vm.link_behavior(lowest, &[average, read_north])?;
+        let to_pump = add(&mut vm, "StructureLogicWriter")?;
+        vm.set_builtin_behavior(BuiltinBehavior::LogicWriter(LogicWriter {
+            device: to_pump,
+            input: Some(lowest),
+            output: Some(pump),
+            field: Some(LogicType::Setting),
+        }))?;
+        let lock_pumps = add(&mut vm, "StructureLogicBatchWriter")?;
+        vm.set_builtin_behavior(BuiltinBehavior::LogicBatchWriter(LogicBatchWriter {
+            device: lock_pumps,
+            input: Some(read_north),
+            prefab_hash: pump_hash,
+            field: Some(LogicType::Ratio),
+        }))?;
+
+        vm.run_ticks(1)?;
+        assert_eq!(field(&vm, average, LogicType::Setting), 295.0);
+        assert_eq!(field(&vm, read_north, LogicType::Setting), 290.0);
+        assert_eq!(field(&vm, lowest, LogicType::Setting), 290.0);
+        assert_eq!(field(&vm, pump, LogicType::Setting), 290.0);
+        // Ratio is read only
+        assert_eq!(field(&vm, lock_pumps, LogicType::Error), 1.0);
+        assert_eq!(field(&vm, pump, LogicType::Ratio), 0.0);
+
+        // ForceWrite only sends unchanged values again, it doesn't get past the field's access
+        vm.devices[&lock_pumps]
+            .borrow_mut()
+            .set_field(LogicType::ForceWrite, 1.0, &vm, false)?;
+        vm.run_ticks(1)?;
+        assert_eq!(field(&vm, lock_pumps, LogicType::Error), 1.0);
+        assert_eq!(field(&vm, pump, LogicType::Ratio), 0.0);
+        Ok(())
+    }
+}
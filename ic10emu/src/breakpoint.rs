@@ -0,0 +1,313 @@
+use std::{collections::BTreeMap, fmt::Display, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{
+    grammar::{InstructionOp, LogicType, Operand},
+    interpreter::{ICError, IC},
+    vm::VM,
+};
+
+#[derive(Debug, Error, Clone, Serialize, Deserialize)]
+pub enum BreakpointError {
+    #[error("invalid breakpoint condition '{0}': {1}")]
+    InvalidCondition(String, String),
+}
+
+/// where in a program a breakpoint is placed
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum BreakpointLocation {
+    Line(u32),
+    /// resolved against the program's labels each time it's checked so it follows code edits
+    Label(String),
+    /// checked before every line, only useful with a condition
+    Anywhere,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Comparison {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl Comparison {
+    // longer operators first so `<=` isn't matched as `<`
+    const OPERATORS: [(&'static str, Comparison); 6] = [
+        ("==", Comparison::Eq),
+        ("!=", Comparison::Ne),
+        ("<=", Comparison::Le),
+        (">=", Comparison::Ge),
+        ("<", Comparison::Lt),
+        (">", Comparison::Gt),
+    ];
+
+    /// split `lhs <op> rhs` around the first comparison operator
+    pub fn split(s: &str) -> Option<(&str, Comparison, &str)> {
+        let (index, op, cmp) = Comparison::OPERATORS
+            .iter()
+            .filter_map(|(op, cmp)| s.find(op).map(|index| (index, *op, *cmp)))
+            .min_by_key(|(index, op, _)| (*index, usize::MAX - op.len()))?;
+        Some((&s[..index], cmp, &s[index + op.len()..]))
+    }
+
+    pub fn operator(&self) -> &'static str {
+        Comparison::OPERATORS
+            .iter()
+            .find(|(_, cmp)| cmp == self)
+            .map(|(op, _)| *op)
+            .expect("every comparison has an operator")
+    }
+
+    pub fn apply(&self, lhs: f64, rhs: f64) -> bool {
+        match self {
+            Comparison::Eq => lhs == rhs,
+            Comparison::Ne => lhs != rhs,
+            Comparison::Lt => lhs < rhs,
+            Comparison::Le => lhs <= rhs,
+            Comparison::Gt => lhs > rhs,
+            Comparison::Ge => lhs >= rhs,
+        }
+    }
+}
+
+/// one side of a breakpoint condition
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConditionOperand {
+    /// anything an instruction could read as a value, `r0`, `5`, an alias or define
+    Value(Operand),
+    /// a device logic field, `db Setting`
+    Field(Operand, Operand),
+}
+
+impl FromStr for ConditionOperand {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parts = s.split_whitespace().collect::<Vec<_>>();
+        match &parts[..] {
+            [value] => Ok(ConditionOperand::Value(
+                value.parse::<Operand>().map_err(|err| err.to_string())?,
+            )),
+            [device, field] => Ok(ConditionOperand::Field(
+                device.parse::<Operand>().map_err(|err| err.to_string())?,
+                field.parse::<Operand>().map_err(|err| err.to_string())?,
+            )),
+            [] => Err("missing operand".to_owned()),
+            _ => Err(format!("can't parse operand '{s}'")),
+        }
+    }
+}
+
+impl ConditionOperand {
+    pub fn value(&self, ic: &IC, vm: &VM) -> Result<f64, ICError> {
+        match self {
+            ConditionOperand::Value(operand) => operand.as_value(ic, InstructionOp::Nop, 1),
+            ConditionOperand::Field(device, field) => {
+                let (Some(device_id), _connection) = device.as_device(ic, InstructionOp::Nop, 1)?
+                else {
+                    return Err(ICError::DeviceNotSet);
+                };
+                let lt = field.as_logic_type(ic, InstructionOp::Nop, 2)?;
+                if lt == LogicType::LineNumber && ic.device == device_id {
+                    Ok(ic.ip() as f64)
+                } else {
+                    vm.get_device_same_network(ic.device, device_id)
+                        .ok_or(ICError::UnknownDeviceID(device_id as f64))?
+                        .borrow()
+                        .get_field(lt, vm)
+                }
+            }
+        }
+    }
+}
+
+/// a comparison like `r0 > 5` or `db Setting == 1`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct BreakpointCondition {
+    pub lhs: ConditionOperand,
+    pub cmp: Comparison,
+    pub rhs: ConditionOperand,
+    source: String,
+}
+
+impl FromStr for BreakpointCondition {
+    type Err = BreakpointError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = |msg: String| BreakpointError::InvalidCondition(s.to_owned(), msg);
+        let (lhs, cmp, rhs) = Comparison::split(s)
+            .ok_or_else(|| invalid("missing comparison operator".to_owned()))?;
+        let lhs = lhs.parse::<ConditionOperand>().map_err(invalid)?;
+        let rhs = rhs.parse::<ConditionOperand>().map_err(invalid)?;
+        Ok(BreakpointCondition {
+            lhs,
+            cmp,
+            rhs,
+            source: s.trim().to_owned(),
+        })
+    }
+}
+
+impl TryFrom<String> for BreakpointCondition {
+    type Error = BreakpointError;
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<BreakpointCondition> for String {
+    fn from(value: BreakpointCondition) -> Self {
+        value.source
+    }
+}
+
+impl Display for BreakpointCondition {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+impl BreakpointCondition {
+    /// conditions that can't be evaluated, eg. reading an unset device, never match
+    pub fn matches(&self, ic: &IC, vm: &VM) -> bool {
+        match (self.lhs.value(ic, vm), self.rhs.value(ic, vm)) {
+            (Ok(lhs), Ok(rhs)) => self.cmp.apply(lhs, rhs),
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Breakpoint {
+    pub location: BreakpointLocation,
+    pub condition: Option<BreakpointCondition>,
+    pub enabled: bool,
+}
+
+impl Breakpoint {
+    pub fn at_line(line: u32) -> Self {
+        Breakpoint {
+            location: BreakpointLocation::Line(line),
+            condition: None,
+            enabled: true,
+        }
+    }
+
+    pub fn at_label(label: &str) -> Self {
+        Breakpoint {
+            location: BreakpointLocation::Label(label.to_owned()),
+            condition: None,
+            enabled: true,
+        }
+    }
+
+    pub fn when(condition: BreakpointCondition) -> Self {
+        Breakpoint {
+            location: BreakpointLocation::Anywhere,
+            condition: Some(condition),
+            enabled: true,
+        }
+    }
+
+    pub fn with_condition(mut self, condition: BreakpointCondition) -> Self {
+        self.condition = Some(condition);
+        self
+    }
+
+    /// true if the ic should stop before executing `line`
+    pub fn is_hit(&self, ic: &IC, vm: &VM, line: u32) -> bool {
+        if !self.enabled {
+            return false;
+        }
+        let at_location = match &self.location {
+            BreakpointLocation::Line(l) => *l == line,
+            BreakpointLocation::Label(label) => {
+                ic.program.borrow().labels.get(label).copied() == Some(line)
+            }
+            BreakpointLocation::Anywhere => true,
+        };
+        at_location
+            && self
+                .condition
+                .as_ref()
+                .is_none_or(|condition| condition.matches(ic, vm))
+    }
+}
+
+/// a breakpoint that stopped an ic before it executed `line`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BreakpointHit {
+    /// id of the ic's housing
+    pub device: u32,
+    pub ic: u32,
+    pub breakpoint: u32,
+    pub line: u32,
+}
+
+/// the breakpoints set on one ic, keyed by an id unique to that ic
+#[derive(Debug, Default, Clone)]
+pub struct Breakpoints {
+    next_id: u32,
+    breakpoints: BTreeMap<u32, Breakpoint>,
+}
+
+impl Breakpoints {
+    pub fn add(&mut self, breakpoint: Breakpoint) -> u32 {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.breakpoints.insert(id, breakpoint);
+        id
+    }
+
+    pub fn remove(&mut self, id: u32) -> Option<Breakpoint> {
+        self.breakpoints.remove(&id)
+    }
+
+    pub fn get_mut(&mut self, id: u32) -> Option<&mut Breakpoint> {
+        self.breakpoints.get_mut(&id)
+    }
+
+    pub fn clear(&mut self) {
+        self.breakpoints.clear();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.breakpoints.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&u32, &Breakpoint)> {
+        self.breakpoints.iter()
+    }
+
+    /// the id of the first breakpoint that stops `ic` before executing `line`
+    pub fn check(&self, ic: &IC, vm: &VM, line: u32) -> Option<u32> {
+        self.breakpoints
+            .iter()
+            .find(|(_, breakpoint)| breakpoint.is_hit(ic, vm, line))
+            .map(|(id, _)| *id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_conditions() {
+        let cond = "r0 > 5".parse::<BreakpointCondition>().unwrap();
+        assert_eq!(cond.cmp, Comparison::Gt);
+        assert!(matches!(cond.lhs, ConditionOperand::Value(_)));
+        let cond = "db Setting==1".parse::<BreakpointCondition>().unwrap();
+        assert_eq!(cond.cmp, Comparison::Eq);
+        assert!(matches!(cond.lhs, ConditionOperand::Field(_, _)));
+        assert_eq!(cond.to_string(), "db Setting==1");
+        let cond = "r1 <= r2".parse::<BreakpointCondition>().unwrap();
+        assert_eq!(cond.cmp, Comparison::Le);
+        assert!("r0".parse::<BreakpointCondition>().is_err());
+        assert!("a b c > 1".parse::<BreakpointCondition>().is_err());
+    }
+}
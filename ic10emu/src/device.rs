@@ -1,10 +1,15 @@
 use crate::{
     grammar::{LogicType, ReagentMode, SlotLogicType},
     interpreter::{ICError, ICState},
-    network::{CableConnectionType, Connection},
-    vm::VM,
+    network::{Bridge, CableConnectionType, Connection},
+    prefabs::{self, PrefabInfo},
+    vm::{UnknownPrefabAccess, VM},
+    watch::{Access, WatchTarget},
+};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    ops::Deref,
 };
-use std::{collections::BTreeMap, ops::Deref};
 
 use itertools::Itertools;
 
@@ -520,6 +525,7 @@ pub enum SlotType {
     ScanningHead,
     Flare,
     Blocked,
+    SuitMod,
     #[default]
     None = 0,
 }
@@ -549,6 +555,10 @@ pub struct Device {
     pub reagents: BTreeMap<ReagentMode, BTreeMap<i32, f64>>,
     pub ic: Option<u32>,
     pub connections: Vec<Connection>,
+    /// the wireless device a receiver, like a logic transmitter, is linked to
+    wireless_link: Option<u32>,
+    /// what the device passes between its networks, in place of the prefab's
+    bridge: Option<Bridge>,
     fields: BTreeMap<LogicType, LogicField>,
 }
 
@@ -567,6 +577,8 @@ impl Device {
                 net: None,
                 typ: CableConnectionType::default(),
             }],
+            wireless_link: None,
+            bridge: None,
         }
     }
 
@@ -633,7 +645,7 @@ impl Device {
                 LogicField {
                     field_type: FieldType::Read,
                     value: match *ic.state.borrow() {
-                        ICState::Error(_) => 1.0,
+                        ICState::Error(_) | ICState::HasCaughtFire => 1.0,
                         _ => 0.0,
                     },
                 },
@@ -644,11 +656,7 @@ impl Device {
                 LogicType::Power,
                 LogicField {
                     field_type: FieldType::Read,
-                    value: if self.has_power_connection() {
-                        1.0
-                    } else {
-                        0.0
-                    },
+                    value: if self.is_powered(vm) { 1.0 } else { 0.0 },
                 },
             );
         }
@@ -732,24 +740,34 @@ impl Device {
     }
 
     pub fn get_field(&self, typ: LogicType, vm: &VM) -> Result<f64, ICError> {
-        if typ == LogicType::LineNumber && self.ic.is_some() {
+        let val = if typ == LogicType::LineNumber && self.ic.is_some() {
             let ic = vm
                 .ics
                 .get(&self.ic.unwrap())
                 .ok_or_else(|| ICError::UnknownDeviceID(self.ic.unwrap() as f64))?
                 .borrow();
             Ok(ic.ip() as f64)
-        } else if let Some(field) = self.get_fields(vm).get(&typ) {
-            if field.field_type == FieldType::Read || field.field_type == FieldType::ReadWrite {
-                Ok(field.value)
-            } else {
-                Err(ICError::WriteOnlyField(typ.to_string()))
-            }
         } else {
-            Err(ICError::DeviceHasNoField(typ.to_string()))
-        }
+            match self.logic_access(typ, vm) {
+                Some(FieldType::Read | FieldType::ReadWrite) => Ok(self
+                    .get_fields(vm)
+                    .get(&typ)
+                    .map_or(0.0, |field| field.value)),
+                Some(FieldType::Write) => Err(ICError::WriteOnlyField(typ.to_string())),
+                None => Err(ICError::DeviceHasNoField(typ.to_string())),
+            }
+        }?;
+        vm.record_access(Access::read(
+            WatchTarget::Field {
+                device: self.id,
+                field: typ,
+            },
+            val,
+        ));
+        Ok(val)
     }
 
+    /// forced writes come from outside the running scripts and aren't recorded as accesses
     pub fn set_field(
         &mut self,
         typ: LogicType,
@@ -757,7 +775,8 @@ impl Device {
         vm: &VM,
         force: bool,
     ) -> Result<(), ICError> {
-        if typ == LogicType::ReferenceId
+        let old = self.fields.get(&typ).map_or(0.0, |field| field.value);
+        let result = if typ == LogicType::ReferenceId
             || (typ == LogicType::Error && self.ic.is_some())
             || (typ == LogicType::Power && self.has_power_state())
         {
@@ -770,36 +789,103 @@ impl Device {
                 .borrow();
             ic.set_ip(val as u32);
             Ok(())
-        } else if let Some(field) = self.fields.get_mut(&typ) {
-            if field.field_type == FieldType::Write
-                || field.field_type == FieldType::ReadWrite
-                || force
-            {
-                field.value = val;
-                Ok(())
-            } else {
-                Err(ICError::ReadOnlyField(typ.to_string()))
-            }
         } else if force {
-            self.fields.insert(
-                typ,
-                LogicField {
+            self.fields
+                .entry(typ)
+                .or_insert(LogicField {
                     field_type: FieldType::ReadWrite,
                     value: val,
-                },
-            );
+                })
+                .value = val;
             Ok(())
         } else {
-            Err(ICError::DeviceHasNoField(typ.to_string()))
+            match self.logic_access(typ, vm) {
+                Some(field_type @ (FieldType::Write | FieldType::ReadWrite)) => {
+                    self.fields
+                        .entry(typ)
+                        .or_insert(LogicField {
+                            field_type,
+                            value: val,
+                        })
+                        .value = val;
+                    Ok(())
+                }
+                Some(FieldType::Read) => Err(ICError::ReadOnlyField(typ.to_string())),
+                None => Err(ICError::DeviceHasNoField(typ.to_string())),
+            }
+        };
+        if result.is_ok() && !force {
+            vm.record_access(Access::write(
+                WatchTarget::Field {
+                    device: self.id,
+                    field: typ,
+                },
+                old,
+                val,
+            ));
+        }
+        if result.is_ok() && typ == LogicType::On && self.bridge().is_some() {
+            vm.bridges_changed();
+        }
+        result
+    }
+
+    /// the device's entry in the prefab database, if its prefab is known
+    pub fn prefab_info(&self) -> Option<&'static PrefabInfo> {
+        prefabs::get(&self.prefab.as_ref()?.name)
+    }
+
+    /// the access instructions have to a field, from the prefab database if the prefab is known
+    pub fn logic_access(&self, typ: LogicType, vm: &VM) -> Option<FieldType> {
+        match self.prefab_info() {
+            Some(prefab) => prefab.logic_access(typ),
+            None => match vm.unknown_prefab_access() {
+                UnknownPrefabAccess::Permissive => {
+                    self.get_fields(vm).get(&typ).map(|field| field.field_type)
+                }
+                UnknownPrefabAccess::Deny => None,
+            },
         }
     }
 
+    /// check the prefab database lets instructions read, or write, a slot field, the slot's own
+    /// fields decide for unknown prefabs in permissive mode
+    fn check_slot_access(
+        &self,
+        index: usize,
+        typ: SlotLogicType,
+        write: bool,
+        vm: &VM,
+    ) -> Result<(), ICError> {
+        let access = match self.prefab_info() {
+            Some(prefab) => prefab.slot_logic_access(index, typ),
+            None if vm.unknown_prefab_access() == UnknownPrefabAccess::Deny => None,
+            None => return Ok(()),
+        };
+        match (access, write) {
+            (Some(FieldType::ReadWrite), _)
+            | (Some(FieldType::Read), false)
+            | (Some(FieldType::Write), true) => Ok(()),
+            (Some(FieldType::Read), true) => Err(ICError::ReadOnlyField(typ.to_string())),
+            (Some(FieldType::Write), false) => Err(ICError::WriteOnlyField(typ.to_string())),
+            (None, _) => Err(ICError::DeviceHasNoField(typ.to_string())),
+        }
+    }
+
+    /// add a field if the device doesn't already have it
+    pub fn ensure_field(&mut self, typ: LogicType, field_type: FieldType, value: f64) {
+        self.fields
+            .entry(typ)
+            .or_insert(LogicField { field_type, value });
+    }
+
     pub fn get_slot_field(&self, index: f64, typ: SlotLogicType, vm: &VM) -> Result<f64, ICError> {
         let slot = self
             .slots
             .get(index as usize)
             .ok_or(ICError::SlotIndexOutOfRange(index))?;
-        if slot.typ == SlotType::ProgrammableChip
+        self.check_slot_access(index as usize, typ, false, vm)?;
+        let val = if slot.typ == SlotType::ProgrammableChip
             && slot.occupant.is_some()
             && self.ic.is_some()
             && typ == SlotLogicType::LineNumber
@@ -809,10 +895,19 @@ impl Device {
                 .get(&self.ic.unwrap())
                 .ok_or_else(|| ICError::UnknownDeviceID(self.ic.unwrap() as f64))?
                 .borrow();
-            Ok(ic.ip() as f64)
+            ic.ip() as f64
         } else {
-            Ok(slot.get_field(typ))
-        }
+            slot.get_field(typ)
+        };
+        vm.record_access(Access::read(
+            WatchTarget::SlotField {
+                device: self.id,
+                slot: index as u32,
+                field: typ,
+            },
+            val,
+        ));
+        Ok(val)
     }
 
     pub fn get_slot_fields(
@@ -847,14 +942,30 @@ impl Device {
         index: f64,
         typ: SlotLogicType,
         val: f64,
-        _vm: &VM,
+        vm: &VM,
         force: bool,
     ) -> Result<(), ICError> {
+        if !force {
+            self.check_slot_access(index as usize, typ, true, vm)?;
+        }
         let slot = self
             .slots
             .get_mut(index as usize)
             .ok_or(ICError::SlotIndexOutOfRange(index))?;
-        slot.set_field(typ, val, force)
+        let old = slot.get_field(typ);
+        slot.set_field(typ, val, force)?;
+        if !force {
+            vm.record_access(Access::write(
+                WatchTarget::SlotField {
+                    device: self.id,
+                    slot: index as u32,
+                    field: typ,
+                },
+                old,
+                val,
+            ));
+        }
+        Ok(())
     }
 
     pub fn get_slot(&self, index: f64) -> Result<&Slot, ICError> {
@@ -863,13 +974,43 @@ impl Device {
             .ok_or(ICError::SlotIndexOutOfRange(index))
     }
 
+    /// what `lr` reads, `Required` and `TotalContents` are worked out from the contents and recipe
     pub fn get_reagent(&self, rm: &ReagentMode, reagent: f64) -> f64 {
-        if let Some(mode) = self.reagents.get(rm) {
-            if let Some(val) = mode.get(&(reagent as i32)) {
-                return *val;
+        let stored = |rm: ReagentMode| {
+            self.reagents
+                .get(&rm)
+                .and_then(|mode| mode.get(&(reagent as i32)))
+                .copied()
+                .unwrap_or(0.0)
+        };
+        match rm {
+            ReagentMode::Contents | ReagentMode::Recipe => stored(*rm),
+            ReagentMode::Required => {
+                (stored(ReagentMode::Recipe) - stored(ReagentMode::Contents)).max(0.0)
             }
+            ReagentMode::TotalContents => self
+                .reagents
+                .get(&ReagentMode::Contents)
+                .map(|contents| contents.values().sum())
+                .unwrap_or(0.0),
+        }
+    }
+
+    /// set the amount of a reagent the device contains, or its recipe needs
+    pub fn set_reagent(&mut self, rm: ReagentMode, reagent: i32, val: f64) -> Result<(), ICError> {
+        if !matches!(rm, ReagentMode::Contents | ReagentMode::Recipe) {
+            return Err(ICError::ReadOnlyField(rm.to_string()));
+        }
+        let mode = self.reagents.entry(rm).or_default();
+        if val == 0.0 {
+            mode.remove(&reagent);
+        } else {
+            mode.insert(reagent, val);
+        }
+        if mode.is_empty() {
+            self.reagents.remove(&rm);
         }
-        0.0
+        Ok(())
     }
 
     pub fn set_name(&mut self, name: &str) {
@@ -900,6 +1041,92 @@ impl Device {
             )
         })
     }
+
+    /// the network the device draws power from, through its first connected power cable
+    pub fn power_network(&self) -> Option<u32> {
+        self.connections.iter().find_map(|conn| match conn {
+            Connection::CableNetwork {
+                net,
+                typ: CableConnectionType::Power | CableConnectionType::PowerAndData,
+            } => *net,
+            _ => None,
+        })
+    }
+
+    /// the networks the device has a power connection to
+    pub fn power_networks(&self) -> BTreeSet<u32> {
+        self.connections
+            .iter()
+            .filter_map(|conn| match conn {
+                Connection::CableNetwork {
+                    net,
+                    typ: CableConnectionType::Power | CableConnectionType::PowerAndData,
+                } => *net,
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// the networks the device has a data connection to, and those it only has power from
+    pub fn network_membership(&self) -> (BTreeSet<u32>, BTreeSet<u32>) {
+        let mut data = BTreeSet::new();
+        let mut power = BTreeSet::new();
+        for conn in &self.connections {
+            if let Connection::CableNetwork {
+                net: Some(net),
+                typ,
+            } = conn
+            {
+                match typ {
+                    CableConnectionType::Power => power.insert(*net),
+                    CableConnectionType::Data | CableConnectionType::PowerAndData => {
+                        data.insert(*net)
+                    }
+                };
+            }
+        }
+        power.retain(|net| !data.contains(net));
+        (data, power)
+    }
+
+    /// the prefab can link to a wireless device, like a logic transmitter
+    pub fn is_wireless_receiver(&self) -> bool {
+        self.prefab_info().is_some_and(|prefab| prefab.receiver)
+    }
+
+    /// the prefab can be linked to by a wireless receiver
+    pub fn is_wireless_transmitter(&self) -> bool {
+        self.prefab_info().is_some_and(|prefab| prefab.transmitter)
+    }
+
+    /// the wireless device this receiver is linked to
+    pub fn wireless_link(&self) -> Option<u32> {
+        self.wireless_link
+    }
+
+    /// link without checking either end, the vm's `set_wireless_link` does that
+    pub(crate) fn set_wireless_link(&mut self, target: Option<u32>) {
+        self.wireless_link = target;
+    }
+
+    /// what the device passes between its networks, its own setting or its prefab's
+    pub fn bridge(&self) -> Option<Bridge> {
+        self.bridge.or_else(|| self.prefab_info()?.bridge())
+    }
+
+    /// what the device passes between its networks while it's on
+    pub fn active_bridge(&self) -> Option<Bridge> {
+        let on = self.fields.get(&LogicType::On).map(|field| field.value);
+        self.bridge().filter(|_| on != Some(0.0))
+    }
+
+    /// connected to a power network, one that is supplied and not overloaded when power is
+    /// simulated
+    pub fn is_powered(&self, vm: &VM) -> bool {
+        self.power_network()
+            .and_then(|net| vm.networks.get(&net))
+            .is_some_and(|network| !vm.power_simulation() || network.borrow().power.powered())
+    }
 }
 
 #[derive(Debug, Default, Clone, Serialize, Deserialize)]
@@ -908,8 +1135,14 @@ pub struct DeviceTemplate {
     pub name: Option<String>,
     pub prefab_name: Option<String>,
     pub slots: Vec<SlotTemplate>,
-    // pub reagents: BTreeMap<ReagentMode, BTreeMap<i32, f64>>,
+    /// reagent amounts by hash, for `Contents` and `Recipe`
+    #[serde(default)]
+    pub reagents: BTreeMap<ReagentMode, BTreeMap<i32, f64>>,
     pub connections: Vec<Connection>,
+    #[serde(default)]
+    pub wireless_link: Option<u32>,
+    #[serde(default)]
+    pub bridge: Option<Bridge>,
     pub fields: BTreeMap<LogicType, LogicField>,
 }
 
@@ -958,10 +1191,12 @@ impl Device {
             name_hash,
             prefab: template.prefab_name.map(|name| Prefab::new(&name)),
             slots,
-            // reagents: template.reagents,
-            reagents: BTreeMap::new(),
+            reagents: template.reagents,
             ic,
             connections: template.connections,
+            // links are checked and set by the vm once the devices they point at exist
+            wireless_link: None,
+            bridge: template.bridge,
             fields,
         }
     }
@@ -987,7 +1222,10 @@ where
                     }),
                 })
                 .collect_vec(),
+            reagents: device.reagents.clone(),
             connections: device.connections.clone(),
+            wireless_link: device.wireless_link,
+            bridge: device.bridge,
             fields: device.fields.clone(),
         }
     }
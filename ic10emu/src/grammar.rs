@@ -589,6 +589,23 @@ impl Operand {
         }
     }
 
+    /// a reagent hash, reagent names like `Iron` resolve from the database
+    pub fn as_reagent(
+        &self,
+        ic: &interpreter::IC,
+        inst: InstructionOp,
+        index: u32,
+    ) -> Result<f64, ICError> {
+        if let Operand::Identifier(id) | Operand::Type { identifier: id, .. } =
+            self.translate_alias(ic)
+        {
+            if let Some(reagent) = crate::prefabs::reagent(&id.name) {
+                return Ok(reagent.hash as f64);
+            }
+        }
+        self.as_value(ic, inst, index)
+    }
+
     pub fn translate_alias(&self, ic: &interpreter::IC) -> Self {
         match &self {
             Operand::Identifier(id) | Operand::Type { identifier: id, .. } => {
@@ -879,6 +896,7 @@ impl FromStr for Operand {
                     Ok(Operand::Number(Number::Constant(*val)))
                 } else if let Ok(val) = LogicEnums::from_str(s) {
                     Ok(Operand::Number(Number::Enum(
+                        s.to_owned(),
                         val.get_str("value").unwrap().parse().unwrap(),
                     )))
                 } else {
@@ -970,10 +988,7 @@ impl Display for Operand {
                     write!(f, r#"HASH("{s}")"#)
                 }
 
-                Number::Enum(_) => {
-                    // TODO: handle better
-                    Display::fmt(&number.value(), f)
-                }
+                Number::Enum(name, _) => Display::fmt(name, f),
             },
             Operand::Type { identifier, .. } => Display::fmt(&identifier, f),
             Operand::Identifier(ident) => Display::fmt(&ident, f),
@@ -1067,13 +1082,14 @@ pub enum Number {
     Hexadecimal(i64),
     Constant(f64),
     String(String),
-    Enum(f64),
+    /// the enum's name as written and its value
+    Enum(String, f64),
 }
 
 impl Number {
     pub fn value(&self) -> f64 {
         match self {
-            Number::Enum(val) | Number::Float(val) | Number::Constant(val) => *val,
+            Number::Enum(_, val) | Number::Float(val) | Number::Constant(val) => *val,
 
             Number::Binary(val) | Number::Hexadecimal(val) => *val as f64,
             Number::String(s) => const_crc32::crc32(s.as_bytes()) as i32 as f64,
@@ -1081,7 +1097,9 @@ impl Number {
     }
     pub fn value_i64(&self, signed: bool) -> i64 {
         match self {
-            Number::Enum(val) | Number::Float(val) | Number::Constant(val) => interpreter::f64_to_i64(*val, signed),
+            Number::Enum(_, val) | Number::Float(val) | Number::Constant(val) => {
+                interpreter::f64_to_i64(*val, signed)
+            }
             Number::Binary(val) | Number::Hexadecimal(val) => *val,
             Number::String(s) => const_crc32::crc32(s.as_bytes()) as i32 as i64,
         }
@@ -1284,7 +1302,7 @@ mod tests {
                                 indirection: 0,
                                 target: 2,
                             }),
-                            Operand::Number(Number::Enum(6.0)),
+                            Operand::Number(Number::Enum("LogicType.Temperature".to_owned(), 6.0)),
                         ],
                     },),),
                     comment: None,
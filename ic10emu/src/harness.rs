@@ -0,0 +1,595 @@
+use std::{
+    fmt::Display,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{
+    breakpoint::Comparison,
+    grammar::LogicType,
+    interpreter::ICState,
+    scenario::{Scenario, ScenarioError},
+    trace::TraceEntry,
+    vm::{VMError, VM},
+};
+
+#[derive(Debug, Error)]
+pub enum HarnessError {
+    #[error("failed to read {0}")]
+    Io(PathBuf, #[source] std::io::Error),
+    #[error("invalid test file: {0}")]
+    Parse(#[from] toml::de::Error),
+    #[error("invalid check '{0}': {1}")]
+    InvalidCheck(String, String),
+    #[error("assertion refers to unknown device '{0}'")]
+    UnknownDevice(String),
+    #[error("'after = 0' is never checked, assertions are checked after each tick")]
+    AfterZero,
+    #[error(transparent)]
+    Scenario(#[from] ScenarioError),
+    #[error(transparent)]
+    VM(#[from] VMError),
+}
+
+/// a value read from a named device
+#[derive(Debug, Clone, PartialEq)]
+pub enum Probe {
+    Field { device: String, field: LogicType },
+    Register { device: String, register: usize },
+}
+
+impl FromStr for Probe {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (device, name) = s
+            .trim()
+            .rsplit_once('.')
+            .ok_or_else(|| format!("expected Device.Field or Device.register, got '{s}'"))?;
+        let device = device.to_owned();
+        let register = match name {
+            "sp" => Some(16),
+            "ra" => Some(17),
+            _ => name
+                .strip_prefix('r')
+                .and_then(|index| index.parse::<usize>().ok())
+                .filter(|index| *index < 16),
+        };
+        match register {
+            Some(register) => Ok(Probe::Register { device, register }),
+            None => Ok(Probe::Field {
+                device,
+                field: name
+                    .parse()
+                    .map_err(|_| format!("unknown logic type '{name}'"))?,
+            }),
+        }
+    }
+}
+
+impl Display for Probe {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Probe::Field { device, field } => write!(f, "{device}.{field}"),
+            Probe::Register {
+                device,
+                register: 16,
+            } => write!(f, "{device}.sp"),
+            Probe::Register {
+                device,
+                register: 17,
+            } => write!(f, "{device}.ra"),
+            Probe::Register { device, register } => write!(f, "{device}.r{register}"),
+        }
+    }
+}
+
+impl Probe {
+    pub fn device(&self) -> &str {
+        match self {
+            Probe::Field { device, .. } | Probe::Register { device, .. } => device,
+        }
+    }
+
+    pub fn value(&self, vm: &VM) -> Result<f64, String> {
+        let device = find_device(vm, self.device())
+            .ok_or_else(|| format!("no device named '{}'", self.device()))?;
+        match self {
+            Probe::Field { field, .. } => vm
+                .get_device(device)
+                .expect("device found by name")
+                .borrow()
+                .get_field(*field, vm)
+                .map_err(|err| err.to_string()),
+            Probe::Register { register, .. } => {
+                let ic = vm
+                    .get_ic_for_device(device)
+                    .map_err(|err| err.to_string())?;
+                let ic = ic.borrow();
+                let val = ic.registers.borrow()[*register];
+                Ok(val)
+            }
+        }
+    }
+}
+
+/// a comparison of a device value against a number, `Pump.On == 1`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Check {
+    pub probe: Probe,
+    pub cmp: Comparison,
+    pub value: f64,
+}
+
+impl FromStr for Check {
+    type Err = HarnessError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = |msg: String| HarnessError::InvalidCheck(s.to_owned(), msg);
+        let (lhs, cmp, rhs) = Comparison::split(s)
+            .ok_or_else(|| invalid("missing comparison operator".to_owned()))?;
+        Ok(Check {
+            probe: lhs.parse().map_err(invalid)?,
+            cmp,
+            value: rhs
+                .trim()
+                .parse()
+                .map_err(|_| invalid(format!("expected a number, got '{}'", rhs.trim())))?,
+        })
+    }
+}
+
+impl TryFrom<String> for Check {
+    type Error = HarnessError;
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<Check> for String {
+    fn from(value: Check) -> Self {
+        value.to_string()
+    }
+}
+
+impl Display for Check {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {} {}", self.probe, self.cmp.operator(), self.value)
+    }
+}
+
+impl Check {
+    /// `Ok(val)` if the check holds, `Err` describing the value if it doesn't
+    pub fn evaluate(&self, vm: &VM) -> Result<f64, String> {
+        let val = self.probe.value(vm)?;
+        if self.cmp.apply(val, self.value) {
+            Ok(val)
+        } else {
+            Err(format!("{} was {val}", self.probe))
+        }
+    }
+}
+
+/// which ics a `no_errors` assertion covers
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ErrorScope {
+    /// every ic, or none if false
+    All(bool),
+    Device(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged, deny_unknown_fields)]
+pub enum Assertion {
+    /// `check` holds once the test has run `after` ticks, counted from the scenario's start
+    After { after: u64, check: Check },
+    /// `always` holds after every tick
+    Always { always: Check },
+    /// `never` holds after any tick
+    Never { never: Check },
+    /// no ic ends a tick in an error state
+    NoErrors { no_errors: ErrorScope },
+}
+
+impl Display for Assertion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Assertion::After { after, check } => write!(f, "after {after} ticks {check}"),
+            Assertion::Always { always } => write!(f, "always {always}"),
+            Assertion::Never { never } => write!(f, "never {never}"),
+            Assertion::NoErrors {
+                no_errors: ErrorScope::All(_),
+            } => write!(f, "no ic errors"),
+            Assertion::NoErrors {
+                no_errors: ErrorScope::Device(device),
+            } => write!(f, "no errors in {device}"),
+        }
+    }
+}
+
+impl Assertion {
+    fn probes(&self) -> Vec<&str> {
+        match self {
+            Assertion::After { check, .. }
+            | Assertion::Always { always: check }
+            | Assertion::Never { never: check } => vec![check.probe.device()],
+            Assertion::NoErrors {
+                no_errors: ErrorScope::Device(device),
+            } => vec![device],
+            Assertion::NoErrors { .. } => vec![],
+        }
+    }
+
+    /// check the assertion once the test has run `ticks` ticks, returning why it failed
+    pub fn evaluate(&self, vm: &VM, ticks: u64) -> Option<String> {
+        match self {
+            Assertion::After { after, check } if ticks == *after => check.evaluate(vm).err(),
+            Assertion::After { .. } => None,
+            Assertion::Always { always } => always.evaluate(vm).err(),
+            Assertion::Never { never } => never
+                .evaluate(vm)
+                .ok()
+                .map(|val| format!("{} was {val}", never.probe)),
+            Assertion::NoErrors { no_errors } => {
+                let only = match no_errors {
+                    ErrorScope::All(false) => return None,
+                    ErrorScope::All(true) => None,
+                    ErrorScope::Device(device) => find_device(vm, device),
+                };
+                vm.ics.values().find_map(|ic| {
+                    let ic = ic.borrow();
+                    if only.is_some_and(|device| device != ic.device) {
+                        return None;
+                    }
+                    let state = ic.state.borrow();
+                    matches!(*state, ICState::Error(_) | ICState::HasCaughtFire)
+                        .then(|| format!("{} errored: {state}", device_name(vm, ic.device)))
+                })
+            }
+        }
+    }
+}
+
+/// where a test's scenario comes from
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ScenarioSource {
+    /// a scenario file, relative to the test file
+    Path(PathBuf),
+    Inline(Scenario),
+}
+
+/// a scenario and the assertions to check while running it
+///
+/// ```toml
+/// scenario = "bench.toml"
+/// ticks = 40
+///
+/// [[assert]]
+/// after = 20
+/// check = "Pump.On == 1"
+///
+/// [[assert]]
+/// always = "Controller.r3 <= 100"
+///
+/// [[assert]]
+/// no_errors = true
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TestFile {
+    #[serde(default)]
+    pub name: Option<String>,
+    pub scenario: ScenarioSource,
+    /// ticks to run for, extended to cover every `after` assertion
+    #[serde(default)]
+    pub ticks: u64,
+    #[serde(default, rename = "assert")]
+    pub assertions: Vec<Assertion>,
+}
+
+impl TestFile {
+    pub fn from_toml(source: &str) -> Result<Self, HarnessError> {
+        let test: TestFile = toml::from_str(source)?;
+        if test
+            .assertions
+            .iter()
+            .any(|assertion| matches!(assertion, Assertion::After { after: 0, .. }))
+        {
+            return Err(HarnessError::AfterZero);
+        }
+        Ok(test)
+    }
+
+    /// read a test file, loading its scenario and the files it uses relative to it
+    pub fn load(path: &Path) -> Result<Self, HarnessError> {
+        let source =
+            std::fs::read_to_string(path).map_err(|err| HarnessError::Io(path.to_owned(), err))?;
+        let mut test = TestFile::from_toml(&source)?;
+        let base = path.parent().unwrap_or(Path::new(""));
+        test.scenario = ScenarioSource::Inline(match test.scenario {
+            ScenarioSource::Path(scenario) => Scenario::load(&base.join(scenario))?,
+            ScenarioSource::Inline(mut scenario) => {
+                scenario.resolve_files(base)?;
+                scenario
+            }
+        });
+        if test.name.is_none() {
+            test.name = Some(path.display().to_string());
+        }
+        Ok(test)
+    }
+
+    pub fn run(&self) -> Result<TestReport, HarnessError> {
+        let scenario = match &self.scenario {
+            ScenarioSource::Path(path) => Scenario::load(path)?,
+            ScenarioSource::Inline(scenario) => scenario.clone(),
+        };
+        let vm = scenario.build()?;
+        let start = vm.clock();
+        for assertion in &self.assertions {
+            if let Some(device) = assertion
+                .probes()
+                .into_iter()
+                .find(|device| find_device(&vm, device).is_none())
+            {
+                return Err(HarnessError::UnknownDevice(device.to_owned()));
+            }
+        }
+
+        let ticks = self
+            .assertions
+            .iter()
+            .filter_map(|assertion| match assertion {
+                Assertion::After { after, .. } => Some(*after),
+                _ => None,
+            })
+            .fold(self.ticks, u64::max);
+        let housings = vm
+            .ics
+            .values()
+            .map(|ic| ic.borrow().device)
+            .collect::<Vec<_>>();
+
+        let mut failures = vec![None; self.assertions.len()];
+        for _ in 0..ticks {
+            for id in &housings {
+                vm.start_trace(*id)?;
+            }
+            let tick = vm.clock();
+            vm.tick()?;
+            let mut trace = Vec::new();
+            for id in &housings {
+                if let Some(ic_trace) = vm.stop_trace(*id)? {
+                    trace.extend(traced_lines(&vm, *id, ic_trace.entries));
+                }
+            }
+            for (assertion, failure) in self.assertions.iter().zip(failures.iter_mut()) {
+                if failure.is_some() {
+                    continue;
+                }
+                if let Some(message) = assertion.evaluate(&vm, vm.clock() - start) {
+                    *failure = Some(Failure {
+                        tick,
+                        message,
+                        trace: trace.clone(),
+                    });
+                }
+            }
+            if failures.iter().all(Option::is_some) {
+                break;
+            }
+        }
+
+        Ok(TestReport {
+            name: self.name.clone(),
+            ticks: vm.clock() - start,
+            results: self
+                .assertions
+                .iter()
+                .zip(failures)
+                .map(|(assertion, failure)| AssertionResult {
+                    assertion: assertion.to_string(),
+                    failure,
+                })
+                .collect(),
+        })
+    }
+}
+
+/// a line run during a failing tick
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TracedLine {
+    /// name of the ic's housing
+    pub device: String,
+    pub source: String,
+    #[serde(flatten)]
+    pub entry: TraceEntry,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Failure {
+    /// the tick the assertion first failed on
+    pub tick: u64,
+    pub message: String,
+    /// every line the ics ran during that tick
+    pub trace: Vec<TracedLine>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AssertionResult {
+    pub assertion: String,
+    pub failure: Option<Failure>,
+}
+
+impl AssertionResult {
+    pub fn passed(&self) -> bool {
+        self.failure.is_none()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TestReport {
+    pub name: Option<String>,
+    /// ticks the VM ran for
+    pub ticks: u64,
+    pub results: Vec<AssertionResult>,
+}
+
+impl TestReport {
+    pub fn passed(&self) -> bool {
+        self.results.iter().all(AssertionResult::passed)
+    }
+}
+
+impl Display for TestReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let failed = self.results.iter().filter(|res| !res.passed()).count();
+        writeln!(
+            f,
+            "test {}: {} passed, {failed} failed after {} ticks",
+            self.name.as_deref().unwrap_or("<unnamed>"),
+            self.results.len() - failed,
+            self.ticks
+        )?;
+        for result in &self.results {
+            let Some(failure) = &result.failure else {
+                writeln!(f, "  ok    {}", result.assertion)?;
+                continue;
+            };
+            writeln!(f, "  FAIL  {}", result.assertion)?;
+            writeln!(f, "        on tick {}: {}", failure.tick, failure.message)?;
+            for line in &failure.trace {
+                write!(
+                    f,
+                    "          {} {}: {}",
+                    line.device, line.entry.line, line.source
+                )?;
+                if let Some(error) = &line.entry.error {
+                    write!(f, "  ({error})")?;
+                }
+                writeln!(f)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn find_device(vm: &VM, name: &str) -> Option<u32> {
+    vm.devices
+        .values()
+        .find(|device| device.borrow().name.as_deref() == Some(name))
+        .map(|device| device.borrow().id)
+}
+
+fn device_name(vm: &VM, id: u32) -> String {
+    vm.get_device(id)
+        .and_then(|device| device.borrow().name.clone())
+        .unwrap_or_else(|| id.to_string())
+}
+
+fn traced_lines(vm: &VM, device: u32, entries: Vec<TraceEntry>) -> Vec<TracedLine> {
+    let name = device_name(vm, device);
+    let code = vm
+        .get_ic_for_device(device)
+        .map(|ic| ic.borrow().code.borrow().clone())
+        .unwrap_or_default();
+    let lines = code.lines().collect::<Vec<_>>();
+    entries
+        .into_iter()
+        .map(|entry| TracedLine {
+            device: name.clone(),
+            source: lines
+                .get(entry.line as usize)
+                .map_or_else(String::new, |line| line.trim().to_owned()),
+            entry,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn run_assertions() -> Result<(), HarnessError> {
+        let test = TestFile::from_toml(
+            r#"
+name = "heater"
+ticks = 10
+
+[scenario]
+devices = [
+    { name = "Sensor", fields = { Temperature = 280.0 } },
+    { name = "Heater", fields = { On = 0 } },
+    { name = "Controller", pins = { d0 = "Sensor", d1 = "Heater" }, code = """
+start:
+l r0 d0 Temperature
+slt r1 r0 290
+s d1 On r1
+add r3 r3 1
+yield
+j start
+""" },
+]
+
+[[assert]]
+after = 2
+check = "Heater.On == 1"
+
+[[assert]]
+always = "Controller.r3 <= 5"
+
+[[assert]]
+never = "Sensor.Temperature > 300"
+
+[[assert]]
+no_errors = "Controller"
+"#,
+        )?;
+        let report = test.run()?;
+        assert!(!report.passed());
+        assert_eq!(report.ticks, 10);
+        let results = report
+            .results
+            .iter()
+            .map(|res| (res.assertion.as_str(), res.passed()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            results,
+            vec![
+                ("after 2 ticks Heater.On == 1", true),
+                ("always Controller.r3 <= 5", false),
+                ("never Sensor.Temperature > 300", true),
+                ("no errors in Controller", true),
+            ]
+        );
+        let failure = report.results[1].failure.as_ref().unwrap();
+        assert_eq!(failure.tick, 5);
+        assert_eq!(failure.message, "Controller.r3 was 6");
+        assert!(failure
+            .trace
+            .iter()
+            .any(|line| line.source == "add r3 r3 1"));
+
+        assert!(matches!(
+            TestFile::from_toml(
+                r#"
+[scenario]
+devices = [{ name = "Heater" }]
+
+[[assert]]
+after = 0
+check = "Heater.On == 1"
+"#
+            ),
+            Err(HarnessError::AfterZero)
+        ));
+        assert!("Pump.On = 1".parse::<Check>().is_err());
+        assert!("Pump.Bogus == 1".parse::<Check>().is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,125 @@
+use std::collections::{BTreeMap, VecDeque};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    grammar::Operand,
+    interpreter::ICState,
+    rand_mscorlib::Random,
+    watch::{Access, AccessKind},
+};
+
+/// everything needed to undo one executed line
+#[derive(Debug, Clone)]
+pub struct StepDelta {
+    /// id of the ic's housing
+    pub device: u32,
+    pub ic: u32,
+    /// game tick the step ran on
+    pub clock: u64,
+    /// instruction pointer before the step, the line that was executed
+    pub ip: u32,
+    pub ic_count: u16,
+    pub state: ICState,
+    /// writes made by the step, in the order they happened, including those into other
+    /// ics' stacks
+    pub writes: Vec<Access>,
+    /// only saved for lines that can change them
+    pub aliases: Option<BTreeMap<String, Operand>>,
+    pub defines: Option<BTreeMap<String, f64>>,
+    pub random: Option<Random>,
+}
+
+impl StepDelta {
+    /// keep only the writes out of the accesses made by the step
+    pub fn set_writes(&mut self, accesses: Vec<Access>) {
+        self.writes = accesses
+            .into_iter()
+            .filter(|access| access.kind == AccessKind::Write)
+            .collect();
+    }
+}
+
+/// a step in the history, oldest first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HistoryStep {
+    pub device: u32,
+    pub ic: u32,
+    pub clock: u64,
+    pub line: u32,
+}
+
+/// the last `capacity` steps executed by the VM, a capacity of 0 turns recording off
+#[derive(Debug, Default)]
+pub struct History {
+    capacity: usize,
+    steps: VecDeque<StepDelta>,
+}
+
+impl History {
+    pub fn is_enabled(&self) -> bool {
+        self.capacity > 0
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// drops the oldest steps if the history no longer fits
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        while self.steps.len() > capacity {
+            self.steps.pop_front();
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.steps.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.steps.is_empty()
+    }
+
+    pub fn push(&mut self, delta: StepDelta) {
+        if !self.is_enabled() {
+            return;
+        }
+        if self.steps.len() >= self.capacity {
+            self.steps.pop_front();
+        }
+        self.steps.push_back(delta);
+    }
+
+    pub fn pop(&mut self) -> Option<StepDelta> {
+        self.steps.pop_back()
+    }
+
+    pub fn clear(&mut self) {
+        self.steps.clear();
+    }
+
+    /// move the recorded steps of, and writes to, device `old` to `new`
+    pub fn change_device(&mut self, old: u32, new: u32) {
+        for delta in &mut self.steps {
+            if delta.device == old {
+                delta.device = new;
+            }
+            for write in &mut delta.writes {
+                write.target.change_device(old, new);
+            }
+        }
+    }
+
+    pub fn steps(&self) -> Vec<HistoryStep> {
+        self.steps
+            .iter()
+            .map(|delta| HistoryStep {
+                device: delta.device,
+                ic: delta.ic,
+                clock: delta.clock,
+                line: delta.ip,
+            })
+            .collect()
+    }
+}
@@ -14,12 +14,14 @@ use std::{
 
 use itertools::Itertools;
 
-use time::format_description;
-
 use crate::{
+    breakpoint::{BreakpointHit, Breakpoints},
     device::SlotType,
     grammar::{self, LogicType, ParseError, SlotLogicType},
-    vm::VM,
+    profiler::Profile,
+    trace::Trace,
+    vm::{TICKS_PER_SECOND, VM},
+    watch::{Access, AccessLog, WatchTarget, WatchpointHit},
 };
 
 use serde_with::serde_as;
@@ -159,9 +161,42 @@ pub enum ICState {
     Start,
     Running,
     Yield,
-    Sleep(time::OffsetDateTime, f64),
+    /// game tick the ic went to sleep on and the number of seconds to sleep for
+    Sleep(
+        #[serde(deserialize_with = "deserialize_sleep_start")] u64,
+        f64,
+    ),
     HasCaughtFire,
     Error(LineError),
+    /// stopped by a breakpoint before executing the hit line
+    Breakpoint(BreakpointHit),
+    /// stopped by a watchpoint after executing the line that triggered it
+    Watchpoint(WatchpointHit),
+}
+
+/// the game tick an ic that went to sleep on tick `then` for `sleep_for` seconds wakes on
+pub fn sleep_until(then: u64, sleep_for: f64) -> u64 {
+    then.saturating_add((sleep_for.max(0.0) * TICKS_PER_SECOND).ceil() as u64)
+}
+
+/// start tick of a sleep saved with the wall clock time it started, before sleeps went by the
+/// game clock, the vm starts these over from its clock when it's restored
+pub(crate) const WALL_CLOCK_SLEEP: u64 = u64::MAX;
+
+fn deserialize_sleep_start<'de, D>(deserializer: D) -> Result<u64, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum SleepStart {
+        Tick(u64),
+        WallClock(serde::de::IgnoredAny),
+    }
+    Ok(match SleepStart::deserialize(deserializer)? {
+        SleepStart::Tick(tick) => tick,
+        SleepStart::WallClock(_) => WALL_CLOCK_SLEEP,
+    })
 }
 
 impl Display for ICState {
@@ -171,15 +206,25 @@ impl Display for ICState {
             ICState::Running => "Running".to_owned(),
             ICState::Yield => "Ic has yielded, Resume on next tick".to_owned(),
             ICState::Sleep(then, sleep_for) => {
-                let format = format_description::parse("[hour]:[minute]:[second]").unwrap();
-                let resume = *then + time::Duration::new(*sleep_for as i64, 0);
                 format!(
-                    "Sleeping for {sleep_for} seconds, will resume at {}",
-                    resume.format(&format).unwrap()
+                    "Sleeping for {sleep_for} seconds, will resume at tick {}",
+                    sleep_until(*then, *sleep_for)
                 )
             }
             ICState::Error(err) => format!("{err}"),
             ICState::HasCaughtFire => "IC has caught fire! this is not a joke!".to_owned(),
+            ICState::Breakpoint(hit) => {
+                format!(
+                    "Stopped at breakpoint {} on line {}",
+                    hit.breakpoint, hit.line
+                )
+            }
+            ICState::Watchpoint(hit) => {
+                format!(
+                    "Stopped at watchpoint {} after line {}",
+                    hit.watchpoint, hit.line
+                )
+            }
         };
         write!(f, "{out}")
     }
@@ -201,6 +246,13 @@ pub struct IC {
     pub code: RefCell<String>,
     pub program: RefCell<Program>,
     pub state: RefCell<ICState>,
+    pub breakpoints: RefCell<Breakpoints>,
+    /// register and stack accesses, only recorded while watchpoints are set
+    pub accesses: RefCell<AccessLog>,
+    /// executed lines, only recorded while tracing
+    pub trace: RefCell<Option<Trace>>,
+    /// line and budget usage, only recorded while profiling
+    pub profile: RefCell<Option<Profile>>,
 }
 
 #[serde_as]
@@ -258,6 +310,10 @@ impl From<FrozenIC> for IC {
             state: RefCell::new(value.state),
             code: RefCell::new(value.code.clone()),
             program: RefCell::new(Program::from_code_with_invalid(&value.code)),
+            breakpoints: RefCell::new(Breakpoints::default()),
+            accesses: RefCell::new(AccessLog::default()),
+            trace: RefCell::new(None),
+            profile: RefCell::new(None),
         }
     }
 }
@@ -388,6 +444,10 @@ impl IC {
             aliases: RefCell::new(BTreeMap::new()),
             defines: RefCell::new(BTreeMap::new()),
             state: RefCell::new(ICState::Start),
+            breakpoints: RefCell::new(Breakpoints::default()),
+            accesses: RefCell::new(AccessLog::default()),
+            trace: RefCell::new(None),
+            profile: RefCell::new(None),
         }
     }
 
@@ -430,13 +490,38 @@ impl IC {
         Ok(t)
     }
 
+    fn record_register(&self, index: usize, old: Option<f64>, value: f64) {
+        let target = WatchTarget::Register {
+            device: self.device,
+            index: index as u32,
+        };
+        self.accesses.borrow_mut().record(match old {
+            Some(old) => Access::write(target, old, value),
+            None => Access::read(target, value),
+        });
+    }
+
+    fn record_stack(&self, address: usize, old: Option<f64>, value: f64) {
+        let target = WatchTarget::Stack {
+            device: self.device,
+            address: address as u32,
+        };
+        self.accesses.borrow_mut().record(match old {
+            Some(old) => Access::write(target, old, value),
+            None => Access::read(target, value),
+        });
+    }
+
     pub fn get_register(&self, indirection: u32, target: u32) -> Result<f64, ICError> {
         let t = self.get_real_target(indirection, target)?;
-        self.registers
+        let val = self
+            .registers
             .borrow()
             .get(t as usize)
             .ok_or(ICError::RegisterIndexOutOfRange(t))
-            .copied()
+            .copied()?;
+        self.record_register(t as usize, None, val);
+        Ok(val)
     }
 
     /// sets a register thorough, recursing through provided indirection, returns value previously
@@ -448,12 +533,15 @@ impl IC {
             .ok_or(ICError::RegisterIndexOutOfRange(t))
             .copied()?;
         registers[t as usize] = val;
+        self.record_register(t as usize, Some(old_val), val);
         Ok(old_val)
     }
 
     /// save ip to 'ra' or register 18
     fn al(&self) {
-        self.registers.borrow_mut()[17] = self.ip() as f64 + 1.0;
+        let val = self.ip() as f64 + 1.0;
+        let old = std::mem::replace(&mut self.registers.borrow_mut()[17], val);
+        self.record_register(17, Some(old), val);
     }
 
     pub fn push(&self, val: f64) -> Result<f64, ICError> {
@@ -468,6 +556,8 @@ impl IC {
             let last = stack[sp as usize];
             stack[sp as usize] = val;
             registers[16] += 1.0;
+            self.record_stack(sp as usize, Some(last), val);
+            self.record_register(16, Some(registers[16] - 1.0), registers[16]);
             Ok(last)
         }
     }
@@ -475,6 +565,7 @@ impl IC {
     pub fn pop(&self) -> Result<f64, ICError> {
         let mut registers = self.registers.borrow_mut();
         registers[16] -= 1.0;
+        self.record_register(16, Some(registers[16] + 1.0), registers[16]);
         let sp = (registers[16].round()) as i32;
         if sp < 0 {
             Err(ICError::StackUnderflow)
@@ -482,6 +573,7 @@ impl IC {
             Err(ICError::StackOverflow)
         } else {
             let last = self.stack.borrow()[sp as usize];
+            self.record_stack(sp as usize, None, last);
             Ok(last)
         }
     }
@@ -494,10 +586,26 @@ impl IC {
             let mut stack = self.stack.borrow_mut();
             let last = stack[sp as usize];
             stack[sp as usize] = val;
+            self.record_stack(sp as usize, Some(last), val);
             Ok(last)
         }
     }
 
+    /// `poke` made by another ic, the write is recorded by the vm as this ic isn't the one
+    /// stepping
+    pub fn poke_from(&self, vm: &VM, address: f64, val: f64) -> Result<f64, ICError> {
+        let last = self.poke(address, val)?;
+        vm.record_access(Access::write(
+            WatchTarget::Stack {
+                device: self.device,
+                address: address.round() as u32,
+            },
+            last,
+            val,
+        ));
+        Ok(last)
+    }
+
     pub fn peek(&self) -> Result<f64, ICError> {
         let sp = (self.registers.borrow()[16] - 1.0).round() as i32;
         if sp < 0 {
@@ -506,6 +614,7 @@ impl IC {
             Err(ICError::StackOverflow)
         } else {
             let last = self.stack.borrow()[sp as usize];
+            self.record_stack(sp as usize, None, last);
             Ok(last)
         }
     }
@@ -518,6 +627,7 @@ impl IC {
             Err(ICError::StackOverflow)
         } else {
             let last = self.stack.borrow()[sp as usize];
+            self.record_stack(sp as usize, None, last);
             Ok(last)
         }
     }
@@ -538,7 +648,6 @@ impl IC {
 
     /// processes one line of the contained program
     pub fn step(&self, vm: &VM, advance_ip_on_err: bool) -> Result<bool, LineError> {
-        // TODO: handle sleep
         self.state.replace(ICState::Running);
         let line = self.ip();
         let result = self.internal_step(vm, advance_ip_on_err);
@@ -559,6 +668,63 @@ impl IC {
         self.ip.replace(val);
     }
 
+    /// true if the instruction pointer has run off the end of the program
+    pub fn is_finished(&self) -> bool {
+        self.ip() as usize >= self.program.borrow().instructions.len()
+    }
+
+    /// true if the ic has executed `hcf` and needs a reset before it will run again
+    pub fn has_caught_fire(&self) -> bool {
+        matches!(*self.state.borrow(), ICState::HasCaughtFire)
+    }
+
+    /// the breakpoint, if any, that stops this ic before it executes its next line
+    ///
+    /// an ic resuming from a breakpoint doesn't stop on that same line again
+    pub fn check_breakpoints(&self, vm: &VM, resuming: bool) -> Option<BreakpointHit> {
+        let line = self.ip();
+        if resuming {
+            if let ICState::Breakpoint(hit) = &*self.state.borrow() {
+                if hit.line == line {
+                    return None;
+                }
+            }
+        }
+        let breakpoints = self.breakpoints.borrow();
+        if breakpoints.is_empty() {
+            return None;
+        }
+        breakpoints
+            .check(self, vm, line)
+            .map(|breakpoint| BreakpointHit {
+                device: self.device,
+                ic: self.id,
+                breakpoint,
+                line,
+            })
+    }
+
+    /// start a sleep restored from a wall clock time over from game tick `clock`
+    pub(crate) fn restart_wall_clock_sleep(&self, clock: u64) {
+        if let ICState::Sleep(then, _) = &mut *self.state.borrow_mut() {
+            if *then == WALL_CLOCK_SLEEP {
+                *then = clock;
+            }
+        }
+    }
+
+    /// whether the tick scheduler should run this ic on game tick `clock`
+    pub fn should_run_tick(&self, clock: u64) -> bool {
+        if self.is_finished() {
+            return false;
+        }
+        match &*self.state.borrow() {
+            ICState::Error(_) | ICState::HasCaughtFire => false,
+            ICState::Sleep(then, sleep_for) => clock >= sleep_until(*then, *sleep_for),
+            _ => true,
+        }
+    }
+
     fn internal_step(&self, vm: &VM, advance_ip_on_err: bool) -> Result<(), ICError> {
         use grammar::*;
         use ICError::*;
@@ -576,19 +742,21 @@ impl IC {
             };
             let operands = &line.operands;
             let inst = line.instruction;
+            // no wildcard arm: every generated `InstructionOp` must be handled here
             match inst {
                 Nop => Ok(()),
-                Hcf => Ok(()), // TODO
+                Hcf => {
+                    this.state.replace(ICState::HasCaughtFire);
+                    Ok(())
+                }
                 Sleep => match &operands[..] {
                     [a] => {
                         let a = a.as_value(this, inst, 1)?;
-                        let now = time::OffsetDateTime::now_local()
-                            .unwrap_or_else(|_| time::OffsetDateTime::now_utc());
-                        this.state.replace(ICState::Sleep(now, a));
+                        this.state.replace(ICState::Sleep(vm.clock(), a));
                         Ok(())
                     }
                     oprs => Err(ICError::mismatch_operands(oprs.len(), 1)),
-                }, // TODO
+                },
                 Yield => match &operands[..] {
                     [] => {
                         this.state.replace(ICState::Yield);
@@ -1990,7 +2158,11 @@ impl IC {
                         } = reg.as_register(this, inst, 1)?;
                         let a = a.as_value_i64(this, true, inst, 2)?;
                         let b = b.as_value_i32(this, true, inst, 3)?;
-                        this.set_register(indirection, target, i64_to_f64(a << b))?;
+                        this.set_register(
+                            indirection,
+                            target,
+                            i64_to_f64(a.wrapping_shl(b as u32)),
+                        )?;
                         Ok(())
                     }
                     oprs => Err(ICError::mismatch_operands(oprs.len(), 3)),
@@ -2003,7 +2175,11 @@ impl IC {
                         } = reg.as_register(this, inst, 1)?;
                         let a = a.as_value_i64(this, false, inst, 2)?;
                         let b = b.as_value_i32(this, true, inst, 3)?;
-                        this.set_register(indirection, target, i64_to_f64((a as u64 >> b) as i64))?;
+                        this.set_register(
+                            indirection,
+                            target,
+                            i64_to_f64((a as u64).wrapping_shr(b as u32) as i64),
+                        )?;
                         Ok(())
                     }
                     oprs => Err(ICError::mismatch_operands(oprs.len(), 3)),
@@ -2016,7 +2192,11 @@ impl IC {
                         } = reg.as_register(this, inst, 1)?;
                         let a = a.as_value_i64(this, true, inst, 2)?;
                         let b = b.as_value_i32(this, true, inst, 3)?;
-                        this.set_register(indirection, target, i64_to_f64(a >> b))?;
+                        this.set_register(
+                            indirection,
+                            target,
+                            i64_to_f64(a.wrapping_shr(b as u32)),
+                        )?;
                         Ok(())
                     }
                     oprs => Err(ICError::mismatch_operands(oprs.len(), 3)),
@@ -2211,7 +2391,7 @@ impl IC {
                                         this.poke(addr, val)?;
                                     } else {
                                         let ic = vm.ics.get(ic_id).unwrap().borrow();
-                                        ic.poke(addr, val)?;
+                                        ic.poke_from(vm, addr, val)?;
                                     }
                                     vm.set_modified(device_id);
                                     Ok(())
@@ -2239,7 +2419,7 @@ impl IC {
                                         this.poke(addr, val)?;
                                     } else {
                                         let ic = vm.ics.get(ic_id).unwrap().borrow();
-                                        ic.poke(addr, val)?;
+                                        ic.poke_from(vm, addr, val)?;
                                     }
                                     vm.set_modified(device_id as u32);
                                     Ok(())
@@ -2268,7 +2448,7 @@ impl IC {
                                 .map(|device| device.borrow().get_network_id(connection))
                                 .unwrap_or(Err(UnknownDeviceID(device_id as f64)))?;
                             let val = val.as_value(this, inst, 3)?;
-                            vm.set_network_channel(network_id, channel, val)?;
+                            vm.write_network_channel(network_id, channel, val, Some(this.id))?;
                             return Ok(());
                         }
                         let device = vm.get_device_same_network(this.device, device_id);
@@ -2481,7 +2661,7 @@ impl IC {
                         match device {
                             Some(device) => {
                                 let rm = rm.as_reagent_mode(this, inst, 3)?;
-                                let name = name.as_value(this, inst, 4)?;
+                                let name = name.as_reagent(this, inst, 4)?;
                                 let val = device.borrow().get_reagent(&rm, name);
                                 this.set_register(indirection, target, val)?;
                                 Ok(())
@@ -2627,6 +2807,7 @@ pub fn i64_to_f64(i: i64) -> f64 {
 #[cfg(test)]
 mod tests {
     use crate::vm::VMError;
+    use strum::IntoEnumIterator;
 
     use super::*;
 
@@ -2658,6 +2839,98 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn prefab_field_access() -> Result<(), VMError> {
+        let mut vm = VM::new();
+        let ic = vm.add_ic(None).unwrap();
+        let sensor = vm.add_device_by_prefab("StructureGasSensor", None)?;
+        let unknown = vm.add_device(None)?;
+        vm.set_pin(ic, 0, Some(sensor))?;
+        vm.set_pin(ic, 1, Some(unknown))?;
+        vm.get_device(unknown).unwrap().borrow_mut().set_field(
+            LogicType::Setting,
+            0.0,
+            &vm,
+            true,
+        )?;
+        vm.set_code(
+            ic,
+            r#"l r0 d0 Temperature
+            s d0 Temperature 300
+            l r1 d0 On
+            s d1 Setting 1
+            s d1 Setting 2"#,
+        )?;
+        vm.step_ic(ic, true)?;
+        assert!(matches!(
+            vm.step_ic(ic, true),
+            Err(VMError::LineError(LineError {
+                error: ICError::ReadOnlyField(_),
+                ..
+            }))
+        ));
+        assert!(matches!(
+            vm.step_ic(ic, true),
+            Err(VMError::LineError(LineError {
+                error: ICError::DeviceHasNoField(_),
+                ..
+            }))
+        ));
+        vm.step_ic(ic, true)?;
+        vm.set_unknown_prefab_access(crate::vm::UnknownPrefabAccess::Deny);
+        assert!(vm.step_ic(ic, true).is_err());
+        // saved with the rest of the VM
+        let state = vm.save_vm_state();
+        vm.set_unknown_prefab_access(crate::vm::UnknownPrefabAccess::Permissive);
+        vm.restore_vm_state(state)?;
+        assert_eq!(
+            vm.unknown_prefab_access(),
+            crate::vm::UnknownPrefabAccess::Deny
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn reagents() -> Result<(), VMError> {
+        let mut vm = VM::new();
+        let ic = vm.add_ic(None).unwrap();
+        let furnace = vm.add_device(None)?;
+        vm.set_pin(ic, 0, Some(furnace))?;
+        {
+            let device = vm.get_device(furnace).unwrap();
+            let mut device = device.borrow_mut();
+            let iron = crate::prefabs::reagent("Iron").unwrap().hash;
+            let copper = crate::prefabs::reagent("Copper").unwrap().hash;
+            device.set_reagent(grammar::ReagentMode::Contents, iron, 10.0)?;
+            device.set_reagent(grammar::ReagentMode::Contents, copper, 5.0)?;
+            device.set_reagent(grammar::ReagentMode::Recipe, iron, 25.0)?;
+            assert!(device
+                .set_reagent(grammar::ReagentMode::Required, iron, 1.0)
+                .is_err());
+        }
+        let state = vm.save_vm_state();
+        let mut vm = VM::new();
+        vm.restore_vm_state(state)?;
+        let ic_id = vm.get_device(ic).unwrap().borrow().ic.unwrap();
+        let ic_chip = vm.ics.get(&ic_id).unwrap().borrow();
+        vm.set_code(
+            ic,
+            r#"lr r0 d0 Contents Iron
+            lr r1 d0 Recipe HASH("Iron")
+            lr r2 d0 Required Iron
+            lr r3 d0 TotalContents 0
+            lr r4 d0 Contents Gold"#,
+        )?;
+        for _ in 0..5 {
+            vm.step_ic(ic, false)?;
+        }
+        let registers = (0..5)
+            .map(|r| ic_chip.get_register(0, r).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(registers, vec![10.0, 25.0, 15.0, 15.0, 0.0]);
+        Ok(())
+    }
+
     #[test]
     fn stack() -> Result<(), VMError> {
         let mut vm = VM::new();
@@ -2702,4 +2975,52 @@ mod tests {
         assert_eq!(r2, 20.0);
         Ok(())
     }
+
+    #[test]
+    fn atan2_and_shifts() -> Result<(), VMError> {
+        let mut vm = VM::new();
+        let ic = vm.add_ic(None)?;
+        let ic_id = vm.devices[&ic].borrow().ic.unwrap();
+        vm.set_code(
+            ic,
+            "atan2 r0 1 -1\nsll r1 3 2\nsla r2 -3 2\nsrl r3 -1 1\nsra r4 -8 1\n\
+             sll r5 3 64\nsla r6 5 128\nsll r7 1 -12\nsra r8 -8 66\nsrl r9 8 -61",
+        )?;
+        for _ in 0..10 {
+            vm.step_ic(ic, false)?;
+        }
+        let chip = vm.ics[&ic_id].borrow();
+        assert_eq!(chip.get_register(0, 0)?, f64::atan2(1.0, -1.0));
+        assert_eq!(chip.get_register(0, 1)?, 12.0);
+        assert_eq!(chip.get_register(0, 2)?, -12.0);
+        assert_eq!(chip.get_register(0, 3)?, ((1i64 << 53) - 1) as f64);
+        assert_eq!(chip.get_register(0, 4)?, -4.0);
+        // counts of 64 or more, or negative, only keep their low 6 bits like the game's long
+        // shifts instead of overflowing
+        assert_eq!(chip.get_register(0, 5)?, 3.0);
+        assert_eq!(chip.get_register(0, 6)?, 5.0);
+        assert_eq!(chip.get_register(0, 7)?, (1i64 << 52) as f64);
+        assert_eq!(chip.get_register(0, 8)?, -2.0);
+        assert_eq!(chip.get_register(0, 9)?, 1.0);
+        Ok(())
+    }
+
+    #[test]
+    fn all_instructions_parse() {
+        let instructions = include_str!("../data/instructions.txt");
+        for line in instructions.lines() {
+            let name = line.split(' ').next().unwrap();
+            assert!(
+                name.parse::<grammar::InstructionOp>().is_ok(),
+                "instruction '{name}' does not parse"
+            );
+        }
+        // every instruction in the list and nothing else, `Nop` stands in for empty lines
+        assert_eq!(
+            grammar::InstructionOp::iter()
+                .filter(|op| *op != grammar::InstructionOp::Nop)
+                .count(),
+            instructions.lines().count()
+        );
+    }
 }
@@ -1,8 +1,20 @@
+pub mod behavior;
+pub mod breakpoint;
+pub mod device;
 pub mod grammar;
+#[cfg(feature = "scenario")]
+pub mod harness;
+pub mod history;
 pub mod interpreter;
-mod rand_mscorlib;
+pub mod network;
+pub mod power;
+pub mod prefabs;
+pub mod profiler;
+pub mod rand_mscorlib;
+#[cfg(feature = "scenario")]
+pub mod scenario;
+pub mod stimulus;
 pub mod tokens;
-pub mod device;
+pub mod trace;
 pub mod vm;
-pub mod network;
-
+pub mod watch;
@@ -1,4 +1,8 @@
-use std::{collections::HashSet, ops::Deref};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashSet, VecDeque},
+    ops::Deref,
+    rc::Rc,
+};
 
 use serde::{Deserialize, Serialize};
 use strum_macros::{AsRefStr, EnumIter};
@@ -6,6 +10,8 @@ use thiserror::Error;
 
 use itertools::Itertools;
 
+use crate::power::PowerState;
+
 #[derive(Debug, Default, Clone, Copy, Serialize, Deserialize)]
 pub enum CableConnectionType {
     Power,
@@ -86,9 +92,11 @@ impl Connection {
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Network {
     pub id: u32,
-    pub devices: HashSet<u32>,
-    pub power_only: HashSet<u32>,
+    /// members are kept in step with device connections by the VM
+    pub(crate) devices: HashSet<u32>,
+    pub(crate) power_only: HashSet<u32>,
     pub channels: [f64; 8],
+    pub power: PowerState,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -96,7 +104,19 @@ pub struct FrozenNetwork {
     pub id: u32,
     pub devices: Vec<u32>,
     pub power_only: Vec<u32>,
+    #[serde(deserialize_with = "deserialize_channels")]
     pub channels: [f64; 8],
+    #[serde(default)]
+    pub power: PowerState,
+}
+
+/// unset channels are NaN, which json writes out as `null`
+fn deserialize_channels<'de, D>(deserializer: D) -> Result<[f64; 8], D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let channels = <[Option<f64>; 8]>::deserialize(deserializer)?;
+    Ok(channels.map(|chan| chan.unwrap_or(f64::NAN)))
 }
 
 impl<T> From<T> for FrozenNetwork
@@ -109,6 +129,7 @@ where
             devices: value.devices.iter().copied().collect_vec(),
             power_only: value.power_only.iter().copied().collect_vec(),
             channels: value.channels,
+            power: value.power,
         }
     }
 }
@@ -120,6 +141,7 @@ impl From<FrozenNetwork> for Network {
             devices: value.devices.into_iter().collect(),
             power_only: value.power_only.into_iter().collect(),
             channels: value.channels,
+            power: value.power,
         }
     }
 }
@@ -130,17 +152,135 @@ pub enum NetworkError {
     ChannelIndexOutOfRange,
 }
 
-impl Network {
+/// what a device passes between the cable networks it's connected to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Bridge {
+    /// like a transformer, the networks share power but not data
+    Power,
+    /// like a data relay, devices on either network can see each other
+    Data,
+    PowerAndData,
+}
+
+impl Bridge {
+    pub fn passes_power(&self) -> bool {
+        matches!(self, Bridge::Power | Bridge::PowerAndData)
+    }
+
+    pub fn passes_data(&self) -> bool {
+        matches!(self, Bridge::Data | Bridge::PowerAndData)
+    }
+}
+
+/// the groups of networks joined by active bridges, built once from the bridges' connections so
+/// lookups don't have to visit every device
+#[derive(Debug, Default)]
+pub(crate) struct BridgeTopology {
+    data: BTreeMap<u32, Rc<BTreeSet<u32>>>,
+    power: BTreeMap<u32, Rc<BTreeSet<u32>>>,
+}
 
+impl BridgeTopology {
+    /// from the active bridges and the data and power networks each is connected to
+    pub fn new(bridges: impl IntoIterator<Item = (Bridge, BTreeSet<u32>, BTreeSet<u32>)>) -> Self {
+        let mut data = Vec::new();
+        let mut power = Vec::new();
+        for (bridge, data_nets, power_nets) in bridges {
+            if bridge.passes_data() {
+                data.push(data_nets);
+            }
+            if bridge.passes_power() {
+                power.push(power_nets);
+            }
+        }
+        BridgeTopology {
+            data: Self::join(data),
+            power: Self::join(power),
+        }
+    }
+
+    /// merge overlapping sets of networks and key each group by its members
+    fn join(bridged: Vec<BTreeSet<u32>>) -> BTreeMap<u32, Rc<BTreeSet<u32>>> {
+        let mut groups: Vec<BTreeSet<u32>> = Vec::new();
+        for mut nets in bridged {
+            groups.retain(|group| {
+                if group.is_disjoint(&nets) {
+                    true
+                } else {
+                    nets.extend(group);
+                    false
+                }
+            });
+            groups.push(nets);
+        }
+        groups
+            .into_iter()
+            .flat_map(|group| {
+                let group = Rc::new(group);
+                group.iter().map(|net| (*net, group.clone())).collect_vec()
+            })
+            .collect()
+    }
+
+    /// `nets` and the networks joined to them by data bridges, or power bridges
+    pub fn reach(&self, mut nets: BTreeSet<u32>, data: bool) -> BTreeSet<u32> {
+        let groups = if data { &self.data } else { &self.power };
+        for net in nets.clone() {
+            if let Some(group) = groups.get(&net) {
+                nets.extend(group.iter());
+            }
+        }
+        nets
+    }
+}
+
+/// how a device belongs to a network
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum Membership {
+    Data,
+    PowerOnly,
+}
+
+/// a disagreement between device connections and network members, see `VM::check_invariants`
+#[derive(Debug, Clone, PartialEq, Eq, Error, Serialize, Deserialize)]
+pub enum NetworkMismatch {
+    #[error("device {device} is connected to network {network} which doesn't exist")]
+    UnknownNetwork { device: u32, network: u32 },
+    #[error("device {device} is connected to network {network} but isn't a {membership:?} member")]
+    MissingMember {
+        device: u32,
+        network: u32,
+        membership: Membership,
+    },
+    #[error(
+        "network {network} has device {device} as a {membership:?} member without a connection"
+    )]
+    StrayMember {
+        device: u32,
+        network: u32,
+        membership: Membership,
+    },
+}
+
+impl Network {
     pub fn new(id: u32) -> Self {
         Network {
             id,
             devices: HashSet::new(),
             power_only: HashSet::new(),
             channels: [f64::NAN; 8],
+            power: PowerState::default(),
         }
     }
 
+    pub fn devices(&self) -> &HashSet<u32> {
+        &self.devices
+    }
+
+    pub fn power_only(&self) -> &HashSet<u32> {
+        &self.power_only
+    }
+
     pub fn contains(&self, id: &u32) -> bool {
         self.devices.contains(id) || self.power_only.contains(id)
     }
@@ -177,23 +317,26 @@ impl Network {
         }
     }
 
-    pub fn add_data(&mut self, id: u32) -> bool {
+    pub(crate) fn add_data(&mut self, id: u32) -> bool {
         self.devices.insert(id)
     }
 
-    pub fn add_power(&mut self, id: u32) -> bool {
+    pub(crate) fn add_power(&mut self, id: u32) -> bool {
         self.power_only.insert(id)
     }
 
-    pub fn remove_all(&mut self, id: u32) -> bool {
-        self.devices.remove(&id) || self.power_only.remove(&id)
+    pub(crate) fn remove_all(&mut self, id: u32) -> bool {
+        let data = self.remove_data(id);
+        let power = self.remove_power(id);
+        data || power
     }
-    pub fn remove_data(&mut self, id: u32) -> bool {
+
+    pub(crate) fn remove_data(&mut self, id: u32) -> bool {
         self.devices.remove(&id)
     }
 
-    pub fn remove_power(&mut self, id: u32) -> bool {
-        self.devices.remove(&id)
+    pub(crate) fn remove_power(&mut self, id: u32) -> bool {
+        self.power_only.remove(&id)
     }
 
     pub fn set_channel(&mut self, chan: usize, val: f64) -> Result<f64, NetworkError> {
@@ -213,4 +356,101 @@ impl Network {
             Ok(self.channels[chan])
         }
     }
+
+    /// unset every channel, as the game does when a cable network is rebuilt
+    pub fn reset_channels(&mut self) {
+        self.channels = [f64::NAN; 8];
+    }
+}
+
+/// a network channel write seen by the channel monitor
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ChannelWrite {
+    /// game tick of the write
+    pub clock: u64,
+    pub network: u32,
+    pub channel: u32,
+    /// the ic that wrote it, none for writes from outside the vm
+    pub ic: Option<u32>,
+    pub old: f64,
+    pub value: f64,
+}
+
+/// the last `capacity` channel writes, a capacity of 0 logs nothing
+#[derive(Debug, Default, Clone)]
+pub struct ChannelMonitor {
+    capacity: usize,
+    writes: VecDeque<ChannelWrite>,
+}
+
+impl ChannelMonitor {
+    pub fn new(capacity: usize) -> Self {
+        ChannelMonitor {
+            capacity,
+            writes: VecDeque::new(),
+        }
+    }
+
+    pub fn push(&mut self, write: ChannelWrite) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.writes.len() >= self.capacity {
+            self.writes.pop_front();
+        }
+        self.writes.push_back(write);
+    }
+
+    /// the logged writes, oldest first
+    pub fn writes(&self) -> Vec<ChannelWrite> {
+        self.writes.iter().cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn membership() {
+        let mut network = Network::new(1);
+        network.add_data(2);
+        network.add_power(3);
+        assert!(!network.remove_power(2));
+        assert!(network.contains_data(&2));
+        assert!(network.remove_power(3));
+        assert!(!network.contains(&3));
+
+        // a device in both sets leaves both
+        network.add_data(4);
+        network.add_power(4);
+        assert!(network.remove_all(4));
+        assert!(!network.contains(&4));
+    }
+
+    #[test]
+    fn bridge_topology() {
+        // the third bridge joins the groups of the first two
+        let topology = BridgeTopology::new([
+            (Bridge::Data, BTreeSet::from([1, 2]), BTreeSet::new()),
+            (
+                Bridge::PowerAndData,
+                BTreeSet::from([3, 4]),
+                BTreeSet::from([3, 5]),
+            ),
+            (Bridge::Data, BTreeSet::from([2, 3]), BTreeSet::from([2, 3])),
+        ]);
+        assert_eq!(
+            topology.reach(BTreeSet::from([4]), true),
+            BTreeSet::from([1, 2, 3, 4])
+        );
+        assert_eq!(
+            topology.reach(BTreeSet::from([5]), false),
+            BTreeSet::from([3, 5])
+        );
+        assert_eq!(
+            topology.reach(BTreeSet::from([6]), true),
+            BTreeSet::from([6])
+        );
+    }
 }
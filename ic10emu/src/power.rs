@@ -0,0 +1,307 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    device::{Device, FieldType},
+    grammar::LogicType,
+    vm::{TICKS_PER_SECOND, VM},
+};
+
+/// supply and demand of a power network on the last tick
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct PowerState {
+    /// watts the generators and batteries could provide
+    pub supply: f64,
+    /// watts the consumers asked for
+    pub demand: f64,
+    /// demand was more than supply, nothing on the network is powered, the batteries charge
+    /// but only bring the network back once generation alone covers demand
+    pub overloaded: bool,
+}
+
+impl PowerState {
+    /// something supplies the network and it isn't overloaded, a bare cable with nothing
+    /// generating or stored powers nothing
+    pub fn powered(&self) -> bool {
+        self.supply > 0.0 && !self.overloaded
+    }
+}
+
+/// what a device does on its power network
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum PowerRole {
+    /// provides its `PowerGeneration`
+    Generator(f64),
+    /// stores energy in `Charge`, up to `Maximum`
+    Battery { charge: f64, maximum: f64 },
+    /// draws its `RequiredPower`
+    Consumer(f64),
+}
+
+impl PowerRole {
+    fn of(device: &Device, vm: &VM) -> Option<Self> {
+        let fields = device.get_fields(vm);
+        let field = |typ| fields.get(&typ).map(|field| field.value);
+        if field(LogicType::On) == Some(0.0) {
+            return None;
+        }
+        if let Some(generation) = field(LogicType::PowerGeneration) {
+            Some(PowerRole::Generator(generation.max(0.0)))
+        } else if let (Some(charge), Some(maximum), Some(_)) = (
+            field(LogicType::Charge),
+            field(LogicType::Maximum),
+            field(LogicType::PowerPotential),
+        ) {
+            Some(PowerRole::Battery {
+                charge: charge.max(0.0),
+                maximum,
+            })
+        } else {
+            field(LogicType::RequiredPower).map(|required| PowerRole::Consumer(required.max(0.0)))
+        }
+    }
+}
+
+/// balance every power grid for one tick, charging or draining batteries as needed
+pub(crate) fn tick(vm: &VM) {
+    let mut members: BTreeMap<u32, Vec<(u32, PowerRole)>> = BTreeMap::new();
+    for (id, device) in &vm.devices {
+        let device = device.borrow();
+        if let (Some(net), Some(role)) = (device.power_network(), PowerRole::of(&device, vm)) {
+            members.entry(net).or_default().push((*id, role));
+        }
+    }
+    let mut balanced = BTreeSet::new();
+    for net in vm.networks.keys() {
+        if balanced.contains(net) {
+            continue;
+        }
+        // networks joined by transformers share one supply
+        let grid = vm.power_grid(*net);
+        let members = grid
+            .iter()
+            .flat_map(|net| members.remove(net).unwrap_or_default())
+            .collect::<Vec<_>>();
+        let was_overloaded = grid.iter().any(|net| {
+            vm.networks
+                .get(net)
+                .is_some_and(|network| network.borrow().power.overloaded)
+        });
+        let state = balance(vm, &members, was_overloaded);
+        for net in &grid {
+            let Some(network) = vm.networks.get(net) else {
+                continue;
+            };
+            let last = std::mem::replace(&mut network.borrow_mut().power, state);
+            if last.powered() != state.powered() {
+                for (id, _) in &members {
+                    vm.set_modified(*id);
+                }
+            }
+        }
+        balanced.extend(grid);
+    }
+}
+
+/// work out a network's state and move energy in and out of its batteries
+fn balance(vm: &VM, members: &[(u32, PowerRole)], was_overloaded: bool) -> PowerState {
+    let mut generation = 0.0;
+    let mut demand = 0.0;
+    let mut stored = 0.0;
+    for (_, role) in members {
+        match role {
+            PowerRole::Generator(watts) => generation += watts,
+            PowerRole::Consumer(watts) => demand += watts,
+            PowerRole::Battery { charge, .. } => stored += charge,
+        }
+    }
+    let from_batteries = stored * TICKS_PER_SECOND;
+    // a browned out network stays down while its batteries recharge, otherwise it would flip
+    // back on as soon as they held a tick's worth of the shortfall
+    let overloaded = if was_overloaded {
+        demand > generation
+    } else {
+        demand > generation + from_batteries
+    };
+    // an overloaded network browns out, its generators only charge the batteries
+    let mut surplus = generation - if overloaded { 0.0 } else { demand };
+    for (id, role) in members {
+        let PowerRole::Battery { charge, maximum } = *role else {
+            continue;
+        };
+        let energy = (surplus / TICKS_PER_SECOND).clamp(-charge, (maximum - charge).max(0.0));
+        surplus -= energy * TICKS_PER_SECOND;
+        let charge = charge + energy;
+        let ratio = if maximum > 0.0 { charge / maximum } else { 0.0 };
+        set_battery(
+            vm,
+            *id,
+            charge,
+            ratio,
+            (-energy * TICKS_PER_SECOND).max(0.0),
+        );
+    }
+    PowerState {
+        supply: generation + from_batteries,
+        demand,
+        overloaded,
+    }
+}
+
+fn set_battery(vm: &VM, id: u32, charge: f64, ratio: f64, actual: f64) {
+    let Some(device) = vm.get_device(id) else {
+        return;
+    };
+    let mut device = device.borrow_mut();
+    for (typ, value) in [
+        (LogicType::Charge, charge),
+        (LogicType::Ratio, ratio),
+        (LogicType::PowerActual, actual),
+    ] {
+        device.ensure_field(typ, FieldType::Read, value);
+        let _ = device.set_field(typ, value, vm, true);
+    }
+    vm.set_modified(id);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::VMError;
+
+    fn add_device(vm: &mut VM, fields: &[(LogicType, f64)]) -> Result<u32, VMError> {
+        let id = vm.add_device(None)?;
+        let device = vm.get_device(id).unwrap();
+        for (typ, value) in fields {
+            device.borrow_mut().set_field(*typ, *value, vm, true)?;
+        }
+        Ok(id)
+    }
+
+    #[test]
+    fn brown_out() -> Result<(), VMError> {
+        let mut vm = VM::new();
+        vm.set_power_simulation(true);
+        let housing = vm.add_ic(None)?;
+        let ic = vm.devices[&housing].borrow().ic.unwrap();
+        vm.set_code(housing, "loop:\nadd r0 r0 1\nyield\nj loop")?;
+        let counted = |vm: &VM| vm.ics[&ic].borrow().get_register(0, 0).unwrap();
+
+        // no power cable, the ic doesn't run, not even when stepped by hand
+        vm.tick()?;
+        assert_eq!(counted(&vm), 0.0);
+        assert!(matches!(
+            vm.step_ic(housing, false),
+            Err(VMError::Unpowered(_))
+        ));
+        vm.set_device_connection(housing, 1, Some(vm.default_network))?;
+        add_device(&mut vm, &[(LogicType::PowerGeneration, 1000.0)])?;
+        let battery = add_device(
+            &mut vm,
+            &[
+                (LogicType::Charge, 500.0),
+                (LogicType::Maximum, 1000.0),
+                (LogicType::PowerPotential, 0.0),
+            ],
+        )?;
+        let heater = add_device(
+            &mut vm,
+            &[(LogicType::On, 1.0), (LogicType::RequiredPower, 1500.0)],
+        )?;
+
+        // the battery covers the 500W shortfall for two ticks
+        vm.run_ticks(2)?;
+        assert_eq!(counted(&vm), 2.0);
+        let field = |id, typ| vm.get_device(id).unwrap().borrow().get_field(typ, &vm);
+        assert_eq!(field(battery, LogicType::Charge)?, 0.0);
+        vm.tick()?;
+        let power = vm.get_network_power(vm.default_network).unwrap();
+        assert_eq!((power.supply, power.demand), (1000.0, 1500.0));
+        assert!(power.overloaded);
+
+        // browned out, the generator charges the battery but the network stays down
+        vm.run_ticks(4)?;
+        assert_eq!(counted(&vm), 2.0);
+        assert!(vm.get_network_power(vm.default_network).unwrap().overloaded);
+        assert_eq!(field(heater, LogicType::Power)?, 0.0);
+        assert_eq!(field(battery, LogicType::Charge)?, 1000.0);
+
+        // turned off the heater stops drawing and the network comes back
+        vm.get_device(heater)
+            .unwrap()
+            .borrow_mut()
+            .set_field(LogicType::On, 0.0, &vm, true)?;
+        vm.run_ticks(2)?;
+        assert_eq!(counted(&vm), 4.0);
+        assert_eq!(field(battery, LogicType::Charge)?, 1000.0);
+        assert_eq!(field(battery, LogicType::Ratio)?, 1.0);
+
+        let state = vm.save_vm_state();
+        vm.set_power_simulation(false);
+        vm.set_device_connection(housing, 1, None)?;
+        vm.tick()?;
+        assert_eq!(counted(&vm), 5.0);
+        vm.restore_vm_state(state)?;
+        assert!(vm.power_simulation());
+        Ok(())
+    }
+
+    #[test]
+    fn bare_cable() -> Result<(), VMError> {
+        let mut vm = VM::new();
+        vm.set_power_simulation(true);
+        let housing = vm.add_ic(None)?;
+        let ic = vm.devices[&housing].borrow().ic.unwrap();
+        vm.set_code(housing, "loop:\nadd r0 r0 1\nyield\nj loop")?;
+        vm.set_device_connection(housing, 1, Some(vm.default_network))?;
+        let field = |vm: &VM, typ| vm.devices[&housing].borrow().get_field(typ, vm);
+
+        // a cable with nothing generating or stored doesn't power the housing
+        vm.tick()?;
+        assert_eq!(vm.ics[&ic].borrow().get_register(0, 0).unwrap(), 0.0);
+        assert_eq!(field(&vm, LogicType::Power)?, 0.0);
+        assert!(matches!(
+            vm.step_ic(housing, false),
+            Err(VMError::Unpowered(_))
+        ));
+
+        let generator = add_device(&mut vm, &[(LogicType::PowerGeneration, 100.0)])?;
+        vm.tick()?;
+        assert_eq!(field(&vm, LogicType::Power)?, 1.0);
+        assert_eq!(vm.ics[&ic].borrow().get_register(0, 0).unwrap(), 1.0);
+
+        // without power simulation the cable alone is enough
+        vm.set_power_simulation(false);
+        vm.remove_device(generator)?;
+        assert_eq!(field(&vm, LogicType::Power)?, 1.0);
+        Ok(())
+    }
+
+    #[test]
+    fn transformer_grid() -> Result<(), VMError> {
+        let mut vm = VM::new();
+        vm.set_power_simulation(true);
+        let supplied = vm.default_network;
+        let isolated = vm.add_network();
+        let generator = add_device(&mut vm, &[(LogicType::PowerGeneration, 1000.0)])?;
+        vm.set_device_connection(generator, 0, Some(supplied))?;
+        let housing = vm.add_ic(Some(isolated))?;
+        vm.set_device_connection(housing, 1, Some(isolated))?;
+        let heater = add_device(
+            &mut vm,
+            &[(LogicType::On, 1.0), (LogicType::RequiredPower, 500.0)],
+        )?;
+        vm.set_device_connection(heater, 0, Some(isolated))?;
+        vm.tick()?;
+        assert!(!vm.devices[&housing].borrow().is_powered(&vm));
+
+        let transformer = vm.add_device_by_prefab("StructureTransformer", None)?;
+        vm.set_device_connection(transformer, 1, Some(supplied))?;
+        vm.set_device_connection(transformer, 2, Some(isolated))?;
+        vm.tick()?;
+        assert!(vm.devices[&housing].borrow().is_powered(&vm));
+        assert_eq!(vm.get_network_power(isolated).unwrap().supply, 1000.0);
+        Ok(())
+    }
+}
@@ -0,0 +1,213 @@
+use std::collections::BTreeMap;
+
+use crate::{
+    device::{DeviceTemplate, FieldType, LogicField, SlotTemplate, SlotType, SortingClass},
+    grammar::{LogicType, SlotLogicType},
+    network::{Bridge, CableConnectionType, Connection, ConnectionRole, ConnectionType},
+};
+
+include!(concat!(env!("OUT_DIR"), "/prefabs.rs"));
+
+/// what the game's data says about a prefab, generated from `data/database.json`
+#[derive(Debug)]
+pub struct PrefabInfo {
+    pub name: &'static str,
+    pub hash: i32,
+    pub title: &'static str,
+    pub logic: &'static [(LogicType, FieldType)],
+    /// `(slot index, field, access)`
+    pub slot_logic: &'static [(usize, SlotLogicType, FieldType)],
+    pub slots: &'static [PrefabSlot],
+    pub connections: &'static [PrefabConnection],
+    /// `(value, name)` of each `Mode`
+    pub modes: &'static [(u32, &'static str)],
+    /// device pins, for ic housings
+    pub pins: Option<usize>,
+    pub item: Option<PrefabItem>,
+    pub transmitter: bool,
+    pub receiver: bool,
+}
+
+#[derive(Debug)]
+pub struct PrefabSlot {
+    pub name: &'static str,
+    pub typ: SlotType,
+}
+
+#[derive(Debug)]
+pub struct PrefabConnection {
+    pub name: &'static str,
+    pub typ: ConnectionType,
+    pub role: ConnectionRole,
+}
+
+#[derive(Debug)]
+pub struct PrefabItem {
+    /// the slot type the item fits in
+    pub slot_class: SlotType,
+    pub sorting: SortingClass,
+    pub max_quantity: Option<u32>,
+}
+
+/// a reagent from the database's `reagents` table, what `lr` reads amounts of
+#[derive(Debug)]
+pub struct ReagentInfo {
+    pub name: &'static str,
+    pub hash: i32,
+    pub unit: &'static str,
+    /// `(item prefab, amount)` of the items that provide the reagent
+    pub sources: &'static [(&'static str, f64)],
+}
+
+/// prefabs that pass power or data between their networks, the database doesn't say which do.
+/// it has no data relay either, a device's own `bridge` setting makes it one
+const BRIDGES: &[(&str, Bridge)] = &[
+    ("StructureAreaPowerControl", Bridge::Power),
+    ("StructureAreaPowerControlReversed", Bridge::Power),
+    ("StructureRocketTransformerSmall", Bridge::Power),
+    ("StructureTransformer", Bridge::Power),
+    ("StructureTransformerMedium", Bridge::Power),
+    ("StructureTransformerMediumReversed", Bridge::Power),
+    ("StructureTransformerSmall", Bridge::Power),
+    ("StructureTransformerSmallReversed", Bridge::Power),
+];
+
+/// look up a prefab by name, like `StructureGasSensor`
+pub fn get(name: &str) -> Option<&'static PrefabInfo> {
+    PREFABS.get(name)
+}
+
+pub fn get_by_hash(hash: i32) -> Option<&'static PrefabInfo> {
+    PREFAB_NAMES.get(&hash).and_then(|name| PREFABS.get(name))
+}
+
+pub fn all() -> impl Iterator<Item = &'static PrefabInfo> {
+    PREFABS.values()
+}
+
+/// look up a reagent by name, like `Iron`
+pub fn reagent(name: &str) -> Option<&'static ReagentInfo> {
+    REAGENTS.get(name)
+}
+
+pub fn reagent_by_hash(hash: i32) -> Option<&'static ReagentInfo> {
+    REAGENT_NAMES.get(&hash).and_then(|name| REAGENTS.get(name))
+}
+
+impl PrefabInfo {
+    /// the prefab's access to a logic field, `None` if it doesn't have it
+    pub fn logic_access(&self, typ: LogicType) -> Option<FieldType> {
+        self.logic
+            .iter()
+            .find_map(|(field, access)| (*field == typ).then_some(*access))
+    }
+
+    pub fn slot_logic_access(&self, index: usize, typ: SlotLogicType) -> Option<FieldType> {
+        self.slot_logic
+            .iter()
+            .find_map(|(slot, field, access)| (*slot == index && *field == typ).then_some(*access))
+    }
+
+    /// what the prefab passes between its networks, see `BRIDGES`
+    pub fn bridge(&self) -> Option<Bridge> {
+        BRIDGES
+            .iter()
+            .find_map(|(name, bridge)| (*name == self.name).then_some(*bridge))
+    }
+
+    /// a template for a new device of this prefab, with empty slots and no networks
+    pub fn template(&self) -> DeviceTemplate {
+        DeviceTemplate {
+            id: None,
+            name: None,
+            prefab_name: Some(self.name.to_owned()),
+            slots: self
+                .slots
+                .iter()
+                .map(|slot| SlotTemplate {
+                    typ: slot.typ,
+                    occupant: None,
+                })
+                .collect(),
+            reagents: BTreeMap::new(),
+            connections: self
+                .connections
+                .iter()
+                .map(|conn| {
+                    let typ = match conn.typ {
+                        ConnectionType::Data => CableConnectionType::Data,
+                        ConnectionType::Power => CableConnectionType::Power,
+                        ConnectionType::PowerAndData => CableConnectionType::PowerAndData,
+                        _ => return Connection::Other,
+                    };
+                    Connection::CableNetwork { net: None, typ }
+                })
+                .collect(),
+            wireless_link: None,
+            bridge: None,
+            fields: self
+                .logic
+                .iter()
+                // worked out from the device and its connections
+                .filter(|(typ, _)| !matches!(typ, LogicType::ReferenceId | LogicType::Power))
+                .map(|(typ, access)| {
+                    let value = match typ {
+                        LogicType::PrefabHash => self.hash as f64,
+                        // devices are built switched on
+                        LogicType::On => 1.0,
+                        _ => 0.0,
+                    };
+                    (
+                        *typ,
+                        LogicField {
+                            field_type: *access,
+                            value,
+                        },
+                    )
+                })
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::VM;
+
+    #[test]
+    fn add_device_by_prefab() {
+        let housing = get("StructureCircuitHousing").unwrap();
+        assert_eq!(housing.pins, Some(6));
+        assert_eq!(housing.slots[0].typ, SlotType::ProgrammableChip);
+        assert_eq!(get_by_hash(housing.hash).unwrap().name, housing.name);
+        assert_eq!(get("StructureLogicMath").unwrap().modes[3], (3, "Divide"));
+        assert_eq!(
+            get("ItemIntegratedCircuit10")
+                .unwrap()
+                .item
+                .as_ref()
+                .unwrap()
+                .slot_class,
+            SlotType::ProgrammableChip
+        );
+        assert!(BRIDGES.iter().all(|(name, _)| get(name).is_some()));
+
+        let mut vm = VM::new();
+        let id = vm.add_device_by_prefab("StructureGasSensor", None).unwrap();
+        let sensor = vm.get_device(id).unwrap();
+        let mut sensor = sensor.borrow_mut();
+        assert_eq!(
+            sensor.get_field(LogicType::PrefabHash, &vm).unwrap(),
+            -1252983604.0
+        );
+        assert!(sensor.get_field(LogicType::Temperature, &vm).is_ok());
+        assert!(sensor
+            .set_field(LogicType::Temperature, 300.0, &vm, false)
+            .is_err());
+        assert!(sensor.get_field(LogicType::On, &vm).is_err());
+        drop(sensor);
+        assert!(vm.get_default_network().borrow().contains_data(&id));
+        assert!(vm.add_device_by_prefab("NotAPrefab", None).is_err());
+    }
+}
@@ -0,0 +1,101 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+/// the number of lines an ic may run in a single tick before it's forced to yield
+pub const LINES_PER_TICK: u16 = 128;
+
+/// lines run by an ic during one tick
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TickUsage {
+    /// game tick the lines ran on
+    pub clock: u64,
+    pub lines: u16,
+    /// the ic used its whole budget and was forced to yield
+    pub exhausted: bool,
+}
+
+/// how often each line of an ic's program ran and how much of each tick's budget it used
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Profile {
+    /// id of the profiled ic's housing
+    pub device: u32,
+    pub line_hits: BTreeMap<u32, u64>,
+    pub ticks: Vec<TickUsage>,
+}
+
+impl Profile {
+    pub fn new(device: u32) -> Self {
+        Profile {
+            device,
+            ..Default::default()
+        }
+    }
+
+    pub fn record_line(&mut self, line: u32) {
+        *self.line_hits.entry(line).or_default() += 1;
+    }
+
+    pub fn record_tick(&mut self, clock: u64, lines: u16, exhausted: bool) {
+        self.ticks.push(TickUsage {
+            clock,
+            lines,
+            exhausted,
+        });
+    }
+
+    /// hits per line as a fraction of the most run line, lines that never ran are left out
+    pub fn heatmap(&self) -> BTreeMap<u32, f64> {
+        let max = self.line_hits.values().copied().max().unwrap_or(0);
+        self.line_hits
+            .iter()
+            .map(|(line, hits)| (*line, *hits as f64 / max as f64))
+            .collect()
+    }
+
+    pub fn total_lines(&self) -> u64 {
+        self.line_hits.values().sum()
+    }
+
+    pub fn exhausted_ticks(&self) -> Vec<u64> {
+        self.ticks
+            .iter()
+            .filter(|tick| tick.exhausted)
+            .map(|tick| tick.clock)
+            .collect()
+    }
+
+    pub fn report(&self) -> ProfileReport {
+        let max_lines = self.ticks.iter().map(|tick| tick.lines).max().unwrap_or(0);
+        let average_lines = if self.ticks.is_empty() {
+            0.0
+        } else {
+            self.ticks.iter().map(|tick| tick.lines as f64).sum::<f64>() / self.ticks.len() as f64
+        };
+        ProfileReport {
+            device: self.device,
+            line_hits: self.line_hits.clone(),
+            heatmap: self.heatmap(),
+            total_lines: self.total_lines(),
+            ticks: self.ticks.len(),
+            max_lines,
+            average_lines,
+            exhausted_ticks: self.exhausted_ticks(),
+        }
+    }
+}
+
+/// a summary of a [`Profile`]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ProfileReport {
+    pub device: u32,
+    pub line_hits: BTreeMap<u32, u64>,
+    pub heatmap: BTreeMap<u32, f64>,
+    pub total_lines: u64,
+    pub ticks: usize,
+    /// the most lines run in a single tick
+    pub max_lines: u16,
+    pub average_lines: f64,
+    /// game ticks on which the ic ran out of budget
+    pub exhausted_ticks: Vec<u64>,
+}
@@ -1,15 +1,66 @@
 use std::usize;
 
+use serde::{Deserialize, Serialize};
+use serde_with::serde_as;
+use thiserror::Error;
+
 const MSEED: i32 = 161803398;
 // const MZ: i32 = 0;
 
-#[derive(Debug)]
+#[serde_as]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "FrozenRandom")]
 pub struct Random {
     inext: usize,
     inextp: usize,
+    #[serde_as(as = "[_; 56]")]
     seed_array: [i32; 56],
 }
 
+/// a saved `Random` as read, checked before it's used
+#[derive(Deserialize)]
+struct FrozenRandom {
+    inext: usize,
+    inextp: usize,
+    seed_array: Vec<i32>,
+}
+
+#[derive(Debug, Error)]
+pub enum RandomStateError {
+    #[error("random index {0} is out of range")]
+    IndexOutOfRange(usize),
+    #[error("random seed array has {0} values, expected 56")]
+    SeedArrayLength(usize),
+}
+
+impl TryFrom<FrozenRandom> for Random {
+    type Error = RandomStateError;
+
+    fn try_from(frozen: FrozenRandom) -> Result<Self, Self::Error> {
+        let len = frozen.seed_array.len();
+        let seed_array = frozen
+            .seed_array
+            .try_into()
+            .map_err(|_| RandomStateError::SeedArrayLength(len))?;
+        for index in [frozen.inext, frozen.inextp] {
+            if index >= 56 {
+                return Err(RandomStateError::IndexOutOfRange(index));
+            }
+        }
+        Ok(Random {
+            inext: frozen.inext,
+            inextp: frozen.inextp,
+            seed_array,
+        })
+    }
+}
+
+impl Default for Random {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 /// Partial implementation of mscorlib System.Random
 /// <https://github.com/microsoft/referencesource/blob/master/mscorlib/system/random.cs#L94>
 impl Random {
@@ -89,7 +140,8 @@ impl Random {
         retval
     }
 
-    #[allow(dead_code)]
+    /// mirrors `System.Random.Next()`
+    #[allow(dead_code, clippy::should_implement_trait)]
     pub fn next(&mut self) -> i32 {
         self.internal_sample()
     }
@@ -119,4 +171,20 @@ mod test {
         assert_eq!(rand.next_f64(), 0.044092261252967765);
         assert_eq!(rand.next(), 659561101);
     }
+
+    #[test]
+    fn restore_checks_state() {
+        let mut rand = Random::with_seed(7);
+        rand.next();
+        let json = serde_json::to_value(&rand).unwrap();
+        let mut restored: Random = serde_json::from_value(json.clone()).unwrap();
+        assert_eq!(restored.next(), rand.next());
+
+        let mut bad_index = json.clone();
+        bad_index["inext"] = 56.into();
+        assert!(serde_json::from_value::<Random>(bad_index).is_err());
+        let mut short = json;
+        short["seed_array"] = vec![0; 55].into();
+        assert!(serde_json::from_value::<Random>(short).is_err());
+    }
 }
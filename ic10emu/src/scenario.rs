@@ -0,0 +1,683 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{
+    behavior::BuiltinBehavior,
+    device::{
+        Device, DeviceTemplate, FieldType, LogicField, Prefab, SlotOccupantTemplate, SlotTemplate,
+        SlotType,
+    },
+    grammar::{LogicType, SlotLogicType},
+    network::{Bridge, CableConnectionType, Connection},
+    prefabs,
+    stimulus::{Stimulus, StimulusError, StimulusTarget, Waveform},
+    vm::{UnknownPrefabAccess, VMError, VM},
+};
+
+/// the name scenarios use for the VM's default network
+pub const DEFAULT_NETWORK: &str = "default";
+
+#[derive(Debug, Error)]
+pub enum ScenarioError {
+    #[error("failed to read {0}")]
+    Io(PathBuf, #[source] std::io::Error),
+    #[error("invalid scenario: {0}")]
+    Parse(#[from] toml::de::Error),
+    #[error("unknown network '{0}'")]
+    UnknownNetwork(String),
+    #[error("unknown device '{0}'")]
+    UnknownDevice(String),
+    #[error("more than one device is named '{0}'")]
+    DuplicateName(String),
+    #[error("device '{0}' has pins but no code to run")]
+    NotAHousing(String),
+    #[error("invalid pin '{0}', expected d0 to d5")]
+    InvalidPin(String),
+    #[error("device '{0}' configures a behavior but its prefab has none")]
+    NoBehavior(String),
+    #[error("invalid behavior settings on '{0}'")]
+    InvalidBehavior(String, #[source] serde_json::Error),
+    #[error("invalid field '{1}' on '{0}'")]
+    InvalidField(String, String),
+    #[error("field '{1}' on '{0}' sets an access, known prefabs use the database's")]
+    PrefabAccess(String, LogicType),
+    #[error("stimulus on '{0}' needs a waveform or csv")]
+    NoWaveform(String),
+    #[error("failed to read samples from {0}")]
+    Samples(PathBuf, #[source] StimulusError),
+    #[error("failed to load code into '{0}'")]
+    Code(String, #[source] VMError),
+    #[error(transparent)]
+    VM(#[from] VMError),
+}
+
+/// a logic field's value, with an optional access that defaults to the prefab's or `ReadWrite`,
+/// `access` may only be set on devices whose prefab isn't in the database
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ScenarioField {
+    Value(f64),
+    Field {
+        value: f64,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        access: Option<FieldType>,
+    },
+}
+
+impl ScenarioField {
+    pub fn access(&self) -> Option<FieldType> {
+        match self {
+            ScenarioField::Value(_) => None,
+            ScenarioField::Field { access, .. } => *access,
+        }
+    }
+}
+
+impl From<ScenarioField> for LogicField {
+    fn from(field: ScenarioField) -> Self {
+        match field {
+            ScenarioField::Value(value) => LogicField {
+                field_type: FieldType::ReadWrite,
+                value,
+            },
+            ScenarioField::Field { value, access } => LogicField {
+                field_type: access.unwrap_or(FieldType::ReadWrite),
+                value,
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ScenarioSlot {
+    #[serde(rename = "type")]
+    pub typ: SlotType,
+    /// fields of the item in the slot, an empty slot if not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub occupant: Option<BTreeMap<SlotLogicType, ScenarioField>>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ScenarioDevice {
+    /// the name pins refer to the device by, also sets the device's name hash
+    pub name: String,
+    pub prefab: Option<String>,
+    /// data networks the device is on, the default network if empty. they go on the prefab's
+    /// data connections in order, a connection is added for each one left over
+    pub networks: Vec<String>,
+    /// networks on the prefab's separate power connections, in order, a power only connection is
+    /// added for each one left over. those connections stay unplugged if not given
+    pub power: Vec<String>,
+    /// set on top of the prefab's fields, if it's a known prefab
+    pub fields: BTreeMap<LogicType, ScenarioField>,
+    /// replace the prefab's slots
+    pub slots: Vec<ScenarioSlot>,
+    /// ic10 code to run, makes the device an ic housing
+    pub code: Option<String>,
+    /// a file to load code from, relative to the scenario file
+    pub script: Option<PathBuf>,
+    /// `d0` to `d5` set to device names
+    pub pins: BTreeMap<String, String>,
+    /// devices the prefab's behavior reads from or acts on, like a wall heater's rooms or a
+    /// logic unit's inputs
+    pub links: Vec<String>,
+    /// the wireless device a logic transmitter is linked to
+    pub wireless: Option<String>,
+    /// what the device passes between its networks in place of its prefab's, `"Data"` makes it
+    /// a data relay
+    pub bridge: Option<Bridge>,
+    /// settings for the prefab's behavior, like a logic reader's `field`, `prefab_hash` may be
+    /// given as a prefab name
+    pub behavior: BTreeMap<String, toml::Value>,
+}
+
+impl ScenarioDevice {
+    /// the device's built-in behavior with the scenario's settings applied
+    fn configure_behavior(&self, vm: &VM, id: u32) -> Result<BuiltinBehavior, ScenarioError> {
+        let behavior = vm
+            .get_behaviors()
+            .remove(&id)
+            .ok_or_else(|| ScenarioError::NoBehavior(self.name.clone()))?;
+        let invalid = |err| ScenarioError::InvalidBehavior(self.name.clone(), err);
+        let mut value = serde_json::to_value(behavior).map_err(invalid)?;
+        for (key, setting) in &self.behavior {
+            let setting = match (key.as_str(), setting) {
+                ("prefab_hash", toml::Value::String(prefab)) => {
+                    serde_json::Value::from(Prefab::new(prefab).hash)
+                }
+                _ => serde_json::to_value(setting).map_err(invalid)?,
+            };
+            value[key] = setting;
+        }
+        value["device"] = id.into();
+        serde_json::from_value(value).map_err(invalid)
+    }
+}
+
+/// a device field driven by a waveform or csv samples, see [`Waveform`]
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ScenarioStimulus {
+    pub device: String,
+    /// a logic type, or a slot logic type if `slot` is set
+    pub field: String,
+    pub slot: Option<usize>,
+    pub waveform: Option<Waveform>,
+    /// a csv file of one sample per tick, relative to the scenario file
+    pub csv: Option<PathBuf>,
+    /// the csv column to read samples from
+    pub column: usize,
+    /// the tick the first csv sample applies to
+    pub start: u64,
+    /// loop the csv samples instead of holding the last one
+    pub repeat: bool,
+}
+
+impl ScenarioStimulus {
+    fn read_csv(&mut self, path: &Path) -> Result<(), ScenarioError> {
+        let csv =
+            std::fs::read_to_string(path).map_err(|err| ScenarioError::Io(path.to_owned(), err))?;
+        self.waveform = Some(
+            Waveform::from_csv(&csv, self.column, self.start, self.repeat)
+                .map_err(|err| ScenarioError::Samples(path.to_owned(), err))?,
+        );
+        Ok(())
+    }
+
+    fn target(&self, device: u32) -> Result<StimulusTarget, ScenarioError> {
+        let invalid = || ScenarioError::InvalidField(self.device.clone(), self.field.clone());
+        Ok(match self.slot {
+            Some(slot) => StimulusTarget::SlotField {
+                device,
+                slot,
+                field: self.field.parse().map_err(|_| invalid())?,
+            },
+            None => StimulusTarget::Field {
+                device,
+                field: self.field.parse().map_err(|_| invalid())?,
+            },
+        })
+    }
+}
+
+impl ScenarioDevice {
+    pub fn is_housing(&self) -> bool {
+        self.code.is_some() || self.script.is_some()
+    }
+}
+
+/// a test bench of named networks and devices that builds into a [`VM`]
+///
+/// ```toml
+/// networks = ["aux"]
+///
+/// [[devices]]
+/// name = "Sensor"
+/// prefab = "StructureGasSensor"
+/// fields = { Temperature = 293.15, Pressure = 101.3 }
+///
+/// [[devices]]
+/// name = "Controller"
+/// script = "controller.ic10"
+/// pins = { d0 = "Sensor" }
+///
+/// [[devices]]
+/// name = "Heater"
+/// prefab = "StructureWallHeater"
+/// links = ["Sensor"]
+///
+/// [[devices]]
+/// name = "Average"
+/// prefab = "StructureLogicBatchReader"
+/// power = ["default"]
+/// behavior = { prefab_hash = "StructureGasSensor", field = "Temperature", mode = "Average" }
+///
+/// [[stimuli]]
+/// device = "Sensor"
+/// field = "Temperature"
+/// waveform = { type = "sine", offset = 290, amplitude = 10, period = 2400 }
+/// ```
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Scenario {
+    /// seed for the VM's random number source
+    pub seed: Option<i32>,
+    /// how instructions access devices whose prefab isn't in the database
+    pub unknown_prefab_access: UnknownPrefabAccess,
+    /// networks to create besides `default`
+    pub networks: Vec<String>,
+    pub devices: Vec<ScenarioDevice>,
+    pub stimuli: Vec<ScenarioStimulus>,
+}
+
+impl Scenario {
+    pub fn from_toml(source: &str) -> Result<Self, ScenarioError> {
+        Ok(toml::from_str(source)?)
+    }
+
+    /// read a scenario file and the scripts and samples it refers to
+    pub fn load(path: &Path) -> Result<Self, ScenarioError> {
+        let source =
+            std::fs::read_to_string(path).map_err(|err| ScenarioError::Io(path.to_owned(), err))?;
+        let mut scenario = Scenario::from_toml(&source)?;
+        scenario.resolve_files(path.parent().unwrap_or(Path::new("")))?;
+        Ok(scenario)
+    }
+
+    /// replace each device's `script` with the code it points to and each stimulus' `csv` with
+    /// its samples
+    pub fn resolve_files(&mut self, base: &Path) -> Result<(), ScenarioError> {
+        for device in &mut self.devices {
+            if let Some(script) = device.script.take() {
+                let path = base.join(script);
+                device.code = Some(
+                    std::fs::read_to_string(&path).map_err(|err| ScenarioError::Io(path, err))?,
+                );
+            }
+        }
+        for stimulus in &mut self.stimuli {
+            if let Some(csv) = stimulus.csv.take() {
+                stimulus.read_csv(&base.join(csv))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// the scenario's VM, with files resolved from the working directory if not already loaded
+    pub fn build(&self) -> Result<VM, ScenarioError> {
+        let mut vm = VM::new();
+        if let Some(seed) = self.seed {
+            vm.reseed(seed);
+        }
+        vm.set_unknown_prefab_access(self.unknown_prefab_access);
+        // scenarios declare all their housings
+        let default_housings = vm.devices.keys().copied().collect::<Vec<_>>();
+        for id in default_housings {
+            vm.remove_device(id)?;
+        }
+
+        let mut networks = BTreeMap::from([(DEFAULT_NETWORK.to_owned(), vm.default_network)]);
+        for name in &self.networks {
+            if !networks.contains_key(name) {
+                networks.insert(name.clone(), vm.add_network());
+            }
+        }
+        let network = |name: &String| {
+            networks
+                .get(name)
+                .copied()
+                .ok_or_else(|| ScenarioError::UnknownNetwork(name.clone()))
+        };
+
+        let mut ids = BTreeMap::new();
+        for device in &self.devices {
+            if ids.contains_key(&device.name) {
+                return Err(ScenarioError::DuplicateName(device.name.clone()));
+            }
+            let prefab = device.prefab.as_deref().and_then(prefabs::get);
+            let mut template = if device.is_housing() {
+                housing_template()
+            } else if let Some(prefab) = prefab {
+                prefab.template()
+            } else {
+                DeviceTemplate::default()
+            };
+            template.name = Some(device.name.clone());
+            template.bridge = device.bridge;
+            if let Some(prefab) = &device.prefab {
+                template.prefab_name = Some(prefab.clone());
+                // batch reads and writes find devices by this
+                template.fields.insert(
+                    LogicType::PrefabHash,
+                    LogicField {
+                        field_type: FieldType::Read,
+                        value: Prefab::new(prefab).hash as f64,
+                    },
+                );
+            }
+            let data = if device.networks.is_empty() {
+                vec![vm.default_network]
+            } else {
+                device
+                    .networks
+                    .iter()
+                    .map(network)
+                    .collect::<Result<Vec<_>, _>>()?
+            };
+            for net in data {
+                connect(&mut template.connections, net, CableConnectionType::Data);
+            }
+            for name in &device.power {
+                connect(
+                    &mut template.connections,
+                    network(name)?,
+                    CableConnectionType::Power,
+                );
+            }
+            for (typ, field) in &device.fields {
+                if prefab.is_some() && field.access().is_some() {
+                    return Err(ScenarioError::PrefabAccess(device.name.clone(), *typ));
+                }
+                let mut logic = LogicField::from(*field);
+                if let (None, Some(existing)) = (field.access(), template.fields.get(typ)) {
+                    logic.field_type = existing.field_type;
+                }
+                template.fields.insert(*typ, logic);
+            }
+            if prefab.is_some() && !device.is_housing() && !device.slots.is_empty() {
+                template.slots.clear();
+            }
+            template
+                .slots
+                .extend(device.slots.iter().map(|slot| SlotTemplate {
+                    typ: slot.typ,
+                    occupant: slot.occupant.as_ref().map(|fields| {
+                        SlotOccupantTemplate {
+                            id: None,
+                            fields: fields
+                                .iter()
+                                .map(|(typ, field)| (*typ, LogicField::from(*field)))
+                                .collect(),
+                        }
+                    }),
+                }));
+            let id = vm.add_device_from_template(template)?;
+            ids.insert(device.name.clone(), id);
+        }
+
+        // linked first so pins can point at wireless devices
+        for device in &self.devices {
+            if let Some(target) = &device.wireless {
+                let target = *ids
+                    .get(target)
+                    .ok_or_else(|| ScenarioError::UnknownDevice(target.clone()))?;
+                vm.set_wireless_link(ids[&device.name], Some(target))?;
+            }
+        }
+
+        for device in &self.devices {
+            let id = ids[&device.name];
+            if !device.pins.is_empty() && !device.is_housing() {
+                return Err(ScenarioError::NotAHousing(device.name.clone()));
+            }
+            for (pin, target) in &device.pins {
+                let index = pin
+                    .strip_prefix('d')
+                    .and_then(|index| index.parse::<usize>().ok())
+                    .filter(|index| *index < 6)
+                    .ok_or_else(|| ScenarioError::InvalidPin(pin.clone()))?;
+                let target = *ids
+                    .get(target)
+                    .ok_or_else(|| ScenarioError::UnknownDevice(target.clone()))?;
+                vm.set_pin(id, index, Some(target))?;
+            }
+            if !device.behavior.is_empty() {
+                vm.set_builtin_behavior(device.configure_behavior(&vm, id)?)?;
+            }
+            if !device.links.is_empty() {
+                if !vm.has_behavior(id) {
+                    return Err(ScenarioError::NoBehavior(device.name.clone()));
+                }
+                let links = device
+                    .links
+                    .iter()
+                    .map(|name| {
+                        ids.get(name)
+                            .copied()
+                            .ok_or_else(|| ScenarioError::UnknownDevice(name.clone()))
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                vm.link_behavior(id, &links)?;
+            }
+            if let Some(script) = &device.script {
+                let code = std::fs::read_to_string(script)
+                    .map_err(|err| ScenarioError::Io(script.clone(), err))?;
+                vm.set_code(id, &code)
+                    .map_err(|err| ScenarioError::Code(device.name.clone(), err))?;
+            } else if let Some(code) = &device.code {
+                vm.set_code(id, code)
+                    .map_err(|err| ScenarioError::Code(device.name.clone(), err))?;
+            }
+        }
+
+        for stimulus in &self.stimuli {
+            let mut stimulus = stimulus.clone();
+            if let Some(csv) = stimulus.csv.take() {
+                stimulus.read_csv(&csv)?;
+            }
+            let device = *ids
+                .get(&stimulus.device)
+                .ok_or_else(|| ScenarioError::UnknownDevice(stimulus.device.clone()))?;
+            let waveform = stimulus
+                .waveform
+                .clone()
+                .ok_or_else(|| ScenarioError::NoWaveform(stimulus.device.clone()))?;
+            vm.add_stimulus(Stimulus::new(stimulus.target(device)?, waveform))?;
+        }
+        Ok(vm)
+    }
+}
+
+/// put `net` on the first free connection carrying `typ`, keeping the prefab's connection
+/// types, or add a connection of that type if there's none
+fn connect(connections: &mut Vec<Connection>, net: u32, typ: CableConnectionType) {
+    let free = connections.iter_mut().find_map(|conn| match conn {
+        Connection::CableNetwork {
+            net: free @ None,
+            typ: conn_typ,
+        } if matches!(
+            (typ, &*conn_typ),
+            (
+                CableConnectionType::Data,
+                CableConnectionType::Data | CableConnectionType::PowerAndData
+            ) | (CableConnectionType::Power, CableConnectionType::Power)
+        ) =>
+        {
+            Some(free)
+        }
+        _ => None,
+    });
+    match free {
+        Some(free) => *free = Some(net),
+        None => connections.push(Connection::CableNetwork {
+            net: Some(net),
+            typ,
+        }),
+    }
+}
+
+/// the same circuit housing [`VM::add_ic`] creates
+fn housing_template() -> DeviceTemplate {
+    let mut template = DeviceTemplate::from(&Device::with_ic(0, 0));
+    template.id = None;
+    for slot in &mut template.slots {
+        if let Some(occupant) = &mut slot.occupant {
+            occupant.id = None;
+        }
+    }
+    template
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn build_scenario() -> Result<(), ScenarioError> {
+        let scenario = Scenario::from_toml(
+            r#"
+seed = 5
+networks = ["aux"]
+
+[[devices]]
+name = "Sensor"
+prefab = "StructureGasSensor"
+fields = { Temperature = 300.0, Pressure = 101.3 }
+
+[[devices]]
+name = "Pump"
+networks = ["aux"]
+fields = { On = 0, Ratio = { value = 0.5, access = "Read" } }
+
+[[devices]]
+name = "Battery"
+slots = [{ type = "Battery", occupant = { Charge = 5.0 } }]
+
+[[devices]]
+name = "Controller"
+networks = ["default", "aux"]
+pins = { d0 = "Sensor", d1 = "Pump" }
+code = """
+l r0 d0 Temperature
+sgt r1 r0 295
+s d1 On r1
+"""
+"#,
+        )?;
+        let vm = scenario.build()?;
+        assert_eq!(vm.devices.len(), 4);
+        assert_eq!(vm.ics.len(), 1);
+        assert_eq!(vm.networks.len(), 2);
+
+        let find = |name: &str| {
+            vm.devices
+                .values()
+                .find(|device| device.borrow().name.as_deref() == Some(name))
+                .cloned()
+                .unwrap()
+        };
+        vm.run_ticks(1)?;
+        let pump = find("Pump");
+        assert_eq!(pump.borrow().get_field(LogicType::On, &vm).unwrap(), 1.0);
+        let controller = vm.get_ic_for_device(find("Controller").borrow().id)?;
+        assert_eq!(controller.borrow().pins.borrow()[1], Some(pump.borrow().id));
+        assert!(find("Sensor")
+            .borrow_mut()
+            .set_field(LogicType::Pressure, 1.0, &vm, false)
+            .is_err());
+        assert!(pump
+            .borrow_mut()
+            .set_field(LogicType::Ratio, 1.0, &vm, false)
+            .is_err());
+
+        let stimulated = Scenario::from_toml(
+            r#"
+[[devices]]
+name = "Sensor"
+
+[[stimuli]]
+device = "Sensor"
+field = "Temperature"
+waveform = { type = "ramp", from = 280, to = 300, start = 0, end = 10 }
+"#,
+        )?
+        .build()?;
+        stimulated.run_ticks(6)?;
+        let sensor = stimulated.devices.values().next().unwrap().borrow();
+        assert_eq!(
+            sensor
+                .get_field(LogicType::Temperature, &stimulated)
+                .unwrap(),
+            290.0
+        );
+        drop(sensor);
+
+        let unknown = Scenario::from_toml(
+            "[[devices]]\nname = \"IC\"\ncode = \"yield\"\npins = { d0 = \"Nope\" }",
+        )?;
+        assert!(matches!(
+            unknown.build(),
+            Err(ScenarioError::UnknownDevice(_))
+        ));
+
+        // a device set to bridge data joins its networks
+        let relayed = Scenario::from_toml(
+            r#"
+networks = ["aux"]
+
+[[devices]]
+name = "Sensor"
+networks = ["aux"]
+
+[[devices]]
+name = "Relay"
+networks = ["default", "aux"]
+bridge = "Data"
+
+[[devices]]
+name = "IC"
+code = "yield"
+pins = { d0 = "Sensor" }
+"#,
+        )?;
+        assert_eq!(relayed.devices[1].bridge, Some(Bridge::Data));
+        assert!(relayed.build().is_ok());
+
+        // declared networks go on the prefab's own connections
+        let powered = Scenario::from_toml(
+            r#"
+networks = ["grid", "load"]
+
+[[devices]]
+name = "Transformer"
+prefab = "StructureTransformer"
+power = ["grid", "load"]
+
+[[devices]]
+name = "Heater"
+prefab = "StructureWallHeater"
+networks = ["load"]
+"#,
+        )?
+        .build()?;
+        let connections = |name: &str| {
+            powered
+                .devices
+                .values()
+                .find(|device| device.borrow().name.as_deref() == Some(name))
+                .map(|device| device.borrow().connections.clone())
+                .unwrap()
+        };
+        let transformer = connections("Transformer");
+        assert_eq!(transformer.len(), 3);
+        assert!(transformer
+            .iter()
+            .all(|conn| matches!(conn, Connection::CableNetwork { net: Some(_), .. })));
+        let load = powered.networks.keys().copied().max().unwrap();
+        assert!(matches!(
+            connections("Heater")[0],
+            Connection::CableNetwork {
+                net: Some(net),
+                typ: CableConnectionType::PowerAndData,
+            } if net == load
+        ));
+        assert_eq!(powered.power_grid(load).len(), 2);
+
+        let prefab_access = Scenario::from_toml(
+            r#"
+unknown_prefab_access = "Deny"
+
+[[devices]]
+name = "Sensor"
+prefab = "StructureGasSensor"
+fields = { Pressure = { value = 101.3, access = "ReadWrite" } }
+"#,
+        )?;
+        assert_eq!(
+            prefab_access.unknown_prefab_access,
+            UnknownPrefabAccess::Deny
+        );
+        assert!(matches!(
+            prefab_access.build(),
+            Err(ScenarioError::PrefabAccess(_, LogicType::Pressure))
+        ));
+        Ok(())
+    }
+}
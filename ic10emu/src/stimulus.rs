@@ -0,0 +1,371 @@
+use std::{collections::BTreeMap, f64::consts::TAU};
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{
+    grammar::{LogicType, SlotLogicType},
+    rand_mscorlib::Random,
+};
+
+#[derive(Debug, Error, Serialize, Deserialize)]
+pub enum StimulusError {
+    #[error("line {0}: no numeric value in column {1}")]
+    MissingColumn(usize, usize),
+    #[error("no samples found")]
+    NoSamples,
+    #[error("random walk range {0}..={1} is empty")]
+    EmptyRange(f64, f64),
+    #[error("sine period must be more than 0, not {0}")]
+    BadPeriod(f64),
+}
+
+/// how a stimulus value changes over game ticks
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Waveform {
+    /// `(tick, value)` pairs, each value holds from its tick until the next
+    Steps { steps: Vec<(u64, f64)> },
+    /// `from` until `start`, moving linearly to `to` at `end`
+    Ramp {
+        from: f64,
+        to: f64,
+        start: u64,
+        end: u64,
+    },
+    /// `offset + amplitude * sin(2π * tick / period + phase)`
+    Sine {
+        #[serde(default)]
+        offset: f64,
+        amplitude: f64,
+        /// ticks per cycle
+        period: f64,
+        #[serde(default)]
+        phase: f64,
+    },
+    /// one sample per tick starting at `start`, holding the last sample unless `repeat` is set
+    Samples {
+        samples: Vec<f64>,
+        #[serde(default)]
+        start: u64,
+        #[serde(default)]
+        repeat: bool,
+    },
+    /// moves up to `step` from `start` each tick, kept within `min..=max`
+    RandomWalk {
+        start: f64,
+        step: f64,
+        #[serde(default = "neg_infinity")]
+        min: f64,
+        #[serde(default = "infinity")]
+        max: f64,
+        #[serde(default)]
+        seed: i32,
+    },
+}
+
+fn neg_infinity() -> f64 {
+    f64::NEG_INFINITY
+}
+
+fn infinity() -> f64 {
+    f64::INFINITY
+}
+
+impl Waveform {
+    /// catch settings that would panic or produce garbage when sampled
+    pub fn validate(&self) -> Result<(), StimulusError> {
+        match self {
+            Waveform::RandomWalk { min, max, .. } if min.is_nan() || max.is_nan() || min > max => {
+                Err(StimulusError::EmptyRange(*min, *max))
+            }
+            Waveform::Sine { period, .. } if period.is_nan() || *period <= 0.0 => {
+                Err(StimulusError::BadPeriod(*period))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// samples from a column of csv text, rows that aren't numbers (like headers) are skipped
+    pub fn from_csv(
+        csv: &str,
+        column: usize,
+        start: u64,
+        repeat: bool,
+    ) -> Result<Self, StimulusError> {
+        let mut samples = Vec::new();
+        for (index, line) in csv.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let value = line
+                .split(',')
+                .nth(column)
+                .map(|value| value.trim().parse::<f64>());
+            match value {
+                Some(Ok(value)) => samples.push(value),
+                // a header row
+                Some(Err(_)) if samples.is_empty() => {}
+                _ => return Err(StimulusError::MissingColumn(index + 1, column)),
+            }
+        }
+        if samples.is_empty() {
+            return Err(StimulusError::NoSamples);
+        }
+        Ok(Waveform::Samples {
+            samples,
+            start,
+            repeat,
+        })
+    }
+}
+
+/// the device field a stimulus drives
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum StimulusTarget {
+    Field {
+        device: u32,
+        field: LogicType,
+    },
+    SlotField {
+        device: u32,
+        slot: usize,
+        field: SlotLogicType,
+    },
+}
+
+impl StimulusTarget {
+    pub fn device(&self) -> u32 {
+        match self {
+            StimulusTarget::Field { device, .. } | StimulusTarget::SlotField { device, .. } => {
+                *device
+            }
+        }
+    }
+}
+
+/// a device field the VM sets from a waveform at the start of every tick
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Stimulus {
+    pub target: StimulusTarget,
+    pub waveform: Waveform,
+    #[serde(skip)]
+    walk: Option<Walk>,
+}
+
+/// random walk position, rebuilt from the seed if the clock goes backwards
+#[derive(Debug, Clone, PartialEq)]
+struct Walk {
+    random: Random,
+    clock: u64,
+    value: f64,
+}
+
+impl Stimulus {
+    pub fn new(target: StimulusTarget, waveform: Waveform) -> Self {
+        Stimulus {
+            target,
+            waveform,
+            walk: None,
+        }
+    }
+
+    /// the value for `clock`, or `None` to leave the field alone
+    pub fn sample(&mut self, clock: u64) -> Option<f64> {
+        match &self.waveform {
+            Waveform::Steps { steps } => steps
+                .iter()
+                .filter(|(tick, _)| *tick <= clock)
+                .max_by_key(|(tick, _)| *tick)
+                .map(|(_, value)| *value),
+            Waveform::Ramp {
+                from,
+                to,
+                start,
+                end,
+            } => Some(if clock <= *start {
+                *from
+            } else if clock >= *end {
+                *to
+            } else {
+                from + (to - from) * (clock - start) as f64 / (end - start) as f64
+            }),
+            Waveform::Sine {
+                offset,
+                amplitude,
+                period,
+                phase,
+            } => Some(offset + amplitude * (TAU * clock as f64 / period + phase).sin()),
+            Waveform::Samples {
+                samples,
+                start,
+                repeat,
+            } => {
+                let index = clock.checked_sub(*start)? as usize;
+                if *repeat {
+                    samples.get(index % samples.len().max(1)).copied()
+                } else {
+                    samples.get(index).or(samples.last()).copied()
+                }
+            }
+            Waveform::RandomWalk {
+                start,
+                step,
+                min,
+                max,
+                seed,
+            } => {
+                let walk = match self.walk.take() {
+                    Some(walk) if walk.clock <= clock => walk,
+                    _ => Walk {
+                        random: Random::with_seed(*seed),
+                        clock: 0,
+                        value: start.clamp(*min, *max),
+                    },
+                };
+                let Walk {
+                    mut random,
+                    clock: mut at,
+                    mut value,
+                } = walk;
+                while at < clock {
+                    value = (value + (random.next_f64() * 2.0 - 1.0) * step).clamp(*min, *max);
+                    at += 1;
+                }
+                self.walk = Some(Walk {
+                    random,
+                    clock,
+                    value,
+                });
+                Some(value)
+            }
+        }
+    }
+}
+
+/// stimuli attached to a VM, keyed by an id unique to the VM
+#[derive(Debug, Default, Clone)]
+pub struct Stimuli {
+    next_id: u32,
+    stimuli: BTreeMap<u32, Stimulus>,
+}
+
+impl Stimuli {
+    pub fn add(&mut self, stimulus: Stimulus) -> u32 {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.stimuli.insert(id, stimulus);
+        id
+    }
+
+    pub fn remove(&mut self, id: u32) -> Option<Stimulus> {
+        self.stimuli.remove(&id)
+    }
+
+    pub fn clear(&mut self) {
+        self.stimuli.clear();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.stimuli.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&u32, &Stimulus)> {
+        self.stimuli.iter()
+    }
+
+    /// point the stimuli driving `old` at its new id
+    pub fn change_device(&mut self, old: u32, new: u32) {
+        for stimulus in self.stimuli.values_mut() {
+            match &mut stimulus.target {
+                StimulusTarget::Field { device, .. } | StimulusTarget::SlotField { device, .. }
+                    if *device == old =>
+                {
+                    *device = new;
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// drop the stimuli driving a removed device
+    pub fn remove_device(&mut self, device: u32) {
+        self.stimuli
+            .retain(|_, stimulus| stimulus.target.device() != device);
+    }
+
+    /// the value each stimulus sets on `clock`
+    pub fn sample(&mut self, clock: u64) -> Vec<(StimulusTarget, f64)> {
+        self.stimuli
+            .values_mut()
+            .filter_map(|stimulus| Some((stimulus.target, stimulus.sample(clock)?)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn samples(waveform: Waveform, ticks: u64) -> Vec<Option<f64>> {
+        let target = StimulusTarget::Field {
+            device: 1,
+            field: LogicType::Setting,
+        };
+        let mut stimulus = Stimulus::new(target, waveform);
+        (0..ticks).map(|clock| stimulus.sample(clock)).collect()
+    }
+
+    #[test]
+    fn waveforms() {
+        let steps = Waveform::Steps {
+            steps: vec![(2, 1.0), (4, 3.0)],
+        };
+        assert_eq!(
+            samples(steps, 6),
+            vec![None, None, Some(1.0), Some(1.0), Some(3.0), Some(3.0)]
+        );
+        let ramp = Waveform::Ramp {
+            from: 0.0,
+            to: 10.0,
+            start: 1,
+            end: 3,
+        };
+        assert_eq!(
+            samples(ramp, 5),
+            vec![Some(0.0), Some(0.0), Some(5.0), Some(10.0), Some(10.0)]
+        );
+        let sine = Waveform::Sine {
+            offset: 1.0,
+            amplitude: 2.0,
+            period: 4.0,
+            phase: 0.0,
+        };
+        let sine = samples(sine, 4);
+        assert!((sine[1].unwrap() - 3.0).abs() < 1e-9);
+        assert!((sine[3].unwrap() + 1.0).abs() < 1e-9);
+
+        let csv = Waveform::from_csv("time,temp\n0,280\n1,285\n", 1, 1, true).unwrap();
+        assert_eq!(
+            samples(csv, 4),
+            vec![None, Some(280.0), Some(285.0), Some(280.0)]
+        );
+        assert!(Waveform::from_csv("a\n1\nb\n", 0, 0, false).is_err());
+
+        let walk = Waveform::RandomWalk {
+            start: 5.0,
+            step: 1.0,
+            min: 0.0,
+            max: 6.0,
+            seed: 42,
+        };
+        let first = samples(walk.clone(), 50);
+        assert_eq!(first, samples(walk, 50));
+        assert_eq!(first[0], Some(5.0));
+        assert!(first
+            .iter()
+            .flatten()
+            .all(|value| (0.0..=6.0).contains(value)));
+    }
+}
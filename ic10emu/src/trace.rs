@@ -0,0 +1,148 @@
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "trace-export")]
+use thiserror::Error;
+
+use crate::{
+    grammar::{InstructionOp, Number, Operand, RegisterSpec},
+    interpreter::IC,
+    watch::{deserialize_nan, Access},
+};
+
+/// prefix of the binary trace format, followed by a format version byte
+#[cfg(feature = "trace-export")]
+const TRACE_MAGIC: &[u8; 6] = b"IC10TR";
+#[cfg(feature = "trace-export")]
+const TRACE_VERSION: u8 = 2;
+
+#[cfg(feature = "trace-export")]
+#[derive(Debug, Error)]
+pub enum TraceError {
+    #[error("failed to write trace as json: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("failed to encode binary trace: {0}")]
+    Binary(#[from] bincode::Error),
+    #[error("not a binary ic10 trace")]
+    BadHeader,
+    #[error("unsupported binary trace version {0}")]
+    UnsupportedVersion(u8),
+}
+
+/// what an operand resolved to just before its line ran
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum TraceOperand {
+    /// NaN is written as `null` in json
+    Value(#[serde(deserialize_with = "deserialize_nan")] f64),
+    /// the register the line writes its result to
+    Register(String),
+    /// a logic type, mode or enum by name, with its value
+    Enum {
+        name: String,
+        value: f64,
+    },
+    Device(u32),
+    /// names, unset devices and anything else that isn't read as a value
+    Unresolved,
+}
+
+impl TraceOperand {
+    pub fn resolve(operand: &Operand, ic: &IC, inst: InstructionOp, index: u32) -> Self {
+        let operand = operand.translate_alias(ic);
+        match &operand {
+            Operand::RegisterSpec(RegisterSpec {
+                indirection,
+                target,
+            }) if index == 1 && inst.writes_register() => {
+                match ic.get_real_target(*indirection, *target) {
+                    Ok(target) => TraceOperand::Register(
+                        Operand::RegisterSpec(RegisterSpec {
+                            indirection: 0,
+                            target: target as u32,
+                        })
+                        .to_string(),
+                    ),
+                    Err(_) => TraceOperand::Unresolved,
+                }
+            }
+            Operand::Number(Number::Enum(name, value)) => TraceOperand::Enum {
+                name: name.clone(),
+                value: *value,
+            },
+            Operand::Type { identifier, .. } => match operand.as_value(ic, inst, index) {
+                Ok(value) => TraceOperand::Enum {
+                    name: identifier.name.clone(),
+                    value,
+                },
+                Err(_) => TraceOperand::Unresolved,
+            },
+            _ => {
+                if let Ok(val) = operand.as_value(ic, inst, index) {
+                    TraceOperand::Value(val)
+                } else if let Ok((Some(device), _)) = operand.as_device(ic, inst, index) {
+                    TraceOperand::Device(device)
+                } else {
+                    TraceOperand::Unresolved
+                }
+            }
+        }
+    }
+}
+
+/// one executed line
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TraceEntry {
+    /// game tick the line ran on
+    pub clock: u64,
+    pub line: u32,
+    pub instruction: InstructionOp,
+    pub operands: Vec<TraceOperand>,
+    pub writes: Vec<Access>,
+    pub error: Option<String>,
+}
+
+/// the lines executed by an ic while it was being traced
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Trace {
+    /// id of the traced ic's housing
+    pub device: u32,
+    pub entries: Vec<TraceEntry>,
+}
+
+impl Trace {
+    pub fn new(device: u32) -> Self {
+        Trace {
+            device,
+            entries: Vec::new(),
+        }
+    }
+
+    /// one json object per executed line
+    #[cfg(feature = "trace-export")]
+    pub fn to_json_lines(&self) -> Result<String, TraceError> {
+        let mut out = String::new();
+        for entry in &self.entries {
+            out.push_str(&serde_json::to_string(entry)?);
+            out.push('\n');
+        }
+        Ok(out)
+    }
+
+    #[cfg(feature = "trace-export")]
+    pub fn to_binary(&self) -> Result<Vec<u8>, TraceError> {
+        let mut out = TRACE_MAGIC.to_vec();
+        out.push(TRACE_VERSION);
+        bincode::serialize_into(&mut out, self)?;
+        Ok(out)
+    }
+
+    #[cfg(feature = "trace-export")]
+    pub fn from_binary(bytes: &[u8]) -> Result<Self, TraceError> {
+        let Some(body) = bytes.strip_prefix(TRACE_MAGIC.as_slice()) else {
+            return Err(TraceError::BadHeader);
+        };
+        match body.split_first() {
+            Some((&TRACE_VERSION, body)) => Ok(bincode::deserialize(body)?),
+            Some((version, _)) => Err(TraceError::UnsupportedVersion(*version)),
+            None => Err(TraceError::BadHeader),
+        }
+    }
+}
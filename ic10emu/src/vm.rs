@@ -1,12 +1,24 @@
 use crate::{
+    behavior::{Behavior, BuiltinBehavior},
+    breakpoint::{Breakpoint, BreakpointError, BreakpointHit},
     device::{Device, DeviceTemplate, SlotOccupant, SlotOccupantTemplate},
-    grammar::{BatchMode, LogicType, SlotLogicType},
+    grammar::{BatchMode, InstructionOp, LogicType, SlotLogicType},
+    history::{History, HistoryStep, StepDelta},
     interpreter::{self, FrozenIC, ICError, LineError},
-    network::{CableConnectionType, Connection, FrozenNetwork, Network},
+    network::{
+        BridgeTopology, CableConnectionType, ChannelMonitor, ChannelWrite, Connection,
+        FrozenNetwork, Membership, Network, NetworkMismatch,
+    },
+    power::{self, PowerState},
+    prefabs,
+    profiler::{Profile, LINES_PER_TICK},
+    stimulus::{Stimuli, Stimulus, StimulusError, StimulusTarget},
+    trace::{Trace, TraceEntry, TraceOperand},
+    watch::{Access, AccessKind, AccessLog, WatchTarget, Watchpoint, WatchpointHit, Watchpoints},
 };
 use std::{
-    cell::RefCell,
-    collections::{BTreeMap, HashSet},
+    cell::{Cell, RefCell},
+    collections::{BTreeMap, BTreeSet, HashSet},
     rc::Rc,
 };
 
@@ -36,8 +48,37 @@ pub enum VMError {
     IdsInUse(Vec<u32>),
     #[error("atempt to use a set of id's with duplicates: id(s) {0:?} exsist more than once")]
     DuplicateIds(Vec<u32>),
+    #[error("ic in device {0} has caught fire and must be reset")]
+    HasCaughtFire(u32),
+    #[error("device {0} is not powered")]
+    Unpowered(u32),
+    #[error("{0}")]
+    BreakpointError(#[from] BreakpointError),
+    #[error("device {0} has no behavior")]
+    NoBehavior(u32),
+    #[error("unknown prefab '{0}'")]
+    UnknownPrefab(String),
+    #[error("device {0} can't link to wireless devices")]
+    NotWirelessReceiver(u32),
+    #[error("device {0} can't be linked to wirelessly")]
+    NotWirelessTransmitter(u32),
+    #[error("bad stimulus: {0}")]
+    StimulusError(#[from] StimulusError),
 }
 
+/// how instructions may access the fields of devices whose prefab isn't in the prefab database
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum UnknownPrefabAccess {
+    /// the access of the device's own fields
+    #[default]
+    Permissive,
+    /// no fields at all, as if the device had no logic
+    Deny,
+}
+
+/// The game runs logic at 2 ticks a second
+pub const TICKS_PER_SECOND: f64 = 2.0;
+
 #[derive(Debug)]
 pub struct VM {
     pub ics: BTreeMap<u32, Rc<RefCell<interpreter::IC>>>,
@@ -47,9 +88,27 @@ pub struct VM {
     id_space: IdSpace,
     network_id_space: IdSpace,
     random: Rc<RefCell<crate::rand_mscorlib::Random>>,
+    /// game ticks elapsed
+    clock: Cell<u64>,
 
     /// list of device id's touched on the last operation
     operation_modified: RefCell<Vec<u32>>,
+    watchpoints: RefCell<Watchpoints>,
+    /// device field accesses, only recorded while watchpoints are set
+    accesses: RefCell<AccessLog>,
+    /// watchpoints hit since last collected by a tick
+    watchpoint_hits: RefCell<Vec<WatchpointHit>>,
+    history: RefCell<History>,
+    stimuli: RefCell<Stimuli>,
+    /// device behaviors, keyed by the device they drive
+    behaviors: RefCell<BTreeMap<u32, Box<dyn Behavior>>>,
+    unknown_prefab_access: Cell<UnknownPrefabAccess>,
+    /// balance supply and demand on power networks, off leaves any cable powered
+    power_simulation: Cell<bool>,
+    /// network channel writes, only logged while the monitor is running
+    channel_monitor: RefCell<Option<ChannelMonitor>>,
+    /// networks joined by bridges, rebuilt on the next lookup after it's dropped
+    bridges: RefCell<Option<BridgeTopology>>,
 }
 
 impl Default for VM {
@@ -75,12 +134,58 @@ impl VM {
             id_space: id_gen,
             network_id_space,
             random: Rc::new(RefCell::new(crate::rand_mscorlib::Random::new())),
+            clock: Cell::new(0),
             operation_modified: RefCell::new(Vec::new()),
+            watchpoints: RefCell::new(Watchpoints::default()),
+            accesses: RefCell::new(AccessLog::default()),
+            watchpoint_hits: RefCell::new(Vec::new()),
+            history: RefCell::new(History::default()),
+            stimuli: RefCell::new(Stimuli::default()),
+            behaviors: RefCell::new(BTreeMap::new()),
+            unknown_prefab_access: Cell::new(UnknownPrefabAccess::default()),
+            power_simulation: Cell::new(false),
+            channel_monitor: RefCell::new(None),
+            bridges: RefCell::new(None),
         };
         let _ = vm.add_ic(None);
         vm
     }
 
+    /// a new VM whose `rand` sequence is reproducible from `seed`
+    pub fn with_seed(seed: i32) -> Self {
+        let vm = Self::new();
+        vm.reseed(seed);
+        vm
+    }
+
+    pub fn unknown_prefab_access(&self) -> UnknownPrefabAccess {
+        self.unknown_prefab_access.get()
+    }
+
+    pub fn set_unknown_prefab_access(&self, access: UnknownPrefabAccess) {
+        self.unknown_prefab_access.set(access);
+    }
+
+    pub fn power_simulation(&self) -> bool {
+        self.power_simulation.get()
+    }
+
+    /// turn the power simulation on or off, turning it off powers every connected device again
+    pub fn set_power_simulation(&self, enabled: bool) {
+        self.power_simulation.set(enabled);
+        if !enabled {
+            for network in self.networks.values() {
+                network.borrow_mut().power = PowerState::default();
+            }
+        }
+    }
+
+    /// restart the VM's random source from `seed`
+    pub fn reseed(&self, seed: i32) {
+        self.random
+            .replace(crate::rand_mscorlib::Random::with_seed(seed));
+    }
+
     fn new_device(&mut self) -> Device {
         Device::new(self.id_space.next())
     }
@@ -97,6 +202,16 @@ impl VM {
         self.random.borrow_mut().next_f64()
     }
 
+    /// the number of game ticks the VM has been run for
+    pub fn clock(&self) -> u64 {
+        self.clock.get()
+    }
+
+    /// the simulated time in seconds the VM has been run for
+    pub fn game_time(&self) -> f64 {
+        self.clock.get() as f64 / TICKS_PER_SECOND
+    }
+
     pub fn add_device(&mut self, network: Option<u32>) -> Result<u32, VMError> {
         if let Some(n) = &network {
             if !self.networks.contains_key(n) {
@@ -221,6 +336,7 @@ impl VM {
         // use those ids or fail
         self.id_space.use_ids(&to_use_ids)?;
 
+        let wireless_link = template.wireless_link;
         let device = Device::from_template(template, || self.id_space.next());
         let device_id: u32 = device.id;
 
@@ -230,31 +346,42 @@ impl VM {
             self.ics.insert(*ic_id, Rc::new(RefCell::new(chip)));
         }
 
-        device.connections.iter().for_each(|conn| {
-            if let Connection::CableNetwork {
-                net: Some(net),
-                typ,
-            } = conn
-            {
-                if let Some(network) = self.networks.get(net) {
-                    match typ {
-                        CableConnectionType::Power => {
-                            network.borrow_mut().add_power(device_id);
-                        }
-                        _ => {
-                            network.borrow_mut().add_data(device_id);
-                        }
-                    }
-                }
-            }
-        });
-
         self.devices
             .insert(device_id, Rc::new(RefCell::new(device)));
+        if wireless_link.is_some() {
+            if let Err(err) = self.set_wireless_link(device_id, wireless_link) {
+                self.remove_device(device_id)?;
+                return Err(err);
+            }
+        }
+        let rebuilt = self.sync_network_membership(device_id);
+        self.reset_channels(&rebuilt);
+        self.attach_prefab_behavior(device_id);
 
         Ok(device_id)
     }
 
+    /// add a device with the fields, access, slots and connections of a prefab, its first data
+    /// connection is put on `network` or the default network
+    pub fn add_device_by_prefab(
+        &mut self,
+        name: &str,
+        network: Option<u32>,
+    ) -> Result<u32, VMError> {
+        let prefab = prefabs::get(name).ok_or_else(|| VMError::UnknownPrefab(name.to_owned()))?;
+        let mut template = prefab.template();
+        if let Some(net) = template.connections.iter_mut().find_map(|conn| match conn {
+            Connection::CableNetwork {
+                net,
+                typ: CableConnectionType::Data | CableConnectionType::PowerAndData,
+            } => Some(net),
+            _ => None,
+        }) {
+            *net = Some(network.unwrap_or(self.default_network));
+        }
+        self.add_device_from_template(template)
+    }
+
     pub fn add_network(&mut self) -> u32 {
         let next_id = self.network_id_space.next();
         self.networks
@@ -270,6 +397,11 @@ impl VM {
         self.networks.get(&id).cloned()
     }
 
+    /// supply and demand on a network as of the last tick
+    pub fn get_network_power(&self, id: u32) -> Option<PowerState> {
+        self.networks.get(&id).map(|network| network.borrow().power)
+    }
+
     pub fn remove_ic(&mut self, id: u32) {
         if self.ics.remove(&id).is_some() {
             self.devices.remove(&id);
@@ -295,13 +427,22 @@ impl VM {
                 }
             });
         });
-        self.networks.iter().for_each(|(_net_id, net)| {
-            if let Ok(mut net_ref) = net.try_borrow_mut() {
-                if net_ref.devices.remove(&old_id) {
-                    net_ref.devices.insert(new_id);
-                }
+        self.sync_network_membership(old_id);
+        self.sync_network_membership(new_id);
+        self.unlink_wireless(old_id, Some(new_id));
+        {
+            let mut behaviors = self.behaviors.borrow_mut();
+            if let Some(mut behavior) = behaviors.remove(&old_id) {
+                behavior.set_device(new_id);
+                behaviors.insert(new_id, behavior);
             }
-        });
+            for behavior in behaviors.values_mut() {
+                behavior.replace_link(old_id, Some(new_id));
+            }
+        }
+        self.stimuli.borrow_mut().change_device(old_id, new_id);
+        self.watchpoints.borrow_mut().change_device(old_id, new_id);
+        self.history.borrow_mut().change_device(old_id, new_id);
         self.id_space.free_id(old_id);
         Ok(())
     }
@@ -322,6 +463,7 @@ impl VM {
         let new_prog = interpreter::Program::try_from_code(code)?;
         ic.program.replace(new_prog);
         ic.code.replace(code.to_string());
+        self.clear_history();
         Ok(true)
     }
 
@@ -341,6 +483,7 @@ impl VM {
         let new_prog = interpreter::Program::from_code_with_invalid(code);
         ic.program.replace(new_prog);
         ic.code.replace(code.to_string());
+        self.clear_history();
         Ok(true)
     }
 
@@ -363,9 +506,19 @@ impl VM {
             .get(&ic_id)
             .ok_or(VMError::UnknownIcId(ic_id))?
             .clone();
+        if ic.borrow().has_caught_fire() {
+            return Err(VMError::HasCaughtFire(id));
+        }
+        // an unpowered ic wouldn't run in game, so it can't be stepped either
+        if !self.housing_powered(id) {
+            return Err(VMError::Unpowered(id));
+        }
         ic.borrow().ic.replace(0);
-        let result = ic.borrow().step(self, advance_ip_on_err)?;
-        Ok(result)
+        let (result, watch_hit) = self.step_recorded(&ic.borrow(), advance_ip_on_err);
+        if let Some(hit) = watch_hit {
+            self.watchpoint_hits.borrow_mut().push(hit);
+        }
+        Ok(result?)
     }
 
     /// returns true if executed 128 lines, false if returned early.
@@ -378,18 +531,60 @@ impl VM {
             .get(&ic_id)
             .ok_or(VMError::UnknownIcId(ic_id))?
             .clone();
-        ic.borrow().ic.replace(0);
+        if ic.borrow().has_caught_fire() {
+            return Err(VMError::HasCaughtFire(id));
+        }
+        if !self.housing_powered(id) {
+            return Err(VMError::Unpowered(id));
+        }
         self.set_modified(id);
-        for _i in 0..128 {
-            if let Err(err) = ic.borrow().step(self, ignore_errors) {
+        self.execute_ic(&ic, ignore_errors, false)
+    }
+
+    /// runs up to 128 lines of an ic, returns true if executed 128 lines, false if returned early.
+    ///
+    /// if `halt_at_end` is set the ic stops once it's instruction pointer runs off the end of
+    /// it's program instead of raising an error, as it would in game
+    fn execute_ic(
+        &self,
+        ic: &Rc<RefCell<interpreter::IC>>,
+        ignore_errors: bool,
+        halt_at_end: bool,
+    ) -> Result<bool, VMError> {
+        ic.borrow().ic.replace(0);
+        for i in 0..LINES_PER_TICK {
+            if halt_at_end && ic.borrow().is_finished() {
+                return Ok(false);
+            }
+            let hit = ic.borrow().check_breakpoints(self, i == 0);
+            if let Some(hit) = hit {
+                ic.borrow()
+                    .state
+                    .replace(interpreter::ICState::Breakpoint(hit));
+                return Ok(false);
+            }
+            let (result, watch_hit) = self.step_recorded(&ic.borrow(), ignore_errors);
+            // the line that errored may still have made the access
+            if let Some(hit) = watch_hit {
+                self.watchpoint_hits.borrow_mut().push(hit);
+            }
+            if let Err(err) = result {
                 if !ignore_errors {
                     return Err(err.into());
                 }
             }
-            if let interpreter::ICState::Yield = *ic.borrow().state.borrow() {
+            if let Some(hit) = watch_hit {
+                // a yield or sleep on the same line still takes effect
+                let ic_ref = ic.borrow();
+                let mut state = ic_ref.state.borrow_mut();
+                if let interpreter::ICState::Running = *state {
+                    *state = interpreter::ICState::Watchpoint(hit);
+                }
                 return Ok(false);
-            } else if let interpreter::ICState::Sleep(_then, _sleep_for) =
-                *ic.borrow().state.borrow()
+            }
+            if let interpreter::ICState::Yield
+            | interpreter::ICState::Sleep(_, _)
+            | interpreter::ICState::HasCaughtFire = *ic.borrow().state.borrow()
             {
                 return Ok(false);
             }
@@ -398,6 +593,531 @@ impl VM {
         Ok(true)
     }
 
+    /// Advance every housed IC by one game tick, then advance the VM clock.
+    ///
+    /// ICs are updated in ascending order of their housing's id, each running for up to 128
+    /// lines or until it yields or sleeps. ICs that are still asleep, have finished their
+    /// program, or are in an error state are skipped. An error raised by an IC only stops that
+    /// IC (it is left in `ICState::Error`), the rest of the tick still runs.
+    pub fn tick(&self) -> Result<TickResult, VMError> {
+        self.operation_modified.borrow_mut().clear();
+        let mut result = TickResult::default();
+        self.tick_ics(&mut result)?;
+        result.modified = self.last_operation_modified_sorted();
+        Ok(result)
+    }
+
+    /// Advance the VM by `ticks` game ticks, see [`VM::tick`].
+    ///
+    /// As the clock is simulated this can be used to fast forward a sleeping script.
+    /// Stops early after a tick in which an ic hit a breakpoint or watchpoint.
+    pub fn run_ticks(&self, ticks: u32) -> Result<TickResult, VMError> {
+        self.operation_modified.borrow_mut().clear();
+        let mut result = TickResult::default();
+        for _ in 0..ticks {
+            self.tick_ics(&mut result)?;
+            if result.is_paused() {
+                break;
+            }
+        }
+        result.modified = self.last_operation_modified_sorted();
+        Ok(result)
+    }
+
+    /// runs every ic for one tick, collecting the breakpoints and watchpoints hit
+    fn tick_ics(&self, result: &mut TickResult) -> Result<(), VMError> {
+        let housings = self
+            .devices
+            .iter()
+            .filter_map(|(id, device)| device.borrow().ic.map(|ic_id| (*id, ic_id)))
+            .collect_vec();
+        self.apply_stimuli();
+        self.tick_behaviors();
+        if self.power_simulation() {
+            power::tick(self);
+        }
+        let mut breakpoints = Vec::new();
+        self.watchpoint_hits.borrow_mut().clear();
+        for (id, ic_id) in housings {
+            let ic = self
+                .ics
+                .get(&ic_id)
+                .ok_or(VMError::UnknownIcId(ic_id))?
+                .clone();
+            if !ic.borrow().should_run_tick(self.clock()) || !self.housing_powered(id) {
+                continue;
+            }
+            self.set_modified(id);
+            // errors are recorded in the ic's state, they don't stop the other ics
+            let result = self.execute_ic(&ic, false, true);
+            // only game ticks are profiled, manual runs would add several entries per clock
+            let ic_ref = ic.borrow();
+            if let Some(profile) = ic_ref.profile.borrow_mut().as_mut() {
+                profile.record_tick(self.clock(), ic_ref.ic.get(), matches!(result, Ok(true)));
+            }
+            let state = ic.borrow().state.borrow().clone();
+            if let interpreter::ICState::Breakpoint(hit) = state {
+                breakpoints.push(hit);
+            }
+        }
+        self.clock.set(self.clock.get() + 1);
+        result.breakpoints = breakpoints;
+        result.watchpoints = self.watchpoint_hits.take();
+        Ok(())
+    }
+
+    /// ics in unpowered housings don't run, unless power isn't simulated
+    fn housing_powered(&self, id: u32) -> bool {
+        if !self.power_simulation() {
+            return true;
+        }
+        self.devices.get(&id).is_some_and(|device| {
+            let device = device.borrow();
+            !device.has_power_state() || device.is_powered(self)
+        })
+    }
+
+    /// record a device field access for the watchpoints, does nothing unless recording
+    pub fn record_access(&self, access: Access) {
+        self.accesses.borrow_mut().record(access);
+    }
+
+    /// step `ic` once, recording the accesses it makes if there are watchpoints set, the step
+    /// history is enabled or the ic is being traced
+    fn step_recorded(
+        &self,
+        ic: &interpreter::IC,
+        advance_ip_on_err: bool,
+    ) -> (Result<bool, LineError>, Option<WatchpointHit>) {
+        if let Some(profile) = ic.profile.borrow_mut().as_mut() {
+            profile.record_line(ic.ip());
+        }
+        let watching = self.watchpoints.borrow().is_watching();
+        let recording = self.history.borrow().is_enabled();
+        let tracing = ic.trace.borrow().is_some();
+        if !watching && !recording && !tracing {
+            return (ic.step(self, advance_ip_on_err), None);
+        }
+        let line = ic.ip();
+        let delta = recording.then(|| self.step_delta(ic));
+        let entry = if tracing { self.trace_entry(ic) } else { None };
+        ic.accesses.borrow_mut().start();
+        self.accesses.borrow_mut().start();
+        let result = ic.step(self, advance_ip_on_err);
+        let mut accesses = ic.accesses.borrow_mut().take();
+        accesses.extend(self.accesses.borrow_mut().take());
+
+        let watch_hit = self
+            .watchpoints
+            .borrow()
+            .check(&accesses)
+            .map(|(watchpoint, access)| WatchpointHit {
+                watchpoint,
+                device: ic.device,
+                ic: ic.id,
+                line,
+                access,
+            });
+        if let Some(mut entry) = entry {
+            entry.writes = accesses
+                .iter()
+                .filter(|access| access.kind == AccessKind::Write)
+                .copied()
+                .collect();
+            entry.error = result.as_ref().err().map(ToString::to_string);
+            if let Some(trace) = ic.trace.borrow_mut().as_mut() {
+                trace.entries.push(entry);
+            }
+        }
+        if let Some(mut delta) = delta {
+            delta.set_writes(accesses);
+            self.history.borrow_mut().push(delta);
+        }
+        (result, watch_hit)
+    }
+
+    /// the line `ic` is about to execute with its operands resolved
+    fn trace_entry(&self, ic: &interpreter::IC) -> Option<TraceEntry> {
+        let program = ic.program.borrow();
+        let line = program.get_line(ic.ip()).ok()?;
+        let operands = line
+            .operands
+            .iter()
+            .enumerate()
+            .map(|(index, operand)| {
+                TraceOperand::resolve(operand, ic, line.instruction, index as u32 + 1)
+            })
+            .collect();
+        Some(TraceEntry {
+            clock: self.clock(),
+            line: ic.ip(),
+            instruction: line.instruction,
+            operands,
+            writes: Vec::new(),
+            error: None,
+        })
+    }
+
+    /// start recording a trace of the lines run by the ic in device `id`, discarding any
+    /// trace already recorded
+    pub fn start_trace(&self, id: u32) -> Result<(), VMError> {
+        let ic = self.get_ic_for_device(id)?;
+        ic.borrow().trace.replace(Some(Trace::new(id)));
+        Ok(())
+    }
+
+    /// stop tracing the ic in device `id` returning the recorded trace
+    pub fn stop_trace(&self, id: u32) -> Result<Option<Trace>, VMError> {
+        let ic = self.get_ic_for_device(id)?;
+        let trace = ic.borrow().trace.take();
+        Ok(trace)
+    }
+
+    /// start profiling the ic in device `id`, discarding any profile already recorded
+    pub fn start_profile(&self, id: u32) -> Result<(), VMError> {
+        let ic = self.get_ic_for_device(id)?;
+        ic.borrow().profile.replace(Some(Profile::new(id)));
+        Ok(())
+    }
+
+    /// stop profiling the ic in device `id` returning the recorded profile
+    pub fn stop_profile(&self, id: u32) -> Result<Option<Profile>, VMError> {
+        let ic = self.get_ic_for_device(id)?;
+        let profile = ic.borrow().profile.take();
+        Ok(profile)
+    }
+
+    /// the profile recorded so far for the ic in device `id`, if it is being profiled
+    pub fn get_profile(&self, id: u32) -> Result<Option<Profile>, VMError> {
+        let ic = self.get_ic_for_device(id)?;
+        let profile = ic.borrow().profile.borrow().clone();
+        Ok(profile)
+    }
+
+    /// the trace recorded so far for the ic in device `id`, if it is being traced
+    pub fn get_trace(&self, id: u32) -> Result<Option<Trace>, VMError> {
+        let ic = self.get_ic_for_device(id)?;
+        let trace = ic.borrow().trace.borrow().clone();
+        Ok(trace)
+    }
+
+    /// the state `ic` is in before it executes its next line
+    fn step_delta(&self, ic: &interpreter::IC) -> StepDelta {
+        let instruction = ic
+            .program
+            .borrow()
+            .get_line(ic.ip())
+            .map(|line| line.instruction)
+            .ok();
+        StepDelta {
+            device: ic.device,
+            ic: ic.id,
+            clock: self.clock(),
+            ip: ic.ip(),
+            ic_count: ic.ic.get(),
+            state: ic.state.borrow().clone(),
+            writes: Vec::new(),
+            aliases: matches!(instruction, Some(InstructionOp::Alias))
+                .then(|| ic.aliases.borrow().clone()),
+            defines: matches!(instruction, Some(InstructionOp::Define))
+                .then(|| ic.defines.borrow().clone()),
+            random: matches!(instruction, Some(InstructionOp::Rand))
+                .then(|| self.random.borrow().clone()),
+        }
+    }
+
+    /// the number of steps kept for [`VM::step_back`], 0 turns recording off
+    pub fn set_history_size(&self, size: usize) {
+        self.history.borrow_mut().set_capacity(size);
+    }
+
+    pub fn history_size(&self) -> usize {
+        self.history.borrow().capacity()
+    }
+
+    /// the recorded steps, oldest first
+    pub fn get_history(&self) -> Vec<HistoryStep> {
+        self.history.borrow().steps()
+    }
+
+    pub fn clear_history(&self) {
+        self.history.borrow_mut().clear();
+    }
+
+    /// Undo the last recorded step of any ic, returns the step undone.
+    ///
+    /// Only changes made by executing lines are recorded, edits made from outside a script
+    /// (eg. setting a register or device field by hand) are not undone.
+    pub fn step_back(&self) -> Result<Option<HistoryStep>, VMError> {
+        self.operation_modified.borrow_mut().clear();
+        let step = self.undo_step()?;
+        Ok(step)
+    }
+
+    /// Undo up to `steps` recorded steps, returns the number of steps undone.
+    pub fn rewind(&self, steps: usize) -> Result<usize, VMError> {
+        self.operation_modified.borrow_mut().clear();
+        for undone in 0..steps {
+            if self.undo_step()?.is_none() {
+                return Ok(undone);
+            }
+        }
+        Ok(steps)
+    }
+
+    fn undo_step(&self) -> Result<Option<HistoryStep>, VMError> {
+        let Some(delta) = self.history.borrow_mut().pop() else {
+            return Ok(None);
+        };
+        for write in delta.writes.iter().rev() {
+            match write.target {
+                WatchTarget::Register { device, index } => {
+                    let ic = self.get_ic_for_device(device)?;
+                    let ic_ref = ic.borrow();
+                    ic_ref.registers.borrow_mut()[index as usize] = write.old;
+                }
+                WatchTarget::Stack { device, address } => {
+                    let ic = self.get_ic_for_device(device)?;
+                    let ic_ref = ic.borrow();
+                    ic_ref.stack.borrow_mut()[address as usize] = write.old;
+                }
+                WatchTarget::Field { device, field } => {
+                    let device_ref = self
+                        .devices
+                        .get(&device)
+                        .ok_or(VMError::UnknownId(device))?;
+                    device_ref
+                        .borrow_mut()
+                        .set_field(field, write.old, self, true)?;
+                    self.set_modified(device);
+                }
+                WatchTarget::SlotField {
+                    device,
+                    slot,
+                    field,
+                } => {
+                    let device_ref = self
+                        .devices
+                        .get(&device)
+                        .ok_or(VMError::UnknownId(device))?;
+                    device_ref.borrow_mut().set_slot_field(
+                        slot as f64,
+                        field,
+                        write.old,
+                        self,
+                        true,
+                    )?;
+                    self.set_modified(device);
+                }
+                WatchTarget::Channel { network, channel } => {
+                    self.set_network_channel(network, channel as usize, write.old)?;
+                }
+            }
+        }
+        let ic = self
+            .ics
+            .get(&delta.ic)
+            .ok_or(VMError::UnknownIcId(delta.ic))?
+            .clone();
+        let ic_ref = ic.borrow();
+        ic_ref.set_ip(delta.ip);
+        ic_ref.ic.set(delta.ic_count);
+        ic_ref.state.replace(delta.state);
+        if let Some(aliases) = delta.aliases {
+            ic_ref.aliases.replace(aliases);
+        }
+        if let Some(defines) = delta.defines {
+            ic_ref.defines.replace(defines);
+        }
+        if let Some(random) = delta.random {
+            self.random.replace(random);
+        }
+        ic_ref.propgate_line_number(self);
+        self.clock.set(delta.clock);
+        self.set_modified(delta.device);
+        Ok(Some(HistoryStep {
+            device: delta.device,
+            ic: delta.ic,
+            clock: delta.clock,
+            line: delta.ip,
+        }))
+    }
+
+    /// drive a device field from a waveform each tick, returns the stimulus' id
+    pub fn add_stimulus(&self, stimulus: Stimulus) -> Result<u32, VMError> {
+        let device = self
+            .devices
+            .get(&stimulus.target.device())
+            .ok_or(VMError::UnknownId(stimulus.target.device()))?;
+        if let StimulusTarget::SlotField { slot, .. } = stimulus.target {
+            if slot >= device.borrow().slots.len() {
+                return Err(ICError::SlotIndexOutOfRange(slot as f64).into());
+            }
+        }
+        stimulus.waveform.validate()?;
+        Ok(self.stimuli.borrow_mut().add(stimulus))
+    }
+
+    pub fn remove_stimulus(&self, stimulus: u32) -> bool {
+        self.stimuli.borrow_mut().remove(stimulus).is_some()
+    }
+
+    pub fn clear_stimuli(&self) {
+        self.stimuli.borrow_mut().clear();
+    }
+
+    pub fn get_stimuli(&self) -> BTreeMap<u32, Stimulus> {
+        self.stimuli
+            .borrow()
+            .iter()
+            .map(|(id, stimulus)| (*id, stimulus.clone()))
+            .collect()
+    }
+
+    /// set stimulus driven fields for the coming tick, before any ic runs
+    fn apply_stimuli(&self) {
+        if self.stimuli.borrow().is_empty() {
+            return;
+        }
+        let values = self.stimuli.borrow_mut().sample(self.clock());
+        for (target, val) in values {
+            let Some(device) = self.devices.get(&target.device()) else {
+                continue;
+            };
+            // read only fields like ReferenceId can't be driven and are skipped
+            let _ = match target {
+                StimulusTarget::Field { field, .. } => {
+                    device.borrow_mut().set_field(field, val, self, true)
+                }
+                StimulusTarget::SlotField { slot, field, .. } => device
+                    .borrow_mut()
+                    .set_slot_field(slot as f64, field, val, self, true),
+            };
+            self.set_modified(target.device());
+        }
+    }
+
+    /// attach a behavior to its device, replacing the one it had
+    pub fn set_behavior(&self, mut behavior: Box<dyn Behavior>) -> Result<(), VMError> {
+        let device = behavior.device();
+        if !self.devices.contains_key(&device) {
+            return Err(VMError::UnknownId(device));
+        }
+        if let Some(link) = behavior
+            .links()
+            .into_iter()
+            .find(|link| !self.devices.contains_key(link))
+        {
+            return Err(VMError::UnknownId(link));
+        }
+        behavior.attach(self);
+        self.behaviors.borrow_mut().insert(device, behavior);
+        Ok(())
+    }
+
+    pub fn set_builtin_behavior(&self, behavior: BuiltinBehavior) -> Result<(), VMError> {
+        self.set_behavior(behavior.into_behavior())
+    }
+
+    pub fn remove_behavior(&self, device: u32) -> bool {
+        self.behaviors.borrow_mut().remove(&device).is_some()
+    }
+
+    pub fn has_behavior(&self, device: u32) -> bool {
+        self.behaviors.borrow().contains_key(&device)
+    }
+
+    /// set the devices a behavior reads from or acts on, like a heater's rooms or a logic
+    /// unit's inputs
+    pub fn link_behavior(&self, device: u32, links: &[u32]) -> Result<(), VMError> {
+        if let Some(link) = links.iter().find(|link| !self.devices.contains_key(link)) {
+            return Err(VMError::UnknownId(*link));
+        }
+        let mut behaviors = self.behaviors.borrow_mut();
+        let behavior = behaviors
+            .get_mut(&device)
+            .ok_or(VMError::NoBehavior(device))?;
+        behavior.set_links(links);
+        Ok(())
+    }
+
+    /// the built-in behaviors attached to devices, keyed by device id
+    pub fn get_behaviors(&self) -> BTreeMap<u32, BuiltinBehavior> {
+        self.behaviors
+            .borrow()
+            .iter()
+            .filter_map(|(id, behavior)| Some((*id, behavior.freeze()?)))
+            .collect()
+    }
+
+    /// attach the built-in behavior for the device's prefab, if it has one
+    fn attach_prefab_behavior(&self, id: u32) {
+        let builtin = self.devices.get(&id).and_then(|device| {
+            let device = device.borrow();
+            BuiltinBehavior::for_prefab(&device.prefab.as_ref()?.name, id)
+        });
+        if let Some(builtin) = builtin {
+            let _ = self.set_builtin_behavior(builtin);
+        }
+    }
+
+    /// run device behaviors for the coming tick, after the stimuli and before any ic
+    fn tick_behaviors(&self) {
+        // behaviors may reach back into the VM, so don't hold the borrow while they run
+        let mut behaviors = self.behaviors.take();
+        for behavior in behaviors.values_mut() {
+            behavior.tick(self);
+        }
+        let added = self.behaviors.replace(behaviors);
+        self.behaviors.borrow_mut().extend(added);
+    }
+
+    /// set a watchpoint, returns the watchpoint's id
+    pub fn add_watchpoint(&self, watchpoint: Watchpoint) -> u32 {
+        self.watchpoints.borrow_mut().add(watchpoint)
+    }
+
+    pub fn remove_watchpoint(&self, watchpoint: u32) -> bool {
+        self.watchpoints.borrow_mut().remove(watchpoint).is_some()
+    }
+
+    pub fn set_watchpoint_enabled(&self, watchpoint: u32, enabled: bool) -> bool {
+        if let Some(watchpoint) = self.watchpoints.borrow_mut().get_mut(watchpoint) {
+            watchpoint.enabled = enabled;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// the watchpoints hit by ics run or stepped since the last tick, including on lines that
+    /// errored
+    pub fn watchpoint_hits(&self) -> Vec<WatchpointHit> {
+        self.watchpoint_hits.borrow().clone()
+    }
+
+    pub fn clear_watchpoints(&self) {
+        self.watchpoints.borrow_mut().clear();
+    }
+
+    pub fn get_watchpoints(&self) -> BTreeMap<u32, Watchpoint> {
+        self.watchpoints
+            .borrow()
+            .iter()
+            .map(|(id, watchpoint)| (*id, watchpoint.clone()))
+            .collect()
+    }
+
+    fn last_operation_modified_sorted(&self) -> Vec<u32> {
+        self.operation_modified
+            .borrow()
+            .iter()
+            .copied()
+            .sorted()
+            .dedup()
+            .collect_vec()
+    }
+
     pub fn set_modified(&self, id: u32) {
         self.operation_modified.borrow_mut().push(id);
     }
@@ -412,9 +1132,69 @@ impl VM {
             .clone();
         ic.borrow().ic.replace(0);
         ic.borrow().reset();
+        self.clear_history();
         Ok(true)
     }
 
+    /// the ic in the housing `id`
+    pub fn get_ic_for_device(&self, id: u32) -> Result<Rc<RefCell<interpreter::IC>>, VMError> {
+        let device = self.devices.get(&id).ok_or(VMError::UnknownId(id))?;
+        let ic_id = *device.borrow().ic.as_ref().ok_or(VMError::NoIC(id))?;
+        Ok(self
+            .ics
+            .get(&ic_id)
+            .ok_or(VMError::UnknownIcId(ic_id))?
+            .clone())
+    }
+
+    /// set a breakpoint on the ic in device `id`, returns the breakpoint's id
+    pub fn add_breakpoint(&self, id: u32, breakpoint: Breakpoint) -> Result<u32, VMError> {
+        let ic = self.get_ic_for_device(id)?;
+        let bp_id = ic.borrow().breakpoints.borrow_mut().add(breakpoint);
+        Ok(bp_id)
+    }
+
+    pub fn remove_breakpoint(&self, id: u32, breakpoint: u32) -> Result<bool, VMError> {
+        let ic = self.get_ic_for_device(id)?;
+        let removed = ic.borrow().breakpoints.borrow_mut().remove(breakpoint);
+        Ok(removed.is_some())
+    }
+
+    pub fn set_breakpoint_enabled(
+        &self,
+        id: u32,
+        breakpoint: u32,
+        enabled: bool,
+    ) -> Result<bool, VMError> {
+        let ic = self.get_ic_for_device(id)?;
+        let ic_ref = ic.borrow();
+        let mut breakpoints = ic_ref.breakpoints.borrow_mut();
+        if let Some(breakpoint) = breakpoints.get_mut(breakpoint) {
+            breakpoint.enabled = enabled;
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    pub fn clear_breakpoints(&self, id: u32) -> Result<(), VMError> {
+        let ic = self.get_ic_for_device(id)?;
+        ic.borrow().breakpoints.borrow_mut().clear();
+        Ok(())
+    }
+
+    pub fn get_breakpoints(&self, id: u32) -> Result<BTreeMap<u32, Breakpoint>, VMError> {
+        let ic = self.get_ic_for_device(id)?;
+        let breakpoints = ic
+            .borrow()
+            .breakpoints
+            .borrow()
+            .iter()
+            .map(|(id, breakpoint)| (*id, breakpoint.clone()))
+            .collect();
+        Ok(breakpoints)
+    }
+
     pub fn get_device(&self, id: u32) -> Option<Rc<RefCell<Device>>> {
         self.devices.get(&id).cloned()
     }
@@ -425,6 +1205,7 @@ impl VM {
         prefab_hash: f64,
         name: Option<f64>,
     ) -> impl Iterator<Item = &Rc<RefCell<Device>>> {
+        let reach = self.data_networks(source);
         self.devices
             .iter()
             .filter(move |(id, device)| {
@@ -435,59 +1216,213 @@ impl VM {
                     .is_some_and(|f| f.value == prefab_hash)
                     && (name.is_none()
                         || name == device.borrow().name_hash.as_ref().map(|hash| *hash as f64))
-                    && self.devices_on_same_network(&[source, **id])
+                    && reach
+                        .iter()
+                        .any(|net| self.networks[net].borrow().contains_data(id))
             })
             .map(|(_, d)| d)
     }
 
+    /// `other` if it's on one of `source`'s data networks or wirelessly linked to one of them
     pub fn get_device_same_network(&self, source: u32, other: u32) -> Option<Rc<RefCell<Device>>> {
-        if self.devices_on_same_network(&[source, other]) {
+        if self.devices_on_same_network(&[source, other]) || self.wirelessly_visible(source, other)
+        {
             self.get_device(other)
         } else {
             None
         }
     }
 
+    /// link a wireless receiver, like a logic transmitter, to a wireless device so the devices on
+    /// the receiver's networks can see it
+    pub fn set_wireless_link(&self, id: u32, target: Option<u32>) -> Result<(), VMError> {
+        let device = self.devices.get(&id).ok_or(VMError::UnknownId(id))?;
+        if !device.borrow().is_wireless_receiver() {
+            return Err(VMError::NotWirelessReceiver(id));
+        }
+        if let Some(target) = target {
+            let other = self
+                .devices
+                .get(&target)
+                .ok_or(VMError::UnknownId(target))?;
+            if !other.borrow().is_wireless_transmitter() {
+                return Err(VMError::NotWirelessTransmitter(target));
+            }
+        }
+        device.borrow_mut().set_wireless_link(target);
+        self.set_modified(id);
+        Ok(())
+    }
+
+    /// point wireless links to `id` at `new_id` instead, or drop them
+    fn unlink_wireless(&self, id: u32, new_id: Option<u32>) {
+        for device in self.devices.values() {
+            let mut device = device.borrow_mut();
+            if device.wireless_link() == Some(id) {
+                device.set_wireless_link(new_id);
+            }
+        }
+    }
+
+    /// the wireless devices linked to receivers on `source`'s data networks, receivers that lost
+    /// power drop their link
+    fn wireless_links(&self, source: u32) -> Vec<u32> {
+        let reach = self.data_networks(source);
+        self.devices
+            .iter()
+            .filter_map(|(id, device)| {
+                let device = device.borrow();
+                let link = device.wireless_link()?;
+                let powered = !self.power_simulation()
+                    || !device.has_power_state()
+                    || device.is_powered(self);
+                let on_network = reach
+                    .iter()
+                    .any(|net| self.networks[net].borrow().contains_data(id));
+                (powered && on_network).then_some(link)
+            })
+            .collect()
+    }
+
+    fn wirelessly_visible(&self, source: u32, other: u32) -> bool {
+        self.wireless_links(source).contains(&other)
+    }
+
     pub fn get_network_channel(&self, id: u32, channel: usize) -> Result<f64, ICError> {
         let network = self.networks.get(&id).ok_or(ICError::BadNetworkId(id))?;
         if !(0..8).contains(&channel) {
             Err(ICError::ChannelIndexOutOfRange(channel))
         } else {
-            Ok(network.borrow().channels[channel])
+            let val = network.borrow().channels[channel];
+            self.record_access(Access::read(
+                WatchTarget::Channel {
+                    network: id,
+                    channel: channel as u32,
+                },
+                val,
+            ));
+            Ok(val)
         }
     }
 
     pub fn set_network_channel(&self, id: u32, channel: usize, val: f64) -> Result<(), ICError> {
+        self.write_network_channel(id, channel, val, None)
+    }
+
+    /// set a channel on network `id`, logging the write against `ic` if the channel monitor
+    /// is running
+    pub(crate) fn write_network_channel(
+        &self,
+        id: u32,
+        channel: usize,
+        val: f64,
+        ic: Option<u32>,
+    ) -> Result<(), ICError> {
         let network = self.networks.get(&(id)).ok_or(ICError::BadNetworkId(id))?;
         if !(0..8).contains(&channel) {
             Err(ICError::ChannelIndexOutOfRange(channel))
         } else {
-            network.borrow_mut().channels[channel] = val;
+            let old = std::mem::replace(&mut network.borrow_mut().channels[channel], val);
+            self.record_access(Access::write(
+                WatchTarget::Channel {
+                    network: id,
+                    channel: channel as u32,
+                },
+                old,
+                val,
+            ));
+            if let Some(monitor) = self.channel_monitor.borrow_mut().as_mut() {
+                monitor.push(ChannelWrite {
+                    clock: self.clock.get(),
+                    network: id,
+                    channel: channel as u32,
+                    ic,
+                    old,
+                    value: val,
+                });
+            }
             Ok(())
         }
     }
 
+    /// start logging network channel writes, keeping the last `capacity`, discarding any writes
+    /// already logged
+    pub fn start_channel_monitor(&self, capacity: usize) {
+        self.channel_monitor
+            .replace(Some(ChannelMonitor::new(capacity)));
+    }
+
+    /// stop logging channel writes returning those logged
+    pub fn stop_channel_monitor(&self) -> Option<Vec<ChannelWrite>> {
+        self.channel_monitor.take().map(|monitor| monitor.writes())
+    }
+
+    /// the channel writes logged so far, if the monitor is running
+    pub fn channel_writes(&self) -> Option<Vec<ChannelWrite>> {
+        self.channel_monitor
+            .borrow()
+            .as_ref()
+            .map(|monitor| monitor.writes())
+    }
+
+    /// every device can see the others over data networks, directly or through data bridges
     pub fn devices_on_same_network(&self, ids: &[u32]) -> bool {
-        for net in self.networks.values() {
-            if net.borrow().contains_all_data(ids) {
-                return true;
-            }
+        let Some((first, rest)) = ids.split_first() else {
+            return false;
+        };
+        let reach = self.data_networks(*first);
+        !reach.is_empty()
+            && rest.iter().all(|id| {
+                reach
+                    .iter()
+                    .any(|net| self.networks[net].borrow().contains_data(id))
+            })
+    }
+
+    /// the networks device `id` is on for data, and those joined to them by data bridges
+    pub fn data_networks(&self, id: u32) -> BTreeSet<u32> {
+        let nets = self
+            .networks
+            .iter()
+            .filter(|(_, net)| net.borrow().contains_data(&id))
+            .map(|(net_id, _)| *net_id)
+            .collect();
+        self.bridged_networks(nets, true)
+    }
+
+    /// the networks sharing power with `net` through power bridges, like transformers
+    pub fn power_grid(&self, net: u32) -> BTreeSet<u32> {
+        self.bridged_networks(BTreeSet::from([net]), false)
+    }
+
+    /// `nets` and the networks joined to them by the active bridges passing data, or power
+    fn bridged_networks(&self, nets: BTreeSet<u32>, data: bool) -> BTreeSet<u32> {
+        if self.bridges.borrow().is_none() {
+            let topology = BridgeTopology::new(self.devices.values().filter_map(|device| {
+                let device = device.borrow();
+                let (data, _) = device.network_membership();
+                Some((device.active_bridge()?, data, device.power_networks()))
+            }));
+            self.bridges.replace(Some(topology));
         }
-        false
+        self.bridges.borrow().as_ref().unwrap().reach(nets, data)
+    }
+
+    /// drop the cached bridge topology, for when a bridge's connections or `On` change
+    pub(crate) fn bridges_changed(&self) {
+        self.bridges.replace(None);
     }
 
     /// return a vecter with the device ids the source id can see via it's connected networks
     pub fn visible_devices(&self, source: u32) -> Vec<u32> {
-        self.networks
-            .values()
-            .filter_map(|net| {
-                if net.borrow().contains_data(&source) {
-                    Some(net.borrow().data_visible(&source))
-                } else {
-                    None
-                }
-            })
-            .concat()
+        let mut visible = self
+            .data_networks(source)
+            .iter()
+            .flat_map(|net| self.networks[net].borrow().devices().clone())
+            .filter(|id| *id != source)
+            .collect::<BTreeSet<_>>();
+        visible.extend(self.wireless_links(source));
+        visible.into_iter().collect()
     }
 
     pub fn set_pin(&self, id: u32, pin: usize, val: Option<u32>) -> Result<bool, VMError> {
@@ -498,7 +1433,9 @@ impl VM {
             if !self.devices.contains_key(&other_device) {
                 return Err(VMError::UnknownId(other_device));
             }
-            if !self.devices_on_same_network(&[id, other_device]) {
+            if !self.devices_on_same_network(&[id, other_device])
+                && !self.wirelessly_visible(id, other_device)
+            {
                 return Err(VMError::DeviceNotVisible(other_device, id));
             }
         }
@@ -527,89 +1464,139 @@ impl VM {
             return Err(ICError::ConnectionIndexOutOfRange(connection, conn_len).into());
         }
 
+        if let Some(target_net) = target_net {
+            if !self.networks.contains_key(&target_net) {
+                return Err(VMError::InvalidNetwork(target_net));
+            }
+        }
         {
-            // scope this borrow
-            let connections = &device.borrow().connections;
-            let Connection::CableNetwork { net, typ } = &connections[connection] else {
+            let mut device_ref = device.borrow_mut();
+            let Connection::CableNetwork { ref mut net, .. } = device_ref.connections[connection]
+            else {
                 return Err(ICError::NotACableConnection(connection).into());
             };
-            // remove from current network
-            if let Some(net) = net {
-                if let Some(network) = self.networks.get(net) {
-                    // if there is no other connection to this network
-                    if connections
-                        .iter()
-                        .filter(|conn| {
-                            matches!(conn, Connection::CableNetwork {
-                                net: Some(other_net),
-                                typ: other_typ
-                            } if other_net == net && (
-                                !matches!(typ,  CableConnectionType::Power) ||
-                                matches!(other_typ, CableConnectionType::Data | CableConnectionType::PowerAndData))
-                            )
-                        })
-                        .count()
-                        == 1
-                    {
-                        match typ {
-                            CableConnectionType::Power => {
-                                network.borrow_mut().remove_power(id);
-                            }
-                            _ => {
-                                network.borrow_mut().remove_data(id);
-
-                            }
-                        }
-                    }
-                }
-            }
+            *net = target_net;
         }
-        let mut device_ref = device.borrow_mut();
-        let connections = &mut device_ref.connections;
-        let Connection::CableNetwork {
-            ref mut net,
-            ref typ,
-        } = connections[connection]
-        else {
-            return Err(ICError::NotACableConnection(connection).into());
+        let rebuilt = self.sync_network_membership(id);
+        self.reset_channels(&rebuilt);
+        Ok(true)
+    }
+
+    pub fn remove_device_from_network(&self, id: u32, network_id: u32) -> Result<bool, VMError> {
+        if !self.networks.contains_key(&network_id) {
+            return Err(VMError::InvalidNetwork(network_id));
+        }
+        let Some(device) = self.devices.get(&id) else {
+            return Err(VMError::UnknownId(id));
         };
-        if let Some(target_net) = target_net {
-            if let Some(network) = self.networks.get(&target_net) {
-                match typ {
-                    CableConnectionType::Power => {
-                        network.borrow_mut().add_power(id);
-                    }
-                    _ => {
-                        network.borrow_mut().add_data(id);
-                    }
+        for conn in device.borrow_mut().connections.iter_mut() {
+            if let Connection::CableNetwork { net, .. } = conn {
+                if net.is_some_and(|id| id == network_id) {
+                    *net = None;
                 }
-            } else {
-                return Err(VMError::InvalidNetwork(target_net));
             }
         }
-        *net = target_net;
+        let rebuilt = self.sync_network_membership(id);
+        self.reset_channels(&rebuilt);
         Ok(true)
     }
 
-    pub fn remove_device_from_network(&self, id: u32, network_id: u32) -> Result<bool, VMError> {
-        if let Some(network) = self.networks.get(&network_id) {
-            let Some(device) = self.devices.get(&id) else {
-                return Err(VMError::UnknownId(id));
-            };
-            let mut device_ref = device.borrow_mut();
+    /// make every network's members match the connections of device `id`, removing it from all
+    /// of them if it's gone, returns the networks it joined or left as a data member
+    fn sync_network_membership(&self, id: u32) -> Vec<u32> {
+        let (data, power) = self
+            .devices
+            .get(&id)
+            .map(|device| device.borrow().network_membership())
+            .unwrap_or_default();
+        // the device may be, or have been, a bridge
+        self.bridges_changed();
+        let mut changed = Vec::new();
+        for (net_id, network) in &self.networks {
+            let mut network = network.borrow_mut();
+            let was = network.contains_data(&id);
+            network.remove_all(id);
+            if data.contains(net_id) {
+                network.add_data(id);
+            } else if power.contains(net_id) {
+                network.add_power(id);
+            }
+            if was != network.contains_data(&id) {
+                changed.push(*net_id);
+            }
+        }
+        changed
+    }
+
+    /// unset the channels of `nets`, as the game does when a device joins or leaves a data
+    /// network and it's rebuilt
+    fn reset_channels(&self, nets: &[u32]) {
+        for net in nets {
+            if let Some(network) = self.networks.get(net) {
+                network.borrow_mut().reset_channels();
+            }
+        }
+    }
 
-            for conn in device_ref.connections.iter_mut() {
-                if let Connection::CableNetwork { net, .. } = conn {
-                    if net.is_some_and(|id| id == network_id) {
-                        *net = None;
-                    }
+    /// every disagreement between device connections and network members, empty if they match
+    pub fn check_invariants(&self) -> Vec<NetworkMismatch> {
+        let mut mismatches = Vec::new();
+        let mut expected: BTreeMap<(u32, u32), Membership> = BTreeMap::new();
+        for (id, device) in &self.devices {
+            let (data, power) = device.borrow().network_membership();
+            let memberships = data
+                .into_iter()
+                .map(|net| (net, Membership::Data))
+                .chain(power.into_iter().map(|net| (net, Membership::PowerOnly)));
+            for (network, membership) in memberships {
+                if self.networks.contains_key(&network) {
+                    expected.insert((network, *id), membership);
+                } else {
+                    mismatches.push(NetworkMismatch::UnknownNetwork {
+                        device: *id,
+                        network,
+                    });
                 }
             }
-            network.borrow_mut().remove_all(id);
-            Ok(true)
-        } else {
-            Err(VMError::InvalidNetwork(network_id))
         }
+        for (net_id, network) in &self.networks {
+            let network = network.borrow();
+            let members = network
+                .devices()
+                .iter()
+                .map(|id| (*id, Membership::Data))
+                .chain(
+                    network
+                        .power_only()
+                        .iter()
+                        .map(|id| (*id, Membership::PowerOnly)),
+                )
+                .sorted();
+            for (device, membership) in members {
+                if expected.get(&(*net_id, device)) != Some(&membership) {
+                    mismatches.push(NetworkMismatch::StrayMember {
+                        device,
+                        network: *net_id,
+                        membership,
+                    });
+                }
+            }
+        }
+        for ((network, device), membership) in expected {
+            let net = self.networks[&network].borrow();
+            let member = match membership {
+                Membership::Data => net.contains_data(&device),
+                Membership::PowerOnly => net.contains_power(&device),
+            };
+            if !member {
+                mismatches.push(NetworkMismatch::MissingMember {
+                    device,
+                    network,
+                    membership,
+                });
+            }
+        }
+        mismatches
     }
 
     pub fn set_batch_device_field(
@@ -736,17 +1723,22 @@ impl VM {
         let Some(device) = self.devices.remove(&id) else {
             return Err(VMError::UnknownId(id));
         };
-
-        for conn in device.borrow().connections.iter() {
-            if let Connection::CableNetwork { net: Some(net), .. } = conn {
-                if let Some(network) = self.networks.get(net) {
-                    network.borrow_mut().remove_all(id);
-                }
-            }
-        }
+        let rebuilt = self.sync_network_membership(id);
+        self.reset_channels(&rebuilt);
+        self.unlink_wireless(id, None);
         if let Some(ic_id) = device.borrow().ic {
             let _ = self.ics.remove(&ic_id);
         }
+        self.stimuli.borrow_mut().remove_device(id);
+        // recorded steps may have written to the device, they can't be undone without it
+        self.clear_history();
+        {
+            let mut behaviors = self.behaviors.borrow_mut();
+            behaviors.remove(&id);
+            for behavior in behaviors.values_mut() {
+                behavior.replace_link(id, None);
+            }
+        }
         self.id_space.free_id(id);
         Ok(())
     }
@@ -811,13 +1803,28 @@ impl VM {
                 .map(|network| network.borrow().into())
                 .collect(),
             default_network: self.default_network,
+            clock: self.clock.get(),
+            random: Some(self.random.borrow().clone()),
+            behaviors: self.get_behaviors().into_values().collect(),
+            power_simulation: self.power_simulation(),
+            unknown_prefab_access: self.unknown_prefab_access(),
         }
     }
 
-    pub fn restore_vm_state(&mut self, state: FrozenVM) -> Result<(), VMError> {
+    /// restore a saved state, returning where the saved network members disagreed with the
+    /// device connections, the members are rebuilt from the connections
+    pub fn restore_vm_state(&mut self, state: FrozenVM) -> Result<Vec<NetworkMismatch>, VMError> {
+        // a bad link would otherwise fail the restore half way through
+        check_wireless_links(&state.devices)?;
+        self.clear_history();
         self.ics.clear();
         self.devices.clear();
         self.networks.clear();
+        self.behaviors.borrow_mut().clear();
+        // saves don't keep stimuli or watchpoints, they'd hit whatever reuses their device ids
+        self.clear_stimuli();
+        self.clear_watchpoints();
+        self.bridges_changed();
         self.id_space.reset();
         self.network_id_space.reset();
 
@@ -847,11 +1854,16 @@ impl VM {
             .into_iter()
             .map(|ic| (ic.id, Rc::new(RefCell::new(ic.into()))))
             .collect();
+        let mut wireless_links = Vec::new();
         self.devices = state
             .devices
             .into_iter()
             .map(|template| {
+                let link = template.wireless_link;
                 let device = Device::from_template(template, || self.id_space.next());
+                if link.is_some() {
+                    wireless_links.push((device.id, link));
+                }
                 (device.id, Rc::new(RefCell::new(device)))
             })
             .collect();
@@ -860,8 +1872,75 @@ impl VM {
             .into_iter()
             .map(|network| (network.id, Rc::new(RefCell::new(network.into()))))
             .collect();
+        // connections are what the devices go by, saved member lists may have drifted from them
+        let mismatches = self.check_invariants();
+        for id in self.devices.keys().copied().collect_vec() {
+            self.sync_network_membership(id);
+        }
+        for (id, link) in wireless_links {
+            self.set_wireless_link(id, link)?;
+        }
         self.default_network = state.default_network;
-        Ok(())
+        self.clock.set(state.clock);
+        for ic in self.ics.values() {
+            ic.borrow().restart_wall_clock_sleep(state.clock);
+        }
+        if let Some(random) = state.random {
+            self.random.replace(random);
+        }
+        self.power_simulation.set(state.power_simulation);
+        self.unknown_prefab_access.set(state.unknown_prefab_access);
+        for behavior in state.behaviors {
+            self.set_builtin_behavior(behavior)?;
+        }
+        // states saved before behaviors existed get the defaults
+        for id in self.devices.keys().copied().collect_vec() {
+            if !self.has_behavior(id) {
+                self.attach_prefab_behavior(id);
+            }
+        }
+        Ok(mismatches)
+    }
+}
+
+/// check the saved wireless links go from receivers to transmitters in the same save
+fn check_wireless_links(templates: &[DeviceTemplate]) -> Result<(), VMError> {
+    let prefab = |template: &DeviceTemplate| template.prefab_name.as_deref().and_then(prefabs::get);
+    for template in templates {
+        let Some(target) = template.wireless_link else {
+            continue;
+        };
+        if !prefab(template).is_some_and(|prefab| prefab.receiver) {
+            return Err(VMError::NotWirelessReceiver(
+                template.id.unwrap_or_default(),
+            ));
+        }
+        let other = templates
+            .iter()
+            .find(|other| other.id == Some(target))
+            .ok_or(VMError::UnknownId(target))?;
+        if !prefab(other).is_some_and(|prefab| prefab.transmitter) {
+            return Err(VMError::NotWirelessTransmitter(target));
+        }
+    }
+    Ok(())
+}
+
+/// The outcome of advancing the VM one or more game ticks
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct TickResult {
+    /// ids of the devices touched while ticking, in ascending order
+    pub modified: Vec<u32>,
+    /// breakpoints hit on the last tick run
+    pub breakpoints: Vec<BreakpointHit>,
+    /// watchpoints hit on the last tick run
+    pub watchpoints: Vec<WatchpointHit>,
+}
+
+impl TickResult {
+    /// true if an ic was stopped by a breakpoint or watchpoint
+    pub fn is_paused(&self) -> bool {
+        !self.breakpoints.is_empty() || !self.watchpoints.is_empty()
     }
 }
 
@@ -871,6 +1950,16 @@ pub struct FrozenVM {
     pub devices: Vec<DeviceTemplate>,
     pub networks: Vec<FrozenNetwork>,
     pub default_network: u32,
+    #[serde(default)]
+    pub clock: u64,
+    #[serde(default)]
+    pub random: Option<crate::rand_mscorlib::Random>,
+    #[serde(default)]
+    pub behaviors: Vec<BuiltinBehavior>,
+    #[serde(default)]
+    pub power_simulation: bool,
+    #[serde(default)]
+    pub unknown_prefab_access: UnknownPrefabAccess,
 }
 
 impl BatchMode {
@@ -963,3 +2052,831 @@ impl IdSpace {
         self.in_use.clear();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::Bridge;
+    use crate::watch::{AccessKind, WatchOn, WatchTarget};
+
+    #[test]
+    fn tick_runs_ics_in_order() -> Result<(), VMError> {
+        let mut vm = VM::new();
+        let master = vm.add_ic(None)?;
+        let slave = vm.add_ic(None)?;
+        vm.set_pin(master, 0, Some(slave))?;
+        vm.set_code(
+            master,
+            "move r0 0\nloop:\nadd r0 r0 1\ns d0 Setting r0\nyield\nj loop",
+        )?;
+        vm.set_code(slave, "loop:\nl r1 db Setting\nyield\nj loop")?;
+        let slave_ic = vm.devices[&slave].borrow().ic.unwrap();
+
+        let result = vm.tick()?;
+        assert_eq!(result.modified, vec![master, slave]);
+        // the master runs first so the slave sees it's write in the same tick
+        assert_eq!(vm.ics[&slave_ic].borrow().get_register(0, 1)?, 1.0);
+
+        vm.run_ticks(2)?;
+        assert_eq!(vm.ics[&slave_ic].borrow().get_register(0, 1)?, 3.0);
+        Ok(())
+    }
+
+    #[test]
+    fn network_membership() -> Result<(), VMError> {
+        let mut vm = VM::new();
+        let default = vm.default_network;
+        let power = vm.add_network();
+        let housing = vm.add_ic(None)?;
+        vm.set_device_connection(housing, 1, Some(power))?;
+        assert!(vm.networks[&power].borrow().contains_power(&housing));
+        vm.set_device_connection(housing, 1, Some(default))?;
+        assert!(!vm.networks[&power].borrow().contains(&housing));
+        assert!(!vm.networks[&default].borrow().contains_power(&housing));
+        vm.remove_device_from_network(housing, default)?;
+        assert!(!vm.networks[&default].borrow().contains(&housing));
+        vm.set_device_connection(housing, 0, Some(default))?;
+        vm.change_device_id(housing, 100)?;
+        assert!(vm.networks[&default].borrow().contains_data(&100));
+        assert!(vm.check_invariants().is_empty());
+
+        vm.networks[&power].borrow_mut().add_data(100);
+        vm.networks[&default].borrow_mut().remove_all(100);
+        vm.devices[&100].borrow_mut().connections[1] = Connection::CableNetwork {
+            net: Some(7),
+            typ: CableConnectionType::Power,
+        };
+        let mismatches = vm.check_invariants();
+        assert_eq!(
+            mismatches,
+            vec![
+                NetworkMismatch::UnknownNetwork {
+                    device: 100,
+                    network: 7
+                },
+                NetworkMismatch::StrayMember {
+                    device: 100,
+                    network: power,
+                    membership: Membership::Data
+                },
+                NetworkMismatch::MissingMember {
+                    device: 100,
+                    network: default,
+                    membership: Membership::Data
+                },
+            ]
+        );
+
+        // restoring reports the drift and rebuilds the member lists from the connections
+        vm.devices[&100].borrow_mut().connections[1] = Connection::CableNetwork {
+            net: None,
+            typ: CableConnectionType::Power,
+        };
+        let state = vm.save_vm_state();
+        assert_eq!(vm.restore_vm_state(state)?, mismatches[1..]);
+        assert!(vm.check_invariants().is_empty());
+        vm.remove_device(100)?;
+        assert!(!vm.networks[&default].borrow().contains(&100));
+        Ok(())
+    }
+
+    #[test]
+    fn wireless_link() -> Result<(), VMError> {
+        let mut vm = VM::new();
+        let robot = vm.add_device_by_prefab("Robot", None)?;
+        let transmitter = vm.add_device_by_prefab("StructureLogicTransmitter", None)?;
+        let housing = vm.add_ic(None)?;
+        assert!(matches!(
+            vm.set_pin(housing, 0, Some(robot)),
+            Err(VMError::DeviceNotVisible(..))
+        ));
+        assert!(matches!(
+            vm.set_wireless_link(housing, Some(robot)),
+            Err(VMError::NotWirelessReceiver(_))
+        ));
+        assert!(matches!(
+            vm.set_wireless_link(transmitter, Some(housing)),
+            Err(VMError::NotWirelessTransmitter(_))
+        ));
+        vm.set_wireless_link(transmitter, Some(robot))?;
+        assert!(vm.visible_devices(housing).contains(&robot));
+        vm.set_pin(housing, 0, Some(robot))?;
+        vm.devices[&robot]
+            .borrow_mut()
+            .set_field(LogicType::PositionX, 12.0, &vm, true)?;
+        vm.set_code(housing, "l r0 d0 PositionX")?;
+        vm.step_ic(housing, false)?;
+        let ic = vm.devices[&housing].borrow().ic.unwrap();
+        assert_eq!(vm.ics[&ic].borrow().get_register(0, 0)?, 12.0);
+
+        let state = vm.save_vm_state();
+        vm.restore_vm_state(state)?;
+        assert_eq!(
+            vm.devices[&transmitter].borrow().wireless_link(),
+            Some(robot)
+        );
+
+        // links from templates and saves are checked like any other
+        let mut template: DeviceTemplate = vm.devices[&transmitter].borrow().into();
+        template.id = None;
+        template.wireless_link = Some(housing);
+        let devices = vm.devices.len();
+        assert!(matches!(
+            vm.add_device_from_template(template),
+            Err(VMError::NotWirelessTransmitter(_))
+        ));
+        assert_eq!(vm.devices.len(), devices);
+        // a restore with a bad link fails before it touches the vm
+        vm.tick()?;
+        let saved = |vm: &VM| serde_json::to_string(&vm.save_vm_state()).unwrap();
+        let before = saved(&vm);
+        let mut state = vm.save_vm_state();
+        for template in &mut state.devices {
+            if template.id == Some(transmitter) {
+                template.wireless_link = Some(housing);
+            }
+        }
+        assert!(matches!(
+            vm.restore_vm_state(state),
+            Err(VMError::NotWirelessTransmitter(_))
+        ));
+        assert_eq!(saved(&vm), before);
+        vm.remove_device(transmitter)?;
+        assert!(vm.get_device_same_network(housing, robot).is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn add_stimulus_rejects_bad_waveforms() -> Result<(), VMError> {
+        use crate::stimulus::Waveform;
+
+        let mut vm = VM::new();
+        let device = vm.add_device(None)?;
+        let target = StimulusTarget::Field {
+            device,
+            field: LogicType::Setting,
+        };
+        for waveform in [
+            Waveform::RandomWalk {
+                start: 0.0,
+                step: 1.0,
+                min: 5.0,
+                max: 1.0,
+                seed: 0,
+            },
+            Waveform::RandomWalk {
+                start: 0.0,
+                step: 1.0,
+                min: f64::NAN,
+                max: 1.0,
+                seed: 0,
+            },
+            Waveform::Sine {
+                offset: 0.0,
+                amplitude: 1.0,
+                period: 0.0,
+                phase: 0.0,
+            },
+        ] {
+            assert!(matches!(
+                vm.add_stimulus(Stimulus::new(target, waveform)),
+                Err(VMError::StimulusError(_))
+            ));
+        }
+        assert!(vm.get_stimuli().is_empty());
+        vm.run_ticks(1)?;
+        Ok(())
+    }
+
+    #[test]
+    fn change_device_id_moves_behaviors() -> Result<(), VMError> {
+        use crate::behavior::LogicMath;
+        use crate::stimulus::Waveform;
+
+        let mut vm = VM::new();
+        let a = vm.add_device(None)?;
+        let b = vm.add_device(None)?;
+        let math = vm.add_device(None)?;
+        vm.set_builtin_behavior(BuiltinBehavior::LogicMath(LogicMath {
+            device: math,
+            inputs: [Some(a), Some(b)],
+        }))?;
+        let target = StimulusTarget::Field {
+            device: a,
+            field: LogicType::Setting,
+        };
+        vm.add_stimulus(Stimulus::new(
+            target,
+            Waveform::Steps {
+                steps: vec![(0, 1.0)],
+            },
+        ))?;
+        vm.add_watchpoint(Watchpoint::new(
+            WatchTarget::Field {
+                device: a,
+                field: LogicType::Setting,
+            },
+            WatchOn::Write,
+        ));
+
+        vm.change_device_id(a, 100)?;
+        vm.change_device_id(math, 101)?;
+        let behaviors = vm.get_behaviors();
+        assert_eq!(
+            behaviors.get(&101),
+            Some(&BuiltinBehavior::LogicMath(LogicMath {
+                device: 101,
+                inputs: [Some(100), Some(b)],
+            }))
+        );
+        assert_eq!(vm.get_stimuli()[&0].target.device(), 100);
+        assert_eq!(
+            vm.get_watchpoints()[&0].target,
+            WatchTarget::Field {
+                device: 100,
+                field: LogicType::Setting
+            }
+        );
+        let state = vm.save_vm_state();
+        vm.restore_vm_state(state)?;
+        assert_eq!(vm.get_behaviors(), behaviors);
+        // stimuli and watchpoints aren't saved, so restoring drops them
+        assert!(vm.get_stimuli().is_empty());
+        assert!(vm.get_watchpoints().is_empty());
+
+        // removing an input unlinks it without moving the other one
+        vm.remove_device(100)?;
+        assert_eq!(
+            vm.get_behaviors()[&101],
+            BuiltinBehavior::LogicMath(LogicMath {
+                device: 101,
+                inputs: [None, Some(b)],
+            })
+        );
+        let state = vm.save_vm_state();
+        vm.restore_vm_state(state)?;
+        Ok(())
+    }
+
+    #[test]
+    fn bridged_networks() -> Result<(), VMError> {
+        let mut vm = VM::new();
+        let first = vm.default_network;
+        let second = vm.add_network();
+        let housing = vm.add_ic(None)?;
+        let sensor = vm.add_device_by_prefab("StructureGasSensor", Some(second))?;
+        let hash = vm.devices[&sensor]
+            .borrow()
+            .get_field(LogicType::PrefabHash, &vm)?;
+
+        // a transformer carries power between the networks but not data
+        let transformer = vm.add_device_by_prefab("StructureTransformer", Some(first))?;
+        vm.set_device_connection(transformer, 1, Some(first))?;
+        vm.set_device_connection(transformer, 2, Some(second))?;
+        assert_eq!(vm.power_grid(second), BTreeSet::from([first, second]));
+        assert!(!vm.visible_devices(housing).contains(&sensor));
+        assert_eq!(vm.batch_device(housing, hash, None).count(), 0);
+
+        let relay = vm.add_device_from_template(DeviceTemplate {
+            connections: vec![
+                Connection::CableNetwork {
+                    net: Some(first),
+                    typ: CableConnectionType::Data,
+                },
+                Connection::CableNetwork {
+                    net: Some(second),
+                    typ: CableConnectionType::Data,
+                },
+            ],
+            bridge: Some(Bridge::Data),
+            ..Default::default()
+        })?;
+        assert!(vm.visible_devices(housing).contains(&sensor));
+        assert!(vm.devices_on_same_network(&[housing, sensor]));
+        assert_eq!(vm.batch_device(housing, hash, None).count(), 1);
+        // a relay that's turned off isolates the networks again
+        vm.devices[&relay]
+            .borrow_mut()
+            .set_field(LogicType::On, 0.0, &vm, true)?;
+        assert!(vm.get_device_same_network(housing, sensor).is_none());
+        vm.devices[&relay]
+            .borrow_mut()
+            .set_field(LogicType::On, 1.0, &vm, true)?;
+        assert!(vm.get_device_same_network(housing, sensor).is_some());
+        // and so does unplugging it
+        vm.set_device_connection(relay, 1, None)?;
+        assert!(!vm.visible_devices(housing).contains(&sensor));
+        Ok(())
+    }
+
+    #[test]
+    fn tick_skips_finished_and_errored_ics() -> Result<(), VMError> {
+        let mut vm = VM::new();
+        let finished = vm.add_ic(None)?;
+        let errored = vm.add_ic(None)?;
+        vm.set_code(finished, "move r0 1")?;
+        vm.set_code(errored, "pop r0")?;
+        let finished_ic = vm.devices[&finished].borrow().ic.unwrap();
+        let errored_ic = vm.devices[&errored].borrow().ic.unwrap();
+
+        vm.tick()?;
+        assert!(vm.ics[&finished_ic].borrow().is_finished());
+        assert!(matches!(
+            *vm.ics[&errored_ic].borrow().state.borrow(),
+            interpreter::ICState::Error(_)
+        ));
+
+        let result = vm.tick()?;
+        assert!(result.modified.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn sleep_uses_game_clock() -> Result<(), VMError> {
+        let mut vm = VM::new();
+        let ic = vm.add_ic(None)?;
+        vm.set_code(ic, "sleep 30\nmove r0 1")?;
+        let ic_id = vm.devices[&ic].borrow().ic.unwrap();
+
+        vm.tick()?;
+        assert!(matches!(
+            *vm.ics[&ic_id].borrow().state.borrow(),
+            interpreter::ICState::Sleep(0, _)
+        ));
+
+        // sleeping survives a save and restore
+        let state = vm.save_vm_state();
+        let mut vm = VM::new();
+        vm.restore_vm_state(state)?;
+        assert_eq!(vm.clock(), 1);
+
+        vm.run_ticks(58)?;
+        assert_eq!(vm.ics[&ic_id].borrow().get_register(0, 0)?, 0.0);
+        vm.tick()?;
+        assert_eq!(vm.game_time(), 30.0);
+        assert_eq!(vm.ics[&ic_id].borrow().get_register(0, 0)?, 0.0);
+        vm.tick()?;
+        assert_eq!(vm.ics[&ic_id].borrow().get_register(0, 0)?, 1.0);
+        Ok(())
+    }
+
+    #[test]
+    fn wall_clock_sleep_restores() -> Result<(), VMError> {
+        let mut vm = VM::new();
+        let ic = vm.add_ic(None)?;
+        vm.set_code(ic, "sleep 30\nmove r0 1")?;
+        let ic_id = vm.devices[&ic].borrow().ic.unwrap();
+        vm.run_ticks(3)?;
+
+        // sleeps used to be saved with the time they started
+        let state = serde_json::to_string(&vm.save_vm_state()).unwrap().replace(
+            r#"{"Sleep":[0,30.0]}"#,
+            r#"{"Sleep":[[2024,100,12,30,15,0,0,0,0],30.0]}"#,
+        );
+        let state: FrozenVM = serde_json::from_str(&state).unwrap();
+        vm.restore_vm_state(state)?;
+        assert!(matches!(
+            *vm.ics[&ic_id].borrow().state.borrow(),
+            interpreter::ICState::Sleep(3, _)
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn seeded_random_is_reproducible() -> Result<(), VMError> {
+        let vm = VM::with_seed(1234);
+        let first = (0..4).map(|_| vm.random_f64()).collect_vec();
+        vm.reseed(1234);
+        let second = (0..4).map(|_| vm.random_f64()).collect_vec();
+        assert_eq!(first, second);
+
+        // the rng state is part of the saved vm
+        let state = vm.save_vm_state();
+        let expected = (0..4).map(|_| vm.random_f64()).collect_vec();
+        let mut restored = VM::new();
+        restored.restore_vm_state(state)?;
+        let actual = (0..4).map(|_| restored.random_f64()).collect_vec();
+        assert_eq!(expected, actual);
+        Ok(())
+    }
+
+    #[test]
+    fn breakpoints_stop_run_and_tick() -> Result<(), VMError> {
+        let mut vm = VM::new();
+        let ic = vm.add_ic(None)?;
+        vm.set_code(ic, "start:\nadd r0 r0 1\ns db Setting r0\nj start")?;
+        let ic_id = vm.devices[&ic].borrow().ic.unwrap();
+
+        let line_bp = vm.add_breakpoint(ic, Breakpoint::at_line(2))?;
+        assert!(!vm.run_ic(ic, false)?);
+        let state = vm.ics[&ic_id].borrow().state.borrow().clone();
+        let interpreter::ICState::Breakpoint(hit) = state else {
+            panic!("expected a breakpoint, got {state}");
+        };
+        assert_eq!(hit.breakpoint, line_bp);
+        assert_eq!(hit.line, 2);
+        assert_eq!(vm.ics[&ic_id].borrow().get_register(0, 0)?, 1.0);
+
+        // resuming runs past the breakpoint and stops on the next pass
+        assert!(!vm.run_ic(ic, false)?);
+        assert_eq!(vm.ics[&ic_id].borrow().get_register(0, 0)?, 2.0);
+        vm.remove_breakpoint(ic, line_bp)?;
+
+        let cond_bp = vm.add_breakpoint(
+            ic,
+            Breakpoint::at_label("start").with_condition("db Setting >= 5".parse()?),
+        )?;
+        let result = vm.run_ticks(10)?;
+        assert_eq!(
+            result.breakpoints,
+            vec![BreakpointHit {
+                device: ic,
+                ic: ic_id,
+                breakpoint: cond_bp,
+                line: 0
+            }]
+        );
+        assert_eq!(vm.ics[&ic_id].borrow().get_register(0, 0)?, 5.0);
+
+        vm.set_breakpoint_enabled(ic, cond_bp, false)?;
+        assert!(vm.tick()?.breakpoints.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn watchpoints_report_accesses() -> Result<(), VMError> {
+        let mut vm = VM::new();
+        let ic = vm.add_ic(None)?;
+        vm.set_code(
+            ic,
+            "move r0 0\nmove r1 3\nmove r1 3\ns db Setting r1\npush r1",
+        )?;
+        let ic_id = vm.devices[&ic].borrow().ic.unwrap();
+
+        let setting = vm.add_watchpoint(Watchpoint::new(
+            WatchTarget::Field {
+                device: ic,
+                field: LogicType::Setting,
+            },
+            WatchOn::Write,
+        ));
+        let r1 = vm.add_watchpoint(
+            Watchpoint::new(
+                WatchTarget::Register {
+                    device: ic,
+                    index: 1,
+                },
+                WatchOn::Write,
+            )
+            .on_change(),
+        );
+
+        assert!(!vm.run_ic(ic, false)?);
+        let state = vm.ics[&ic_id].borrow().state.borrow().clone();
+        let interpreter::ICState::Watchpoint(hit) = state else {
+            panic!("expected a watchpoint, got {state}");
+        };
+        assert_eq!((hit.watchpoint, hit.line, hit.ic), (r1, 1, ic_id));
+        assert_eq!((hit.access.old, hit.access.value), (0.0, 3.0));
+
+        // writing the same value again doesn't trigger an on change watchpoint
+        let result = vm.tick()?;
+        assert_eq!(result.watchpoints.len(), 1);
+        assert_eq!(result.watchpoints[0].watchpoint, setting);
+        assert_eq!(result.watchpoints[0].line, 3);
+
+        vm.clear_watchpoints();
+        vm.add_watchpoint(Watchpoint::new(
+            WatchTarget::Stack {
+                device: ic,
+                address: 0,
+            },
+            WatchOn::ReadWrite,
+        ));
+        let result = vm.tick()?;
+        assert_eq!(result.watchpoints[0].line, 4);
+        assert_eq!(result.watchpoints[0].access.kind, AccessKind::Write);
+
+        // writes into another chip's stack are seen as well
+        let other = vm.add_ic(None)?;
+        vm.clear_watchpoints();
+        vm.add_watchpoint(Watchpoint::new(
+            WatchTarget::Stack {
+                device: other,
+                address: 2,
+            },
+            WatchOn::Write,
+        ));
+        vm.set_code(ic, &format!("putd {other} 2 7"))?;
+        vm.reset_ic(ic)?;
+        let result = vm.tick()?;
+        assert_eq!(result.watchpoints.len(), 1);
+        assert_eq!(result.watchpoints[0].access.value, 7.0);
+
+        // a hit on a line that errors is still reported
+        vm.clear_watchpoints();
+        vm.add_watchpoint(Watchpoint::new(
+            WatchTarget::Register {
+                device: ic,
+                index: 1,
+            },
+            WatchOn::Read,
+        ));
+        vm.set_code(ic, "add r0 r1 d0")?;
+        vm.reset_ic(ic)?;
+        assert!(vm.run_ic(ic, false).is_err());
+        let hits = vm.watchpoint_hits();
+        assert_eq!(hits.len(), 1);
+        assert_eq!(
+            hits[0].access.target,
+            WatchTarget::Register {
+                device: ic,
+                index: 1
+            }
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn channel_monitor() -> Result<(), VMError> {
+        let mut vm = VM::new();
+        let net = vm.default_network;
+        let housing = vm.add_ic(None)?;
+        let ic = vm.devices[&housing].borrow().ic.unwrap();
+        vm.set_code(housing, "s db:0 Channel2 5\nyield\nl r0 db:0 Channel2")?;
+        vm.start_channel_monitor(8);
+        vm.run_ticks(2)?;
+        vm.set_network_channel(net, 7, 1.0)?;
+        assert_eq!(vm.ics[&ic].borrow().get_register(0, 0)?, 5.0);
+        let writes = vm.stop_channel_monitor().unwrap();
+        assert_eq!(writes.len(), 2);
+        assert_eq!(
+            (
+                writes[0].clock,
+                writes[0].channel,
+                writes[0].ic,
+                writes[0].value
+            ),
+            (0, 2, Some(ic), 5.0)
+        );
+        assert!(writes[0].old.is_nan());
+        assert_eq!((writes[1].clock, writes[1].ic), (2, None));
+
+        // saving and renumbering devices keeps the channels
+        vm.change_device_id(housing, 50)?;
+        let state = vm.save_vm_state();
+        vm.restore_vm_state(state)?;
+        assert_eq!(vm.get_network_channel(net, 2)?, 5.0);
+        // so does a device only drawing power from it
+        vm.add_device_from_template(DeviceTemplate {
+            connections: vec![Connection::CableNetwork {
+                net: Some(net),
+                typ: CableConnectionType::Power,
+            }],
+            ..Default::default()
+        })?;
+        assert_eq!(vm.get_network_channel(net, 2)?, 5.0);
+        // a device joining the network rebuilds it, unsetting every channel
+        vm.add_device(Some(net))?;
+        assert!(vm.networks[&net]
+            .borrow()
+            .channels
+            .iter()
+            .all(|chan| chan.is_nan()));
+        assert!(vm.channel_writes().is_none());
+
+        // the monitor keeps only the latest writes
+        vm.start_channel_monitor(1);
+        vm.set_network_channel(net, 0, 1.0)?;
+        vm.set_network_channel(net, 1, 2.0)?;
+        let writes = vm.channel_writes().unwrap();
+        assert_eq!((writes.len(), writes[0].channel), (1, 1));
+        Ok(())
+    }
+
+    #[test]
+    fn step_back_undoes_cross_chip_put() -> Result<(), VMError> {
+        let mut vm = VM::new();
+        let ic = vm.add_ic(None)?;
+        let other = vm.add_ic(None)?;
+        vm.set_pin(ic, 0, Some(other))?;
+        vm.set_code(ic, &format!("putd {other} 4 9\nput d0 5 3"))?;
+        let other_ic = vm.devices[&other].borrow().ic.unwrap();
+        vm.set_history_size(4);
+
+        vm.step_ic(ic, false)?;
+        vm.step_ic(ic, false)?;
+        let chip = vm.ics[&other_ic].clone();
+        assert_eq!(chip.borrow().peek_addr(4.0)?, 9.0);
+        assert_eq!(chip.borrow().peek_addr(5.0)?, 3.0);
+        assert_eq!(vm.rewind(2)?, 2);
+        assert_eq!(chip.borrow().peek_addr(4.0)?, 0.0);
+        assert_eq!(chip.borrow().peek_addr(5.0)?, 0.0);
+        Ok(())
+    }
+
+    #[test]
+    fn step_back_follows_renumbered_devices() -> Result<(), VMError> {
+        let mut vm = VM::new();
+        let housing = vm.add_ic(None)?;
+        let other = vm.add_device_by_prefab("StructureLogicMemory", None)?;
+        vm.set_pin(housing, 0, Some(other))?;
+        vm.set_code(housing, "s d0 Setting 5\nmove r0 7")?;
+        let ic = vm.devices[&housing].borrow().ic.unwrap();
+        vm.set_history_size(4);
+        vm.step_ic(housing, false)?;
+        vm.step_ic(housing, false)?;
+
+        vm.change_device_id(housing, 50)?;
+        vm.change_device_id(other, 60)?;
+        assert_eq!(vm.get_history()[0].device, 50);
+        assert_eq!(vm.rewind(2)?, 2);
+        assert_eq!(vm.ics[&ic].borrow().get_register(0, 0)?, 0.0);
+        assert_eq!(
+            vm.devices[&60]
+                .borrow()
+                .get_field(LogicType::Setting, &vm)?,
+            0.0
+        );
+
+        // steps that wrote to a removed device can't be undone, so they're dropped
+        vm.step_ic(50, false)?;
+        vm.remove_device(60)?;
+        assert!(vm.get_history().is_empty());
+        assert!(vm.step_back()?.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn step_back_undoes_steps() -> Result<(), VMError> {
+        let mut vm = VM::with_seed(42);
+        let ic = vm.add_ic(None)?;
+        vm.set_code(
+            ic,
+            "alias counter r0\nadd counter counter 1\npush counter\ns db Setting 7\nrand r1\ns db:0 Channel0 r1",
+        )?;
+        let ic_id = vm.devices[&ic].borrow().ic.unwrap();
+        vm.set_history_size(4);
+
+        for _ in 0..6 {
+            vm.step_ic(ic, false)?;
+        }
+        let chip = vm.ics[&ic_id].clone();
+        let first_rand = chip.borrow().get_register(0, 1)?;
+        assert_eq!(vm.get_history().len(), 4);
+        assert_eq!(vm.get_history()[0].line, 2);
+
+        let step = vm.step_back()?.unwrap();
+        assert_eq!((step.device, step.line), (ic, 5));
+        assert_eq!(
+            vm.get_network_channel(vm.default_network, 0)?.to_bits(),
+            f64::NAN.to_bits()
+        );
+        assert_eq!(vm.rewind(10)?, 3);
+        assert!(vm.step_back()?.is_none());
+
+        // back to just after line 1, only the history size worth of steps are undone
+        let chip_ref = chip.borrow();
+        assert_eq!(chip_ref.ip(), 2);
+        assert_eq!(chip_ref.get_register(0, 0)?, 1.0);
+        assert_eq!(chip_ref.get_register(0, 1)?, 0.0);
+        assert_eq!(chip_ref.get_register(0, 16)?, 0.0);
+        assert_eq!(chip_ref.peek_addr(0.0)?, 0.0);
+        assert!(chip_ref.aliases.borrow().contains_key("counter"));
+        drop(chip_ref);
+        assert_eq!(
+            vm.devices[&ic]
+                .borrow()
+                .get_field(LogicType::Setting, &vm)?,
+            0.0
+        );
+
+        // replaying gives the same random value
+        for _ in 0..4 {
+            vm.step_ic(ic, false)?;
+        }
+        assert_eq!(chip.borrow().get_register(0, 1)?, first_rand);
+        Ok(())
+    }
+
+    #[test]
+    fn trace_records_executed_lines() -> Result<(), VMError> {
+        let mut vm = VM::new();
+        let ic = vm.add_ic(None)?;
+        vm.set_code(ic, "move r0 2\nmul r1 r0 3\ns db Setting r1\npop r2")?;
+        vm.start_trace(ic)?;
+        for _ in 0..3 {
+            vm.step_ic(ic, false)?;
+        }
+        assert!(vm.step_ic(ic, false).is_err());
+        let trace = vm.stop_trace(ic)?.unwrap();
+
+        assert_eq!(trace.device, ic);
+        assert_eq!(trace.entries.len(), 4);
+        let mul = &trace.entries[1];
+        assert_eq!(mul.instruction, InstructionOp::Mul);
+        assert_eq!(
+            mul.operands,
+            vec![
+                TraceOperand::Register("r1".to_owned()),
+                TraceOperand::Value(2.0),
+                TraceOperand::Value(3.0)
+            ]
+        );
+        assert_eq!(mul.writes.len(), 1);
+        assert_eq!(mul.writes[0].value, 6.0);
+        assert_eq!(trace.entries[2].operands[0], TraceOperand::Device(ic));
+        assert_eq!(
+            trace.entries[2].operands[1],
+            TraceOperand::Enum {
+                name: "Setting".to_owned(),
+                value: 12.0
+            }
+        );
+        assert!(trace.entries[2].writes.iter().any(|write| write.target
+            == WatchTarget::Field {
+                device: ic,
+                field: LogicType::Setting
+            }));
+        assert!(trace.entries[3].error.is_some());
+
+        #[cfg(feature = "trace-export")]
+        {
+            let json = trace.to_json_lines().unwrap();
+            assert_eq!(json.lines().count(), 4);
+            let binary = trace.to_binary().unwrap();
+            assert_eq!(Trace::from_binary(&binary).unwrap(), trace);
+            assert!(Trace::from_binary(b"nope").is_err());
+
+            // unset values are written as null and read back as NaN
+            let json = serde_json::to_string(&TraceOperand::Value(f64::NAN)).unwrap();
+            assert_eq!(json, r#"{"Value":null}"#);
+            let TraceOperand::Value(val) = serde_json::from_str(&json).unwrap() else {
+                panic!("expected a value");
+            };
+            assert!(val.is_nan());
+        }
+
+        assert!(vm.get_trace(ic)?.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn profiler_counts_lines_and_budget() -> Result<(), VMError> {
+        let mut vm = VM::new();
+        let ic = vm.add_ic(None)?;
+        vm.set_code(ic, "start:\nadd r0 r0 1\nblt r0 50 start\nyield\nj start")?;
+        vm.start_profile(ic)?;
+        vm.run_ticks(3)?;
+        // running the ic by hand counts its lines but isn't a tick of its own
+        vm.run_ic(ic, false)?;
+        let profile = vm.stop_profile(ic)?.unwrap();
+
+        // the first tick runs out of budget in the loop, the second reaches the yield
+        let report = profile.report();
+        assert_eq!(report.ticks, 3);
+        assert_eq!(report.max_lines, LINES_PER_TICK);
+        assert_eq!(report.exhausted_ticks, vec![0]);
+        assert_eq!(profile.ticks[1].lines, 23);
+        assert_eq!(profile.ticks[2].lines, 5);
+        assert_eq!(report.line_hits[&3], 3);
+        assert_eq!(report.heatmap[&1], 1.0);
+        assert!(report.heatmap[&3] < 0.1);
+        assert!(vm.get_profile(ic)?.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn hcf_stops_ic_until_reset() -> Result<(), VMError> {
+        let mut vm = VM::new();
+        let ic = vm.add_ic(None)?;
+        vm.set_code(ic, "move r0 1\nhcf\nmove r0 2")?;
+        let ic_id = vm.devices[&ic].borrow().ic.unwrap();
+
+        assert!(!vm.run_ic(ic, false)?);
+        assert!(vm.ics[&ic_id].borrow().has_caught_fire());
+        assert_eq!(vm.ics[&ic_id].borrow().get_register(0, 0)?, 1.0);
+        assert_eq!(
+            vm.devices[&ic].borrow().get_field(LogicType::Error, &vm)?,
+            1.0
+        );
+        assert!(matches!(
+            vm.run_ic(ic, false),
+            Err(VMError::HasCaughtFire(id)) if id == ic
+        ));
+        assert!(matches!(
+            vm.step_ic(ic, false),
+            Err(VMError::HasCaughtFire(id)) if id == ic
+        ));
+        assert!(vm.tick()?.modified.is_empty());
+
+        vm.reset_ic(ic)?;
+        assert_eq!(
+            vm.devices[&ic].borrow().get_field(LogicType::Error, &vm)?,
+            0.0
+        );
+        vm.step_ic(ic, false)?;
+        assert_eq!(vm.ics[&ic_id].borrow().get_register(0, 0)?, 1.0);
+        Ok(())
+    }
+}
@@ -0,0 +1,239 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::grammar::{LogicType, SlotLogicType};
+
+/// a value an ic can read or write
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum WatchTarget {
+    /// register `index` of the ic housed in `device`
+    Register {
+        device: u32,
+        index: u32,
+    },
+    /// stack `address` of the ic housed in `device`
+    Stack {
+        device: u32,
+        address: u32,
+    },
+    Field {
+        device: u32,
+        field: LogicType,
+    },
+    SlotField {
+        device: u32,
+        slot: u32,
+        field: SlotLogicType,
+    },
+    Channel {
+        network: u32,
+        channel: u32,
+    },
+}
+
+impl WatchTarget {
+    /// point a target on device `old` at `new` instead
+    pub fn change_device(&mut self, old: u32, new: u32) {
+        match self {
+            WatchTarget::Register { device, .. }
+            | WatchTarget::Stack { device, .. }
+            | WatchTarget::Field { device, .. }
+            | WatchTarget::SlotField { device, .. }
+                if *device == old =>
+            {
+                *device = new;
+            }
+            _ => {}
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AccessKind {
+    Read,
+    Write,
+}
+
+/// a single read or write made by an ic, for reads `old` and `value` are the same
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Access {
+    pub target: WatchTarget,
+    pub kind: AccessKind,
+    #[serde(deserialize_with = "deserialize_nan")]
+    pub old: f64,
+    #[serde(deserialize_with = "deserialize_nan")]
+    pub value: f64,
+}
+
+/// json writes NaN out as `null`, read it back as NaN
+pub(crate) fn deserialize_nan<'de, D>(deserializer: D) -> Result<f64, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    if deserializer.is_human_readable() {
+        Ok(Option::<f64>::deserialize(deserializer)?.unwrap_or(f64::NAN))
+    } else {
+        f64::deserialize(deserializer)
+    }
+}
+
+impl Access {
+    pub fn read(target: WatchTarget, value: f64) -> Self {
+        Access {
+            target,
+            kind: AccessKind::Read,
+            old: value,
+            value,
+        }
+    }
+
+    pub fn write(target: WatchTarget, old: f64, value: f64) -> Self {
+        Access {
+            target,
+            kind: AccessKind::Write,
+            old,
+            value,
+        }
+    }
+
+    /// true for writes that changed the value, NaN is considered equal to NaN
+    pub fn changed(&self) -> bool {
+        self.kind == AccessKind::Write
+            && self.old != self.value
+            && !(self.old.is_nan() && self.value.is_nan())
+    }
+}
+
+/// collects accesses while recording is turned on
+#[derive(Debug, Default)]
+pub struct AccessLog {
+    accesses: Option<Vec<Access>>,
+}
+
+impl AccessLog {
+    pub fn start(&mut self) {
+        self.accesses = Some(Vec::new());
+    }
+
+    /// stop recording, returning what was recorded
+    pub fn take(&mut self) -> Vec<Access> {
+        self.accesses.take().unwrap_or_default()
+    }
+
+    pub fn record(&mut self, access: Access) {
+        if let Some(accesses) = self.accesses.as_mut() {
+            accesses.push(access);
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum WatchOn {
+    Read,
+    Write,
+    ReadWrite,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Watchpoint {
+    pub target: WatchTarget,
+    pub on: WatchOn,
+    /// only trigger on writes that change the value
+    pub on_change: bool,
+    pub enabled: bool,
+}
+
+impl Watchpoint {
+    pub fn new(target: WatchTarget, on: WatchOn) -> Self {
+        Watchpoint {
+            target,
+            on,
+            on_change: false,
+            enabled: true,
+        }
+    }
+
+    pub fn on_change(mut self) -> Self {
+        self.on_change = true;
+        self
+    }
+
+    pub fn is_hit(&self, access: &Access) -> bool {
+        self.enabled
+            && self.target == access.target
+            && matches!(
+                (self.on, access.kind),
+                (WatchOn::Read | WatchOn::ReadWrite, AccessKind::Read)
+                    | (WatchOn::Write | WatchOn::ReadWrite, AccessKind::Write)
+            )
+            && (!self.on_change || access.changed())
+    }
+}
+
+/// a watchpoint triggered by the ic in `device` executing `line`
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct WatchpointHit {
+    pub watchpoint: u32,
+    /// id of the ic's housing
+    pub device: u32,
+    pub ic: u32,
+    pub line: u32,
+    pub access: Access,
+}
+
+/// the watchpoints set on a VM, keyed by id
+#[derive(Debug, Default, Clone)]
+pub struct Watchpoints {
+    next_id: u32,
+    watchpoints: BTreeMap<u32, Watchpoint>,
+}
+
+impl Watchpoints {
+    pub fn add(&mut self, watchpoint: Watchpoint) -> u32 {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.watchpoints.insert(id, watchpoint);
+        id
+    }
+
+    pub fn remove(&mut self, id: u32) -> Option<Watchpoint> {
+        self.watchpoints.remove(&id)
+    }
+
+    pub fn get_mut(&mut self, id: u32) -> Option<&mut Watchpoint> {
+        self.watchpoints.get_mut(&id)
+    }
+
+    pub fn clear(&mut self) {
+        self.watchpoints.clear();
+    }
+
+    /// true if any watchpoint is enabled, accesses only need recording when this is set
+    pub fn is_watching(&self) -> bool {
+        self.watchpoints
+            .values()
+            .any(|watchpoint| watchpoint.enabled)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&u32, &Watchpoint)> {
+        self.watchpoints.iter()
+    }
+
+    /// point the watchpoints on `old` at its new id
+    pub fn change_device(&mut self, old: u32, new: u32) {
+        for watchpoint in self.watchpoints.values_mut() {
+            watchpoint.target.change_device(old, new);
+        }
+    }
+
+    /// the first watchpoint triggered by `accesses`, and the access that triggered it
+    pub fn check(&self, accesses: &[Access]) -> Option<(u32, Access)> {
+        accesses.iter().find_map(|access| {
+            self.watchpoints
+                .iter()
+                .find(|(_, watchpoint)| watchpoint.is_hit(access))
+                .map(|(id, _)| (*id, *access))
+        })
+    }
+}
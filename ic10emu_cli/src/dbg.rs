@@ -0,0 +1,52 @@
+use std::{
+    io::{BufRead, Write},
+    path::PathBuf,
+};
+
+use clap::Parser;
+use ic10emu_cli::{
+    load::{load_vm, LoadError},
+    repl::Repl,
+};
+
+/// Interactive debugger for ic10 scripts, type `help` for commands.
+#[derive(Debug, Parser)]
+#[command(name = "ic10dbg", version)]
+struct Args {
+    /// A .ic10 script, scenario or saved VM state to load on start
+    file: Option<PathBuf>,
+}
+
+fn main() -> Result<(), LoadError> {
+    let args = Args::parse();
+    let mut repl = match &args.file {
+        Some(file) => Repl::new(load_vm(file)?),
+        None => Repl::default(),
+    };
+    let stdin = std::io::stdin();
+    let mut stdout = std::io::stdout();
+    loop {
+        print!("(ic10) ");
+        let _ = stdout.flush();
+        let mut line = String::new();
+        match stdin.lock().read_line(&mut line) {
+            Ok(0) | Err(_) => break,
+            Ok(_) => {}
+        }
+        match repl.eval(&line) {
+            Ok(Some(out)) if out.is_empty() => {}
+            Ok(Some(out)) => println!("{out}"),
+            Ok(None) => break,
+            Err(err) => {
+                eprint!("error: {err}");
+                let mut source = std::error::Error::source(&err);
+                while let Some(cause) = source {
+                    eprint!(": {cause}");
+                    source = cause.source();
+                }
+                eprintln!();
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,3 @@
+pub mod load;
+pub mod repl;
+pub mod report;
@@ -0,0 +1,86 @@
+use std::path::{Path, PathBuf};
+
+use ic10emu::{
+    scenario::{Scenario, ScenarioError},
+    vm::{FrozenVM, VMError, VM},
+};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum LoadError {
+    #[error("failed to read {0}")]
+    Read(PathBuf, #[source] std::io::Error),
+    #[error("failed to parse saved vm state")]
+    State(#[from] serde_json::Error),
+    #[error(transparent)]
+    VM(#[from] VMError),
+    #[error(transparent)]
+    Scenario(#[from] ScenarioError),
+    #[error("the vm has no ic housing to load code into")]
+    NoHousing,
+}
+
+/// Build a VM from a `.ic10` script, a saved `FrozenVM` json file or a `.toml` scenario.
+///
+/// Files ending in `.json` are treated as saved VM state, `.toml` as a scenario and anything
+/// else as ic10 code
+pub fn load_vm(path: &Path) -> Result<VM, LoadError> {
+    if path.extension().is_some_and(|ext| ext == "toml") {
+        return Ok(Scenario::load(path)?.build()?);
+    }
+    let contents =
+        std::fs::read_to_string(path).map_err(|err| LoadError::Read(path.to_owned(), err))?;
+    if path.extension().is_some_and(|ext| ext == "json") {
+        vm_from_state(&contents)
+    } else {
+        vm_from_code(&contents)
+    }
+}
+
+/// a fresh VM with `code` loaded into its default ic housing
+pub fn vm_from_code(code: &str) -> Result<VM, LoadError> {
+    let vm = VM::new();
+    let housing = first_housing(&vm).ok_or(LoadError::NoHousing)?;
+    vm.set_code(housing, code)?;
+    Ok(vm)
+}
+
+pub fn vm_from_state(json: &str) -> Result<VM, LoadError> {
+    let state: FrozenVM = serde_json::from_str(json)?;
+    let mut vm = VM::new();
+    for mismatch in vm.restore_vm_state(state)? {
+        eprintln!("warning: {mismatch}, rebuilt from the device connections");
+    }
+    Ok(vm)
+}
+
+/// the id of the lowest numbered device with an ic
+pub fn first_housing(vm: &VM) -> Option<u32> {
+    vm.devices
+        .iter()
+        .find(|(_, device)| device.borrow().ic.is_some())
+        .map(|(id, _)| *id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::report::VMReport;
+
+    #[test]
+    fn run_code_and_report() -> Result<(), LoadError> {
+        let vm = vm_from_code("move r0 5\npush 7\nyield\nadd r0 r0 1\nj 2")?;
+        vm.run_ticks(3)?;
+        let report = VMReport::new(&vm);
+        assert_eq!(report.clock, 3);
+        assert_eq!(report.ics.len(), 1);
+        assert_eq!(report.ics[0].registers[0], 7.0);
+        assert_eq!(report.ics[0].stack, vec![7.0]);
+        assert!(!report.has_errors());
+
+        let state = serde_json::to_string(&vm.save_vm_state())?;
+        let restored = vm_from_state(&state)?;
+        assert_eq!(VMReport::new(&restored).ics[0].registers[0], 7.0);
+        Ok(())
+    }
+}
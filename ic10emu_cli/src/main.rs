@@ -0,0 +1,129 @@
+use std::path::PathBuf;
+
+use clap::{Parser, Subcommand};
+use ic10emu::{
+    harness::{HarnessError, TestFile},
+    vm::VMError,
+};
+use ic10emu_cli::{
+    load::{load_vm, LoadError},
+    report::VMReport,
+};
+
+/// Run ic10 scripts outside the game.
+#[derive(Debug, Parser)]
+#[command(name = "ic10", version)]
+struct Args {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Run a script or saved VM and print the final state
+    ///
+    /// FILE is either ic10 code, which is loaded into a single ic housing, a scenario ending in
+    /// `.toml` or a saved VM state ending in `.json`
+    Run {
+        file: PathBuf,
+        /// Number of game ticks to run
+        #[arg(long, short = 't', default_value_t = 1)]
+        ticks: u32,
+        /// Seed the VM's random number source
+        #[arg(long)]
+        seed: Option<i32>,
+        /// Print the final state as json
+        #[arg(long)]
+        json: bool,
+        /// Exit with an error status if an ic ends in an error state
+        #[arg(long)]
+        fail_on_error: bool,
+    },
+    /// Run assertion test files and report which assertions failed
+    Test {
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+        /// Print reports as json
+        #[arg(long)]
+        json: bool,
+    },
+}
+
+#[derive(thiserror::Error)]
+enum Error {
+    #[error("failed to load {0}")]
+    Load(PathBuf, #[source] LoadError),
+    #[error("vm error")]
+    VM(#[from] VMError),
+    #[error("failed to write json output")]
+    Json(#[from] serde_json::Error),
+    #[error("one or more ics ended in an error state")]
+    ICErrors,
+    #[error("failed to run test {0}")]
+    Test(PathBuf, #[source] Box<HarnessError>),
+    #[error("{0} of {1} tests failed")]
+    TestsFailed(usize, usize),
+}
+
+impl std::fmt::Debug for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        use std::error::Error;
+        use std::fmt::*;
+        write!(f, "{}", self)?;
+        let mut err: &dyn Error = self;
+        while let Some(cause) = err.source() {
+            write!(f, "\nCaused by: ")?;
+            Display::fmt(&cause, f)?;
+            err = cause;
+        }
+        Ok(())
+    }
+}
+
+fn main() -> Result<(), Error> {
+    let args = Args::parse();
+    match args.command {
+        Command::Run {
+            file,
+            ticks,
+            seed,
+            json,
+            fail_on_error,
+        } => {
+            let vm = load_vm(&file).map_err(|err| Error::Load(file.clone(), err))?;
+            if let Some(seed) = seed {
+                vm.reseed(seed);
+            }
+            vm.run_ticks(ticks)?;
+            let report = VMReport::new(&vm);
+            if json {
+                println!("{}", serde_json::to_string_pretty(&report)?);
+            } else {
+                print!("{report}");
+            }
+            if fail_on_error && report.has_errors() {
+                return Err(Error::ICErrors);
+            }
+        }
+        Command::Test { files, json } => {
+            let mut reports = Vec::new();
+            for file in &files {
+                let report = TestFile::load(file)
+                    .and_then(|test| test.run())
+                    .map_err(|err| Error::Test(file.clone(), Box::new(err)))?;
+                if !json {
+                    print!("{report}");
+                }
+                reports.push(report);
+            }
+            if json {
+                println!("{}", serde_json::to_string_pretty(&reports)?);
+            }
+            let failed = reports.iter().filter(|report| !report.passed()).count();
+            if failed > 0 {
+                return Err(Error::TestsFailed(failed, reports.len()));
+            }
+        }
+    }
+    Ok(())
+}
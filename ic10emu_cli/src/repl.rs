@@ -0,0 +1,485 @@
+use std::{fmt::Write, ops::Range, path::PathBuf, str::FromStr};
+
+use ic10emu::{
+    breakpoint::{Breakpoint, BreakpointCondition, BreakpointLocation, ConditionOperand},
+    grammar::{LogicType, Operand, SlotLogicType},
+    interpreter::{ICError, IC},
+    vm::{VMError, VM},
+};
+use thiserror::Error;
+
+use crate::{
+    load::{first_housing, load_vm, LoadError},
+    report::{register_name, DeviceReport, ICReport, VMReport},
+};
+
+/// steps kept so `back` can undo them
+pub const HISTORY_SIZE: usize = 1024;
+
+pub const HELP: &str = "\
+commands:
+  load <file>                   load a .ic10 script, .toml scenario or saved vm .json
+  ic <id>                       select the ic housing other commands act on
+  step [n]                      run n lines of the selected ic
+  back [n]                      undo n steps
+  run [ticks]                   run the vm for a number of game ticks
+  break <line|label> [if cond]  stop when the selected ic reaches a line
+  break if <cond>               stop when a condition like `r0 > 5` holds
+  delete <id>                   remove a breakpoint
+  breaks                        list breakpoints
+  print <reg|value>             print a register, alias or define
+  print <device> <field>        print a logic field, like `print d0 Temperature`
+  set <reg> <value>             write a register
+  set <device> <field> <value>  write a logic field
+  set <device> <slot> <field> <value>
+                                write a slot logic field
+  stack [start..end]            print stack addresses, 0..16 by default
+  regs                          print every register of the selected ic
+  devices                       list devices and their fields
+  networks                      list networks and their channels
+  save <file>                   save the vm state as json
+  restore <file>                restore a saved vm state
+  help                          show this message
+  quit                          exit";
+
+#[derive(Debug, Error)]
+pub enum ReplError {
+    #[error("unknown command '{0}', try `help`")]
+    UnknownCommand(String),
+    #[error("usage: {0}")]
+    Usage(&'static str),
+    #[error("can't parse '{0}'")]
+    Parse(String),
+    #[error("no ic housing is selected")]
+    NoIC,
+    #[error(transparent)]
+    Load(#[from] LoadError),
+    #[error(transparent)]
+    VM(#[from] VMError),
+    #[error(transparent)]
+    IC(#[from] ICError),
+    #[error("failed to access {0}")]
+    Io(PathBuf, #[source] std::io::Error),
+    #[error("failed to write vm state")]
+    Json(#[from] serde_json::Error),
+}
+
+/// a parsed repl command
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    Load(PathBuf),
+    Select(u32),
+    Step(u32),
+    Back(usize),
+    Run(u32),
+    Break(Breakpoint),
+    Delete(u32),
+    Breaks,
+    Print(ConditionOperand),
+    SetRegister(Operand, f64),
+    SetField(Operand, LogicType, f64),
+    SetSlotField(Operand, f64, SlotLogicType, f64),
+    Stack(Range<usize>),
+    Registers,
+    Devices,
+    Networks,
+    Save(PathBuf),
+    Restore(PathBuf),
+    Help,
+    Quit,
+}
+
+fn parse_arg<T: FromStr>(arg: &str) -> Result<T, ReplError> {
+    arg.parse().map_err(|_| ReplError::Parse(arg.to_owned()))
+}
+
+fn parse_count<T: FromStr>(args: &[&str], default: T) -> Result<T, ReplError> {
+    args.first().map_or(Ok(default), |arg| parse_arg(arg))
+}
+
+fn parse_operand(arg: &str) -> Result<Operand, ReplError> {
+    arg.parse::<Operand>()
+        .map_err(|_| ReplError::Parse(arg.to_owned()))
+}
+
+fn parse_breakpoint(args: &[&str]) -> Result<Breakpoint, ReplError> {
+    let condition = |cond: &[&str]| {
+        let cond = cond.join(" ");
+        cond.parse::<BreakpointCondition>()
+            .map_err(|_| ReplError::Parse(cond))
+    };
+    match args {
+        ["if", cond @ ..] if !cond.is_empty() => Ok(Breakpoint::when(condition(cond)?)),
+        [location, rest @ ..] => {
+            let breakpoint = match location.parse::<u32>() {
+                Ok(line) => Breakpoint::at_line(line),
+                Err(_) => Breakpoint::at_label(location),
+            };
+            match rest {
+                [] => Ok(breakpoint),
+                ["if", cond @ ..] if !cond.is_empty() => {
+                    Ok(breakpoint.with_condition(condition(cond)?))
+                }
+                _ => Err(ReplError::Usage("break <line|label> [if cond]")),
+            }
+        }
+        [] => Err(ReplError::Usage("break <line|label> [if cond]")),
+    }
+}
+
+fn describe(breakpoint: &Breakpoint) -> String {
+    let mut out = match &breakpoint.location {
+        BreakpointLocation::Line(line) => format!("line {line}"),
+        BreakpointLocation::Label(label) => format!("label {label}"),
+        BreakpointLocation::Anywhere => "anywhere".to_owned(),
+    };
+    if let Some(condition) = &breakpoint.condition {
+        write!(out, " if {condition}").unwrap();
+    }
+    if !breakpoint.enabled {
+        out.push_str(" (disabled)");
+    }
+    out
+}
+
+fn parse_range(arg: &str) -> Result<Range<usize>, ReplError> {
+    let (start, end) = arg
+        .split_once("..")
+        .ok_or_else(|| ReplError::Parse(arg.to_owned()))?;
+    Ok(parse_arg(start)?..parse_arg(end)?)
+}
+
+impl FromStr for Command {
+    type Err = ReplError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let words = s.split_whitespace().collect::<Vec<_>>();
+        let Some((cmd, args)) = words.split_first() else {
+            return Err(ReplError::Usage("help"));
+        };
+        match (*cmd, args) {
+            ("load", [file]) => Ok(Command::Load(file.into())),
+            ("load", _) => Err(ReplError::Usage("load <file>")),
+            ("ic", [id]) => Ok(Command::Select(parse_arg(id)?)),
+            ("ic", _) => Err(ReplError::Usage("ic <id>")),
+            ("step" | "s", args) => Ok(Command::Step(parse_count(args, 1)?)),
+            ("back" | "b", args) => Ok(Command::Back(parse_count(args, 1)?)),
+            ("run" | "r", args) => Ok(Command::Run(parse_count(args, 1)?)),
+            ("break", args) => Ok(Command::Break(parse_breakpoint(args)?)),
+            ("delete", [id]) => Ok(Command::Delete(parse_arg(id)?)),
+            ("delete", _) => Err(ReplError::Usage("delete <id>")),
+            ("breaks", []) => Ok(Command::Breaks),
+            ("print" | "p", [_, ..]) => Ok(Command::Print(
+                args.join(" ")
+                    .parse()
+                    .map_err(|_| ReplError::Parse(args.join(" ")))?,
+            )),
+            ("print" | "p", []) => Err(ReplError::Usage("print <reg|value> | <device> <field>")),
+            ("set", [reg, val]) => Ok(Command::SetRegister(parse_operand(reg)?, parse_arg(val)?)),
+            ("set", [device, field, val]) => Ok(Command::SetField(
+                parse_operand(device)?,
+                parse_arg(field)?,
+                parse_arg(val)?,
+            )),
+            ("set", [device, slot, field, val]) => Ok(Command::SetSlotField(
+                parse_operand(device)?,
+                parse_arg(slot)?,
+                parse_arg(field)?,
+                parse_arg(val)?,
+            )),
+            ("set", _) => Err(ReplError::Usage(
+                "set <reg> <value> | <device> <field> <value>",
+            )),
+            ("stack", []) => Ok(Command::Stack(0..16)),
+            ("stack", [range]) => Ok(Command::Stack(parse_range(range)?)),
+            ("stack", _) => Err(ReplError::Usage("stack [start..end]")),
+            ("regs", []) => Ok(Command::Registers),
+            ("devices", []) => Ok(Command::Devices),
+            ("networks", []) => Ok(Command::Networks),
+            ("save", [file]) => Ok(Command::Save(file.into())),
+            ("save", _) => Err(ReplError::Usage("save <file>")),
+            ("restore", [file]) => Ok(Command::Restore(file.into())),
+            ("restore", _) => Err(ReplError::Usage("restore <file>")),
+            ("help" | "h", _) => Ok(Command::Help),
+            ("quit" | "q" | "exit", _) => Ok(Command::Quit),
+            _ => Err(ReplError::UnknownCommand(s.trim().to_owned())),
+        }
+    }
+}
+
+/// a debugging session over a single VM, driving the same VM api as the web emulator
+pub struct Repl {
+    pub vm: VM,
+    /// id of the selected ic housing
+    pub device: Option<u32>,
+}
+
+impl Default for Repl {
+    fn default() -> Self {
+        Self::new(VM::new())
+    }
+}
+
+impl Repl {
+    pub fn new(vm: VM) -> Self {
+        vm.set_history_size(HISTORY_SIZE);
+        let device = first_housing(&vm);
+        Repl { vm, device }
+    }
+
+    fn housing(&self) -> Result<u32, ReplError> {
+        self.device.ok_or(ReplError::NoIC)
+    }
+
+    fn with_ic<T>(&self, f: impl FnOnce(&IC) -> Result<T, ReplError>) -> Result<T, ReplError> {
+        let ic = self.vm.get_ic_for_device(self.housing()?)?;
+        let ic = ic.borrow();
+        f(&ic)
+    }
+
+    /// the selected ic's next line, as `line: code`
+    fn position(&self) -> Result<String, ReplError> {
+        self.with_ic(|ic| {
+            let ip = ic.ip();
+            let code = ic.code.borrow();
+            let line = code.lines().nth(ip as usize).unwrap_or("").trim();
+            Ok(format!("{ip}: {line}  [{}]", ic.state.borrow()))
+        })
+    }
+
+    /// run a command, returning the text to show
+    ///
+    /// returns `Ok(None)` when the session should end
+    pub fn execute(&mut self, command: Command) -> Result<Option<String>, ReplError> {
+        let out = match command {
+            Command::Load(file) => {
+                *self = Repl::new(load_vm(&file)?);
+                format!("loaded {}", file.display())
+            }
+            Command::Select(id) => {
+                self.vm.get_ic_for_device(id)?;
+                self.device = Some(id);
+                self.position()?
+            }
+            Command::Step(steps) => {
+                let device = self.housing()?;
+                for _ in 0..steps {
+                    if !self.vm.step_ic(device, false)? {
+                        break;
+                    }
+                }
+                self.position()?
+            }
+            Command::Back(steps) => {
+                let undone = self.vm.rewind(steps)?;
+                format!("undid {undone} steps\n{}", self.position()?)
+            }
+            Command::Run(ticks) => {
+                let start = self.vm.clock();
+                let result = self.vm.run_ticks(ticks)?;
+                let mut out = format!("ran {} ticks", self.vm.clock() - start);
+                for hit in &result.breakpoints {
+                    write!(
+                        out,
+                        "\nbreakpoint {} hit in device {} at line {}",
+                        hit.breakpoint, hit.device, hit.line
+                    )
+                    .unwrap();
+                }
+                for hit in &result.watchpoints {
+                    write!(out, "\nwatchpoint {} hit", hit.watchpoint).unwrap();
+                }
+                if self.device.is_some() {
+                    write!(out, "\n{}", self.position()?).unwrap();
+                }
+                out
+            }
+            Command::Break(breakpoint) => {
+                let id = self.vm.add_breakpoint(self.housing()?, breakpoint)?;
+                format!("breakpoint {id}")
+            }
+            Command::Delete(id) => {
+                if self.vm.remove_breakpoint(self.housing()?, id)? {
+                    format!("deleted breakpoint {id}")
+                } else {
+                    format!("no breakpoint {id}")
+                }
+            }
+            Command::Breaks => {
+                let breakpoints = self.vm.get_breakpoints(self.housing()?)?;
+                if breakpoints.is_empty() {
+                    "no breakpoints".to_owned()
+                } else {
+                    breakpoints
+                        .iter()
+                        .map(|(id, breakpoint)| format!("{id}: {}", describe(breakpoint)))
+                        .collect::<Vec<_>>()
+                        .join("\n")
+                }
+            }
+            Command::Print(operand) => self
+                .with_ic(|ic| Ok(operand.value(ic, &self.vm)?))?
+                .to_string(),
+            Command::SetRegister(reg, val) => self.with_ic(|ic| {
+                let reg = reg.as_register(ic, ic10emu::grammar::InstructionOp::Nop, 1)?;
+                ic.set_register(reg.indirection, reg.target, val)?;
+                Ok(val.to_string())
+            })?,
+            Command::SetField(device, field, val) => {
+                let device = self.resolve_device(&device)?;
+                device.borrow_mut().set_field(field, val, &self.vm, true)?;
+                self.vm.set_modified(device.borrow().id);
+                val.to_string()
+            }
+            Command::SetSlotField(device, slot, field, val) => {
+                let device = self.resolve_device(&device)?;
+                device
+                    .borrow_mut()
+                    .set_slot_field(slot, field, val, &self.vm, true)?;
+                self.vm.set_modified(device.borrow().id);
+                val.to_string()
+            }
+            Command::Stack(range) => self.with_ic(|ic| {
+                let stack = ic.stack.borrow();
+                let end = range.end.min(stack.len());
+                Ok((range.start.min(end)..end)
+                    .map(|addr| format!("[{addr}] = {}", stack[addr]))
+                    .collect::<Vec<_>>()
+                    .join("\n"))
+            })?,
+            Command::Registers => self.with_ic(|ic| {
+                Ok(ic
+                    .registers
+                    .borrow()
+                    .iter()
+                    .enumerate()
+                    .map(|(i, val)| format!("{:<3} = {val}", register_name(i)))
+                    .collect::<Vec<_>>()
+                    .join("\n"))
+            })?,
+            Command::Devices => {
+                let report = VMReport::new(&self.vm);
+                report
+                    .devices
+                    .iter()
+                    .map(DeviceReport::to_string)
+                    .chain(report.ics.iter().map(ICReport::to_string))
+                    .collect::<String>()
+                    .trim_end()
+                    .to_owned()
+            }
+            Command::Networks => self
+                .vm
+                .networks
+                .values()
+                .map(|net| {
+                    let net = net.borrow();
+                    let mut devices = net.devices().iter().copied().collect::<Vec<_>>();
+                    devices.sort();
+                    let mut power_only = net.power_only().iter().copied().collect::<Vec<_>>();
+                    power_only.sort();
+                    format!(
+                        "network {}: devices {devices:?}, power only {power_only:?}, channels {:?}",
+                        net.id, net.channels
+                    )
+                })
+                .collect::<Vec<_>>()
+                .join("\n"),
+            Command::Save(file) => {
+                let state = serde_json::to_string_pretty(&self.vm.save_vm_state())?;
+                std::fs::write(&file, state).map_err(|err| ReplError::Io(file.clone(), err))?;
+                format!("saved {}", file.display())
+            }
+            Command::Restore(file) => {
+                let state = std::fs::read_to_string(&file)
+                    .map_err(|err| ReplError::Io(file.clone(), err))?;
+                let mismatches = self
+                    .vm
+                    .restore_vm_state(serde_json::from_str(&state).map_err(LoadError::from)?)?;
+                if self
+                    .device
+                    .is_none_or(|id| self.vm.get_ic_for_device(id).is_err())
+                {
+                    self.device = first_housing(&self.vm);
+                }
+                std::iter::once(format!("restored {}", file.display()))
+                    .chain(mismatches.iter().map(|mismatch| {
+                        format!("warning: {mismatch}, rebuilt from the device connections")
+                    }))
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            }
+            Command::Help => HELP.to_owned(),
+            Command::Quit => return Ok(None),
+        };
+        Ok(Some(out))
+    }
+
+    /// a device as seen by the selected ic, so `d0` and `db` resolve like they do in scripts
+    fn resolve_device(
+        &self,
+        device: &Operand,
+    ) -> Result<std::rc::Rc<std::cell::RefCell<ic10emu::device::Device>>, ReplError> {
+        let id = self.with_ic(|ic| {
+            let (id, _connection) =
+                device.as_device(ic, ic10emu::grammar::InstructionOp::Nop, 1)?;
+            let id = id.ok_or(ICError::DeviceNotSet)?;
+            self.vm
+                .get_device_same_network(ic.device, id)
+                .map(|_| id)
+                .ok_or(ICError::UnknownDeviceID(id as f64).into())
+        })?;
+        Ok(self.vm.get_device(id).expect("device looked up above"))
+    }
+
+    /// parse and run one line of input
+    pub fn eval(&mut self, line: &str) -> Result<Option<String>, ReplError> {
+        if line.trim().is_empty() {
+            return Ok(Some(String::new()));
+        }
+        self.execute(line.parse()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::load::vm_from_code;
+
+    fn eval(repl: &mut Repl, line: &str) -> String {
+        repl.eval(line).unwrap().unwrap()
+    }
+
+    #[test]
+    fn debug_session() -> Result<(), ReplError> {
+        let mut repl = Repl::new(vm_from_code(
+            "move r0 1\nstart:\nadd r0 r0 1\npush r0\ns db Setting r0\nyield\nj start",
+        )?);
+        assert_eq!(eval(&mut repl, "step 2"), "2: add r0 r0 1  [Running]");
+        assert_eq!(eval(&mut repl, "print r0"), "1");
+        assert_eq!(eval(&mut repl, "break start if r0 > 3"), "breakpoint 0");
+        assert!(eval(&mut repl, "run 10").contains("breakpoint 0 hit in device 1 at line 1"));
+        assert_eq!(eval(&mut repl, "print r0"), "4");
+        assert_eq!(eval(&mut repl, "print db Setting"), "4");
+        assert_eq!(eval(&mut repl, "stack 0..3"), "[0] = 2\n[1] = 3\n[2] = 4");
+
+        eval(&mut repl, "set db Setting 9");
+        assert_eq!(eval(&mut repl, "print db Setting"), "9");
+        eval(&mut repl, "set r0 0");
+        assert_eq!(eval(&mut repl, "print r0"), "0");
+
+        assert_eq!(eval(&mut repl, "breaks"), "0: label start if r0 > 3");
+        eval(&mut repl, "delete 0");
+        eval(&mut repl, "step 2");
+        assert_eq!(eval(&mut repl, "print r0"), "1");
+        eval(&mut repl, "back 2");
+        assert_eq!(eval(&mut repl, "print r0"), "0");
+
+        assert!(matches!(
+            repl.eval("frobnicate"),
+            Err(ReplError::UnknownCommand(_))
+        ));
+        assert!(matches!(repl.eval("set r0"), Err(ReplError::Usage(_))));
+        assert_eq!(repl.eval("quit")?, None);
+        Ok(())
+    }
+}
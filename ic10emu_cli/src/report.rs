@@ -0,0 +1,151 @@
+use std::{collections::BTreeMap, fmt::Display};
+
+use ic10emu::{grammar::LogicType, interpreter::ICState, vm::VM};
+use serde::Serialize;
+
+/// the state of an ic at the end of a run
+#[derive(Debug, Clone, Serialize)]
+pub struct ICReport {
+    /// id of the ic's housing
+    pub device: u32,
+    pub id: u32,
+    pub state: String,
+    pub errored: bool,
+    pub ip: u32,
+    pub registers: [f64; 18],
+    /// the stack up to its last non zero value
+    pub stack: Vec<f64>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DeviceReport {
+    pub id: u32,
+    pub name: Option<String>,
+    pub prefab: Option<String>,
+    pub fields: BTreeMap<LogicType, f64>,
+}
+
+/// the registers, stack and device fields of every ic and device in a VM
+#[derive(Debug, Clone, Serialize)]
+pub struct VMReport {
+    pub clock: u64,
+    pub ics: Vec<ICReport>,
+    pub devices: Vec<DeviceReport>,
+}
+
+impl VMReport {
+    pub fn new(vm: &VM) -> Self {
+        let ics = vm
+            .ics
+            .values()
+            .map(|ic| {
+                let ic = ic.borrow();
+                let stack = ic.stack.borrow();
+                let used = stack
+                    .iter()
+                    .rposition(|val| *val != 0.0)
+                    .map_or(0, |i| i + 1);
+                let state = ic.state.borrow();
+                let report = ICReport {
+                    device: ic.device,
+                    id: ic.id,
+                    state: state.to_string(),
+                    errored: matches!(*state, ICState::Error(_) | ICState::HasCaughtFire),
+                    ip: ic.ip(),
+                    registers: *ic.registers.borrow(),
+                    stack: stack[..used].to_vec(),
+                };
+                report
+            })
+            .collect();
+        let devices = vm
+            .devices
+            .values()
+            .map(|device| {
+                let device = device.borrow();
+                DeviceReport {
+                    id: device.id,
+                    name: device.name.clone(),
+                    prefab: device.prefab.as_ref().map(|prefab| prefab.name.clone()),
+                    fields: device
+                        .get_fields(vm)
+                        .into_iter()
+                        .map(|(typ, field)| (typ, field.value))
+                        .collect(),
+                }
+            })
+            .collect();
+        VMReport {
+            clock: vm.clock(),
+            ics,
+            devices,
+        }
+    }
+
+    /// true if any ic ended in an error state or caught fire
+    pub fn has_errors(&self) -> bool {
+        self.ics.iter().any(|ic| ic.errored)
+    }
+}
+
+pub fn register_name(index: usize) -> String {
+    match index {
+        16 => "sp".to_owned(),
+        17 => "ra".to_owned(),
+        i => format!("r{i}"),
+    }
+}
+
+impl Display for ICReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "ic {} in device {}: {}",
+            self.id, self.device, self.state
+        )?;
+        writeln!(f, "  ip: {}", self.ip)?;
+        writeln!(f, "  registers:")?;
+        for (i, val) in self.registers.iter().enumerate() {
+            writeln!(f, "    {:<3} = {val}", register_name(i))?;
+        }
+        if self.stack.is_empty() {
+            writeln!(f, "  stack: empty")?;
+        } else {
+            writeln!(f, "  stack:")?;
+            for (addr, val) in self.stack.iter().enumerate() {
+                writeln!(f, "    [{addr}] = {val}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Display for DeviceReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "device {}", self.id)?;
+        if let Some(prefab) = &self.prefab {
+            write!(f, " {prefab}")?;
+        }
+        if let Some(name) = &self.name {
+            write!(f, " \"{name}\"")?;
+        }
+        writeln!(f)?;
+        for (typ, val) in &self.fields {
+            writeln!(f, "    {typ} = {val}")?;
+        }
+        Ok(())
+    }
+}
+
+impl Display for VMReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "clock: {}", self.clock)?;
+        for ic in &self.ics {
+            write!(f, "{ic}")?;
+        }
+        for device in &self.devices {
+            write!(f, "{device}")?;
+        }
+        Ok(())
+    }
+}
@@ -3,9 +3,14 @@ mod utils;
 mod types;
 
 use ic10emu::{
+    behavior::BuiltinBehavior,
+    breakpoint::Breakpoint,
     device::{Device, DeviceTemplate, SlotOccupantTemplate},
-    grammar::{LogicType, SlotLogicType},
-    vm::{FrozenVM, VMError, VM},
+    grammar::{LogicType, ReagentMode, SlotLogicType},
+    prefabs,
+    stimulus::Stimulus,
+    vm::{FrozenVM, UnknownPrefabAccess, VMError, VM},
+    watch::Watchpoint,
 };
 use serde::{Deserialize, Serialize};
 use types::{Registers, Stack};
@@ -36,6 +41,8 @@ pub enum BindingError {
     InvalidEnumVariant(String),
     #[error("Index {0} is out of range {1}")]
     OutOfBounds(usize, usize),
+    #[error("unknown reagent '{0}'")]
+    UnknownReagent(String),
 }
 
 #[wasm_bindgen]
@@ -102,6 +109,11 @@ impl DeviceRef {
         serde_wasm_bindgen::to_value(&self.device.borrow().connections).unwrap()
     }
 
+    #[wasm_bindgen(getter, js_name = "wirelessLink")]
+    pub fn wireless_link(&self) -> Option<u32> {
+        self.device.borrow().wireless_link()
+    }
+
     #[wasm_bindgen(getter, js_name = "ip")]
     pub fn ic_ip(&self) -> Option<u32> {
         self.device.borrow().ic.as_ref().and_then(|ic| {
@@ -328,6 +340,40 @@ impl DeviceRef {
         Ok(serde_wasm_bindgen::to_value(&fields).unwrap())
     }
 
+    #[wasm_bindgen(js_name = "setReagent", skip_typescript)]
+    pub fn set_reagent(&self, mode: &str, reagent: &str, value: f64) -> Result<(), JsError> {
+        let mode = ReagentMode::from_str(mode)?;
+        let reagent = prefabs::reagent(reagent)
+            .ok_or_else(|| BindingError::UnknownReagent(reagent.to_owned()))?;
+        self.device
+            .borrow_mut()
+            .set_reagent(mode, reagent.hash, value)?;
+        Ok(())
+    }
+
+    #[wasm_bindgen(js_name = "getReagent", skip_typescript)]
+    pub fn get_reagent(&self, mode: &str, reagent: &str) -> Result<f64, JsError> {
+        let mode = ReagentMode::from_str(mode)?;
+        let reagent = prefabs::reagent(reagent)
+            .ok_or_else(|| BindingError::UnknownReagent(reagent.to_owned()))?;
+        Ok(self.device.borrow().get_reagent(&mode, reagent.hash as f64))
+    }
+
+    #[wasm_bindgen(js_name = "getChannel")]
+    pub fn get_channel(&self, conn: usize, channel: usize) -> Result<f64, JsError> {
+        let network_id = self.device.borrow().get_network_id(conn)?;
+        Ok(self.vm.borrow().get_network_channel(network_id, channel)?)
+    }
+
+    #[wasm_bindgen(js_name = "setChannel")]
+    pub fn set_channel(&self, conn: usize, channel: usize, value: f64) -> Result<(), JsError> {
+        let network_id = self.device.borrow().get_network_id(conn)?;
+        Ok(self
+            .vm
+            .borrow()
+            .set_network_channel(network_id, channel, value)?)
+    }
+
     #[wasm_bindgen(js_name = "setConnection")]
     pub fn set_connection(&self, conn: usize, net: Option<u32>) -> Result<(), JsError> {
         let device_id = self.device.borrow().id;
@@ -368,11 +414,28 @@ impl VMRef {
         }
     }
 
+    #[wasm_bindgen(js_name = "withSeed")]
+    pub fn with_seed(seed: i32) -> Self {
+        VMRef {
+            vm: Rc::new(RefCell::new(VM::with_seed(seed))),
+        }
+    }
+
+    #[wasm_bindgen(js_name = "reseed")]
+    pub fn reseed(&self, seed: i32) {
+        self.vm.borrow().reseed(seed);
+    }
+
     #[wasm_bindgen(js_name = "addDevice")]
     pub fn add_device(&self, network: Option<u32>) -> Result<u32, JsError> {
         Ok(self.vm.borrow_mut().add_device(network)?)
     }
 
+    #[wasm_bindgen(js_name = "addDeviceByPrefab")]
+    pub fn add_device_by_prefab(&self, name: &str, network: Option<u32>) -> Result<u32, JsError> {
+        Ok(self.vm.borrow_mut().add_device_by_prefab(name, network)?)
+    }
+
     #[wasm_bindgen(js_name = "addDeviceFromTemplate", skip_typescript)]
     pub fn add_device_from_template(&self, template: JsValue) -> Result<u32, JsError> {
         let template: DeviceTemplate = serde_wasm_bindgen::from_value(template)?;
@@ -416,6 +479,286 @@ impl VMRef {
         Ok(self.vm.borrow().reset_ic(id)?)
     }
 
+    #[wasm_bindgen(js_name = "addBreakpoint", skip_typescript)]
+    pub fn add_breakpoint(&self, id: u32, breakpoint: JsValue) -> Result<u32, JsError> {
+        let breakpoint: Breakpoint = serde_wasm_bindgen::from_value(breakpoint)?;
+        Ok(self.vm.borrow().add_breakpoint(id, breakpoint)?)
+    }
+
+    #[wasm_bindgen(js_name = "addLineBreakpoint")]
+    pub fn add_line_breakpoint(
+        &self,
+        id: u32,
+        line: u32,
+        condition: Option<String>,
+    ) -> Result<u32, JsError> {
+        let mut breakpoint = Breakpoint::at_line(line);
+        if let Some(condition) = condition {
+            breakpoint = breakpoint.with_condition(condition.parse()?);
+        }
+        Ok(self.vm.borrow().add_breakpoint(id, breakpoint)?)
+    }
+
+    #[wasm_bindgen(js_name = "removeBreakpoint")]
+    pub fn remove_breakpoint(&self, id: u32, breakpoint: u32) -> Result<bool, JsError> {
+        Ok(self.vm.borrow().remove_breakpoint(id, breakpoint)?)
+    }
+
+    #[wasm_bindgen(js_name = "setBreakpointEnabled")]
+    pub fn set_breakpoint_enabled(
+        &self,
+        id: u32,
+        breakpoint: u32,
+        enabled: bool,
+    ) -> Result<bool, JsError> {
+        Ok(self
+            .vm
+            .borrow()
+            .set_breakpoint_enabled(id, breakpoint, enabled)?)
+    }
+
+    #[wasm_bindgen(js_name = "clearBreakpoints")]
+    pub fn clear_breakpoints(&self, id: u32) -> Result<(), JsError> {
+        Ok(self.vm.borrow().clear_breakpoints(id)?)
+    }
+
+    #[wasm_bindgen(js_name = "getBreakpoints", skip_typescript)]
+    pub fn get_breakpoints(&self, id: u32) -> Result<JsValue, JsError> {
+        let breakpoints = self.vm.borrow().get_breakpoints(id)?;
+        Ok(serde_wasm_bindgen::to_value(&breakpoints).unwrap())
+    }
+
+    #[wasm_bindgen(js_name = "addWatchpoint", skip_typescript)]
+    pub fn add_watchpoint(&self, watchpoint: JsValue) -> Result<u32, JsError> {
+        let watchpoint: Watchpoint = serde_wasm_bindgen::from_value(watchpoint)?;
+        Ok(self.vm.borrow().add_watchpoint(watchpoint))
+    }
+
+    #[wasm_bindgen(js_name = "removeWatchpoint")]
+    pub fn remove_watchpoint(&self, watchpoint: u32) -> bool {
+        self.vm.borrow().remove_watchpoint(watchpoint)
+    }
+
+    #[wasm_bindgen(js_name = "setWatchpointEnabled")]
+    pub fn set_watchpoint_enabled(&self, watchpoint: u32, enabled: bool) -> bool {
+        self.vm.borrow().set_watchpoint_enabled(watchpoint, enabled)
+    }
+
+    #[wasm_bindgen(js_name = "clearWatchpoints")]
+    pub fn clear_watchpoints(&self) {
+        self.vm.borrow().clear_watchpoints();
+    }
+
+    #[wasm_bindgen(js_name = "getWatchpoints", skip_typescript)]
+    pub fn get_watchpoints(&self) -> JsValue {
+        let watchpoints = self.vm.borrow().get_watchpoints();
+        serde_wasm_bindgen::to_value(&watchpoints).unwrap()
+    }
+
+    #[wasm_bindgen(js_name = "addStimulus", skip_typescript)]
+    pub fn add_stimulus(&self, stimulus: JsValue) -> Result<u32, JsError> {
+        let stimulus: Stimulus = serde_wasm_bindgen::from_value(stimulus)?;
+        Ok(self.vm.borrow().add_stimulus(stimulus)?)
+    }
+
+    #[wasm_bindgen(js_name = "removeStimulus")]
+    pub fn remove_stimulus(&self, stimulus: u32) -> bool {
+        self.vm.borrow().remove_stimulus(stimulus)
+    }
+
+    #[wasm_bindgen(js_name = "clearStimuli")]
+    pub fn clear_stimuli(&self) {
+        self.vm.borrow().clear_stimuli();
+    }
+
+    #[wasm_bindgen(js_name = "getStimuli", skip_typescript)]
+    pub fn get_stimuli(&self) -> JsValue {
+        let stimuli = self.vm.borrow().get_stimuli();
+        serde_wasm_bindgen::to_value(&stimuli).unwrap()
+    }
+
+    #[wasm_bindgen(js_name = "setBehavior", skip_typescript)]
+    pub fn set_behavior(&self, behavior: JsValue) -> Result<(), JsError> {
+        let behavior: BuiltinBehavior = serde_wasm_bindgen::from_value(behavior)?;
+        Ok(self.vm.borrow().set_builtin_behavior(behavior)?)
+    }
+
+    #[wasm_bindgen(js_name = "removeBehavior")]
+    pub fn remove_behavior(&self, id: u32) -> bool {
+        self.vm.borrow().remove_behavior(id)
+    }
+
+    #[wasm_bindgen(js_name = "linkBehavior")]
+    pub fn link_behavior(&self, id: u32, links: Vec<u32>) -> Result<(), JsError> {
+        Ok(self.vm.borrow().link_behavior(id, &links)?)
+    }
+
+    #[wasm_bindgen(js_name = "getBehaviors", skip_typescript)]
+    pub fn get_behaviors(&self) -> JsValue {
+        let behaviors = self.vm.borrow().get_behaviors();
+        serde_wasm_bindgen::to_value(&behaviors).unwrap()
+    }
+
+    #[wasm_bindgen(js_name = "setUnknownPrefabAccess", skip_typescript)]
+    pub fn set_unknown_prefab_access(&self, access: JsValue) -> Result<(), JsError> {
+        let access: UnknownPrefabAccess = serde_wasm_bindgen::from_value(access)?;
+        self.vm.borrow().set_unknown_prefab_access(access);
+        Ok(())
+    }
+
+    #[wasm_bindgen(js_name = "setWirelessLink")]
+    pub fn set_wireless_link(&self, id: u32, target: Option<u32>) -> Result<(), JsError> {
+        Ok(self.vm.borrow().set_wireless_link(id, target)?)
+    }
+
+    #[wasm_bindgen(js_name = "checkInvariants", skip_typescript)]
+    pub fn check_invariants(&self) -> JsValue {
+        let mismatches = self.vm.borrow().check_invariants();
+        serde_wasm_bindgen::to_value(&mismatches).unwrap()
+    }
+
+    #[wasm_bindgen(js_name = "setPowerSimulation")]
+    pub fn set_power_simulation(&self, enabled: bool) {
+        self.vm.borrow().set_power_simulation(enabled);
+    }
+
+    #[wasm_bindgen(js_name = "getNetworkPower", skip_typescript)]
+    pub fn get_network_power(&self, id: u32) -> JsValue {
+        let power = self.vm.borrow().get_network_power(id);
+        serde_wasm_bindgen::to_value(&power).unwrap()
+    }
+
+    #[wasm_bindgen(js_name = "getNetworkChannel")]
+    pub fn get_network_channel(&self, id: u32, channel: usize) -> Result<f64, JsError> {
+        Ok(self.vm.borrow().get_network_channel(id, channel)?)
+    }
+
+    #[wasm_bindgen(js_name = "setNetworkChannel")]
+    pub fn set_network_channel(&self, id: u32, channel: usize, value: f64) -> Result<(), JsError> {
+        Ok(self.vm.borrow().set_network_channel(id, channel, value)?)
+    }
+
+    #[wasm_bindgen(js_name = "startChannelMonitor")]
+    pub fn start_channel_monitor(&self, capacity: usize) {
+        self.vm.borrow().start_channel_monitor(capacity);
+    }
+
+    #[wasm_bindgen(js_name = "stopChannelMonitor", skip_typescript)]
+    pub fn stop_channel_monitor(&self) -> JsValue {
+        let writes = self.vm.borrow().stop_channel_monitor();
+        serde_wasm_bindgen::to_value(&writes).unwrap()
+    }
+
+    #[wasm_bindgen(js_name = "getChannelWrites", skip_typescript)]
+    pub fn get_channel_writes(&self) -> JsValue {
+        let writes = self.vm.borrow().channel_writes();
+        serde_wasm_bindgen::to_value(&writes).unwrap()
+    }
+
+    #[wasm_bindgen(js_name = "setHistorySize")]
+    pub fn set_history_size(&self, size: usize) {
+        self.vm.borrow().set_history_size(size);
+    }
+
+    #[wasm_bindgen(getter, js_name = "historySize")]
+    pub fn history_size(&self) -> usize {
+        self.vm.borrow().history_size()
+    }
+
+    #[wasm_bindgen(js_name = "getHistory", skip_typescript)]
+    pub fn get_history(&self) -> JsValue {
+        let history = self.vm.borrow().get_history();
+        serde_wasm_bindgen::to_value(&history).unwrap()
+    }
+
+    #[wasm_bindgen(js_name = "clearHistory")]
+    pub fn clear_history(&self) {
+        self.vm.borrow().clear_history();
+    }
+
+    #[wasm_bindgen(js_name = "stepBack", skip_typescript)]
+    pub fn step_back(&self) -> Result<JsValue, JsError> {
+        let step = self.vm.borrow().step_back()?;
+        Ok(serde_wasm_bindgen::to_value(&step).unwrap())
+    }
+
+    #[wasm_bindgen(js_name = "rewind")]
+    pub fn rewind(&self, steps: usize) -> Result<usize, JsError> {
+        Ok(self.vm.borrow().rewind(steps)?)
+    }
+
+    #[wasm_bindgen(js_name = "startTrace")]
+    pub fn start_trace(&self, id: u32) -> Result<(), JsError> {
+        Ok(self.vm.borrow().start_trace(id)?)
+    }
+
+    #[wasm_bindgen(js_name = "stopTrace", skip_typescript)]
+    pub fn stop_trace(&self, id: u32) -> Result<JsValue, JsError> {
+        let trace = self.vm.borrow().stop_trace(id)?;
+        Ok(serde_wasm_bindgen::to_value(&trace).unwrap())
+    }
+
+    #[cfg(feature = "trace-export")]
+    #[wasm_bindgen(js_name = "traceJsonLines")]
+    pub fn trace_json_lines(&self, id: u32) -> Result<Option<String>, JsError> {
+        let trace = self.vm.borrow().get_trace(id)?;
+        Ok(trace.map(|trace| trace.to_json_lines()).transpose()?)
+    }
+
+    #[cfg(feature = "trace-export")]
+    #[wasm_bindgen(js_name = "traceBinary")]
+    pub fn trace_binary(&self, id: u32) -> Result<Option<Vec<u8>>, JsError> {
+        let trace = self.vm.borrow().get_trace(id)?;
+        Ok(trace.map(|trace| trace.to_binary()).transpose()?)
+    }
+
+    #[wasm_bindgen(js_name = "startProfile")]
+    pub fn start_profile(&self, id: u32) -> Result<(), JsError> {
+        Ok(self.vm.borrow().start_profile(id)?)
+    }
+
+    #[wasm_bindgen(js_name = "stopProfile", skip_typescript)]
+    pub fn stop_profile(&self, id: u32) -> Result<JsValue, JsError> {
+        let report = self
+            .vm
+            .borrow()
+            .stop_profile(id)?
+            .map(|profile| profile.report());
+        Ok(serde_wasm_bindgen::to_value(&report).unwrap())
+    }
+
+    #[wasm_bindgen(js_name = "getProfile", skip_typescript)]
+    pub fn get_profile(&self, id: u32) -> Result<JsValue, JsError> {
+        let report = self
+            .vm
+            .borrow()
+            .get_profile(id)?
+            .map(|profile| profile.report());
+        Ok(serde_wasm_bindgen::to_value(&report).unwrap())
+    }
+
+    #[wasm_bindgen(js_name = "tick", skip_typescript)]
+    pub fn tick(&self) -> Result<JsValue, JsError> {
+        let result = self.vm.borrow().tick()?;
+        Ok(serde_wasm_bindgen::to_value(&result).unwrap())
+    }
+
+    #[wasm_bindgen(js_name = "runTicks", skip_typescript)]
+    pub fn run_ticks(&self, ticks: u32) -> Result<JsValue, JsError> {
+        let result = self.vm.borrow().run_ticks(ticks)?;
+        Ok(serde_wasm_bindgen::to_value(&result).unwrap())
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn clock(&self) -> u64 {
+        self.vm.borrow().clock()
+    }
+
+    #[wasm_bindgen(getter, js_name = "gameTime")]
+    pub fn game_time(&self) -> f64 {
+        self.vm.borrow().game_time()
+    }
+
     #[wasm_bindgen(getter, js_name = "defaultNetwork")]
     pub fn default_network(&self) -> u32 {
         self.vm.borrow().default_network
@@ -508,10 +851,10 @@ impl VMRef {
     }
 
     #[wasm_bindgen(js_name = "restoreVMState", skip_typescript)]
-    pub fn restore_vm_state(&self, state: JsValue) -> Result<(), JsError> {
+    pub fn restore_vm_state(&self, state: JsValue) -> Result<JsValue, JsError> {
         let state: FrozenVM = serde_wasm_bindgen::from_value(state)?;
-        self.vm.borrow_mut().restore_vm_state(state)?;
-        Ok(())
+        let mismatches = self.vm.borrow_mut().restore_vm_state(state)?;
+        Ok(serde_wasm_bindgen::to_value(&mismatches)?)
     }
 }
 
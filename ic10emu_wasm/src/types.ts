@@ -18,6 +18,8 @@ export interface ConnectionCableNetwork {
 
 export type Connection = ConnectionCableNetwork | "Other";
 
+export type Bridge = "Power" | "Data" | "PowerAndData";
+
 export type RegisterSpec = {
   readonly RegisterSpec: {
     readonly indirection: number;
@@ -49,7 +51,7 @@ export type NumberBinary = { readonly Binary: BigInt };
 export type NumberHexadecimal = { readonly Hexadecimal: BigInt };
 export type NumberConstant = { readonly Constant: number };
 export type NumberString = { readonly String: string };
-export type NumberEnum = { readonly Enum: number };
+export type NumberEnum = { readonly Enum: [string, number] };
 
 export type NumberOperand = {
   Number:
@@ -111,6 +113,8 @@ export interface DeviceRef {
   setField(field: LogicType, value: number, force: boolean): void;
   setSlotField(slot: number, field: SlotLogicType, value: number, force: boolean): void;
   getSlotField(slot: number, field: SlotLogicType): number;
+  setReagent(mode: ReagentMode, reagent: string, value: number): void;
+  getReagent(mode: ReagentMode, reagent: string): number;
 }
 
 export interface SlotOccupantTemplate {
@@ -128,8 +132,10 @@ export interface DeviceTemplate {
   name?: string;
   prefab_name?: string;
   slots: SlotTemplate[];
-  // reagents: { [key: string]: float}
+  reagents?: { [key in ReagentMode]?: { [hash: number]: number } };
   connections: Connection[];
+  wireless_link?: number;
+  bridge?: Bridge;
   fields: { [key in LogicType]?: LogicField };
 }
 
@@ -152,6 +158,20 @@ export interface FrozenNetwork {
   devices: number[];
   power_only: number[];
   channels: number[];
+  power?: PowerState;
+}
+
+export type Membership = "Data" | "PowerOnly";
+
+export type NetworkMismatch =
+  | { UnknownNetwork: { device: number; network: number } }
+  | { MissingMember: { device: number; network: number; membership: Membership } }
+  | { StrayMember: { device: number; network: number; membership: Membership } };
+
+export interface PowerState {
+  supply: number;
+  demand: number;
+  overloaded: boolean;
 }
 
 export interface FrozenVM {
@@ -159,11 +179,198 @@ export interface FrozenVM {
   devices: DeviceTemplate[];
   networks: FrozenNetwork[];
   default_network: number;
+  clock?: number;
+  random?: FrozenRandom;
+  behaviors?: BuiltinBehavior[];
+  power_simulation?: boolean;
+  unknown_prefab_access?: UnknownPrefabAccess;
+}
+
+export interface FrozenRandom {
+  inext: number;
+  inextp: number;
+  seed_array: number[];
+}
+
+export type BreakpointLocation = { Line: number } | { Label: string } | "Anywhere";
+
+export interface Breakpoint {
+  location: BreakpointLocation;
+  condition?: string | null;
+  enabled: boolean;
+}
+
+export interface BreakpointHit {
+  device: number;
+  ic: number;
+  breakpoint: number;
+  line: number;
+}
+
+export type WatchTarget =
+  | { Register: { device: number; index: number } }
+  | { Stack: { device: number; address: number } }
+  | { Field: { device: number; field: LogicType } }
+  | { SlotField: { device: number; slot: number; field: SlotLogicType } }
+  | { Channel: { network: number; channel: number } };
+
+export type AccessKind = "Read" | "Write";
+
+export interface Access {
+  target: WatchTarget;
+  kind: AccessKind;
+  old: number;
+  value: number;
+}
+
+export interface Watchpoint {
+  target: WatchTarget;
+  on: "Read" | "Write" | "ReadWrite";
+  on_change: boolean;
+  enabled: boolean;
+}
+
+export interface WatchpointHit {
+  watchpoint: number;
+  device: number;
+  ic: number;
+  line: number;
+  access: Access;
+}
+
+export type StimulusTarget =
+  | { type: "Field"; device: number; field: LogicType }
+  | { type: "SlotField"; device: number; slot: number; field: SlotLogicType };
+
+export type Waveform =
+  | { type: "steps"; steps: [number, number][] }
+  | { type: "ramp"; from: number; to: number; start: number; end: number }
+  | { type: "sine"; offset?: number; amplitude: number; period: number; phase?: number }
+  | { type: "samples"; samples: number[]; start?: number; repeat?: boolean }
+  | {
+      type: "random_walk";
+      start: number;
+      step: number;
+      min?: number;
+      max?: number;
+      seed?: number;
+    };
+
+export interface Stimulus {
+  target: StimulusTarget;
+  waveform: Waveform;
+}
+
+export type BuiltinBehavior =
+  | { type: "DaylightSensor"; device: number; day_length?: number; peak_irradiance?: number }
+  | { type: "WallHeater"; device: number; rooms?: number[]; heat_rate?: number }
+  | { type: "LogicMemory"; device: number }
+  | { type: "LogicMath"; device: number; inputs?: [number | null, number | null] }
+  | { type: "LogicCompare"; device: number; inputs?: [number | null, number | null] }
+  | { type: "LogicSelect"; device: number; inputs?: [number | null, number | null] }
+  | { type: "LogicMinMax"; device: number; inputs?: [number | null, number | null] }
+  | { type: "LogicReader"; device: number; input?: number | null; field?: LogicType | null }
+  | {
+      type: "LogicWriter";
+      device: number;
+      input?: number | null;
+      output?: number | null;
+      field?: LogicType | null;
+    }
+  | {
+      type: "LogicBatchReader";
+      device: number;
+      prefab_hash?: number;
+      field?: LogicType | null;
+      mode?: BatchMode;
+    }
+  | {
+      type: "LogicBatchWriter";
+      device: number;
+      input?: number | null;
+      prefab_hash?: number;
+      field?: LogicType | null;
+    };
+
+export interface HistoryStep {
+  device: number;
+  ic: number;
+  clock: number;
+  line: number;
+}
+
+export type TraceOperand =
+  | { Value: number }
+  | { Register: string }
+  | { Enum: { name: string; value: number } }
+  | { Device: number }
+  | "Unresolved";
+
+export interface TraceEntry {
+  clock: number;
+  line: number;
+  instruction: string;
+  operands: TraceOperand[];
+  writes: Access[];
+  error?: string | null;
+}
+
+export interface Trace {
+  device: number;
+  entries: TraceEntry[];
+}
+
+export interface ProfileReport {
+  device: number;
+  line_hits: Map<number, number>;
+  heatmap: Map<number, number>;
+  total_lines: number;
+  ticks: number;
+  max_lines: number;
+  average_lines: number;
+  exhausted_ticks: number[];
+}
+
+export interface TickResult {
+  modified: number[];
+  breakpoints: BreakpointHit[];
+  watchpoints: WatchpointHit[];
+}
+
+export type UnknownPrefabAccess = "Permissive" | "Deny";
+
+export interface ChannelWrite {
+  clock: number;
+  network: number;
+  channel: number;
+  ic?: number;
+  old: number;
+  value: number;
 }
 
 export interface VMRef {
+  tick(): TickResult;
+  runTicks(ticks: number): TickResult;
+  addBreakpoint(id: number, breakpoint: Breakpoint): number;
+  getBreakpoints(id: number): Map<number, Breakpoint>;
+  addWatchpoint(watchpoint: Watchpoint): number;
+  getWatchpoints(): Map<number, Watchpoint>;
+  addStimulus(stimulus: Stimulus): number;
+  getStimuli(): Map<number, Stimulus>;
+  setBehavior(behavior: BuiltinBehavior): void;
+  getBehaviors(): Map<number, BuiltinBehavior>;
+  setUnknownPrefabAccess(access: UnknownPrefabAccess): void;
+  getNetworkPower(id: number): PowerState | undefined;
+  checkInvariants(): NetworkMismatch[];
+  stopChannelMonitor(): ChannelWrite[] | undefined;
+  getChannelWrites(): ChannelWrite[] | undefined;
+  getHistory(): HistoryStep[];
+  stepBack(): HistoryStep | undefined;
+  stopTrace(id: number): Trace | undefined;
+  stopProfile(id: number): ProfileReport | undefined;
+  getProfile(id: number): ProfileReport | undefined;
   addDeviceFromTemplate(template: DeviceTemplate): number;
   setSlotOccupant(id: number, index: number, template: SlotOccupantTemplate);
   saveVMState(): FrozenVM;
-  restoreVMState(state: FrozenVM): void;
+  restoreVMState(state: FrozenVM): NetworkMismatch[];
 }
@@ -28,7 +28,7 @@ export default defineConfig({
             // "src/index.html",
             "img/*.png",
             "img/*/*.png",
-            // { from: "data/database.json", to: "data" },
+            // { from: "../ic10emu/data/database.json", to: "data" },
             // Copy Shoelace assets to dist/shoelace
             {
               from: path.resolve(
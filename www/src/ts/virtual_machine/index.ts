@@ -72,7 +72,7 @@ class VirtualMachine extends EventTarget {
     this._devices = new Map();
     this._ics = new Map();
 
-    this.dbPromise = import("../../../data/database.json", {
+    this.dbPromise = import("../../../../ic10emu/data/database.json", {
       assert: { type: "json" },
     }) as Promise<{ default: DeviceDB }>;
 
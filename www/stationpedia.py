@@ -522,7 +522,8 @@ def extract_all() -> None:
                     enums[key] = {}
                 enums[key][enum] = val
 
-    with open("data/database.json", "w") as f:
+    # the emulator core generates its prefab database from this too
+    with open("../ic10emu/data/database.json", "w") as f:
         json.dump(
             clean_nones(
                 {